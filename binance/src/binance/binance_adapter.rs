@@ -1,6 +0,0 @@
-pub struct BinanceAdapter {
-
-}
-
-impl BinanceAdapter {
-}
\ No newline at end of file
@@ -1,7 +0,0 @@
-pub struct BinanceReceiveClient {
-
-}
-
-impl BinanceReceiveClient {
-    
-}
\ No newline at end of file
@@ -1,3 +0,0 @@
-pub mod binance_adapter;
-pub mod binance_client;
-pub mod data_types;
\ No newline at end of file
@@ -0,0 +1,116 @@
+"""Writes eth_usdt_session.jsonl, an OKX v5 public session for ETH-USDT.
+
+The session is generated, not captured: this sandbox cannot reach OKX. Checksums are computed
+here, independently of the Rust feed, by the algorithm in OKX's v5 API docs: the best 25 bids
+and asks interleaved as "bidPx:bidSz:askPx:askSz:...", continuing with whichever side is longer,
+joined with ':' and hashed with CRC32 read as a signed 32-bit integer. Prices and sizes are the
+strings as sent, so the checksum covers them exactly as OKX formats them.
+
+Replace the output with a recorded session when one is available; the tests only rely on the
+top of book and the sequence of frames below.
+"""
+import json
+import os
+import random
+import zlib
+
+DEPTH = 400
+CHECKSUM_DEPTH = 25
+TICK = 0.01
+ARG = {"channel": "books", "instId": "ETH-USDT"}
+
+
+def fmt(value, places):
+    # OKX drops trailing zeros: "1897.1", "2", "0.0412".
+    return f"{value:.{places}f}".rstrip("0").rstrip(".")
+
+
+def checksum(bids, asks):
+    best_bids = sorted(bids.items(), key=lambda level: -float(level[0]))[:CHECKSUM_DEPTH]
+    best_asks = sorted(asks.items(), key=lambda level: float(level[0]))[:CHECKSUM_DEPTH]
+    parts = []
+    for i in range(CHECKSUM_DEPTH):
+        if i < len(best_bids):
+            parts.append(f"{best_bids[i][0]}:{best_bids[i][1][0]}")
+        if i < len(best_asks):
+            parts.append(f"{best_asks[i][0]}:{best_asks[i][1][0]}")
+    value = zlib.crc32(":".join(parts).encode())
+    return value - (1 << 32) if value >= 1 << 31 else value
+
+
+def levels(book):
+    return [[price, size, "0", orders] for price, (size, orders) in book.items()]
+
+
+def book_frame(action, bids, asks, changed_bids, changed_asks, ts, prev_seq_id, seq_id):
+    data = {
+        "asks": levels(changed_asks),
+        "bids": levels(changed_bids),
+        "ts": str(ts),
+        "checksum": checksum(bids, asks),
+        "prevSeqId": prev_seq_id,
+        "seqId": seq_id,
+    }
+    return {"arg": ARG, "action": action, "data": [data]}
+
+
+def apply(book, changes):
+    for price, (size, orders) in changes.items():
+        if float(size) == 0.0:
+            book.pop(price, None)
+        else:
+            book[price] = (size, orders)
+
+
+def main():
+    rng = random.Random(1689025543)
+    bids, asks = {}, {}
+    # Fixed tops the tests assert on, then a thinner book out to the full depth.
+    bids["1897.1"] = ("2.5", "3")
+    bids["1897"] = ("1.2", "1")
+    asks["1897.2"] = ("1.1", "2")
+    asks["1897.3"] = ("0.8731", "1")
+    price = 1896.99
+    while len(bids) < DEPTH:
+        price -= TICK * rng.choice([1, 1, 2, 3, 5])
+        bids[fmt(price, 2)] = (fmt(rng.uniform(0.001, 15.0), 4), str(rng.randint(1, 12)))
+    price = 1897.31
+    while len(asks) < DEPTH:
+        price += TICK * rng.choice([1, 1, 2, 3, 5])
+        asks[fmt(price, 2)] = (fmt(rng.uniform(0.001, 15.0), 4), str(rng.randint(1, 12)))
+
+    frames = [
+        {"event": "subscribe", "arg": {"channel": "books", "instId": "ETH-USDT"}, "connId": "a4d3ae55"},
+        {"event": "subscribe", "arg": {"channel": "trades", "instId": "ETH-USDT"}, "connId": "a4d3ae55"},
+        book_frame("snapshot", bids, asks, bids, asks, 1689025543609, -1, 1000),
+    ]
+
+    # The best ask is taken and a bid joins inside the spread.
+    changed_bids, changed_asks = {"1897.05": ("0.4", "1")}, {"1897.2": ("0", "0")}
+    apply(bids, changed_bids)
+    apply(asks, changed_asks)
+    frames.append(book_frame("update", bids, asks, changed_bids, changed_asks, 1689025543650, 1000, 1005))
+
+    frames.append({"arg": {"channel": "trades", "instId": "ETH-USDT"}, "data": [
+        {"instId": "ETH-USDT", "tradeId": "130639474", "px": "1897.1", "sz": "0.5", "side": "sell", "ts": "1689025543700"},
+    ]})
+
+    # Unchanged books are pushed periodically with seqId == prevSeqId.
+    frames.append(book_frame("update", bids, asks, {}, {}, 1689025543800, 1005, 1005))
+
+    # A deep ask is pulled, below the checksum's 25 levels.
+    deep_ask = sorted(asks, key=float)[300]
+    changed_bids, changed_asks = {"1897.1": ("1", "2")}, {"1897.3": ("2", "1"), deep_ask: ("0", "0")}
+    apply(bids, changed_bids)
+    apply(asks, changed_asks)
+    frames.append(book_frame("update", bids, asks, changed_bids, changed_asks, 1689025543900, 1005, 1010))
+
+    path = os.path.join(os.path.dirname(os.path.abspath(__file__)), "eth_usdt_session.jsonl")
+    with open(path, "w") as f:
+        for frame in frames:
+            f.write(json.dumps(frame, separators=(",", ":")) + "\n")
+        f.write("pong\n")
+
+
+if __name__ == "__main__":
+    main()
@@ -1,41 +1,192 @@
-mod order_book;
+use prism::order_book;
 
+use std::net::{Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
-use chrono::Utc;
-use futures_util::{future, TryStreamExt, StreamExt, SinkExt};
 
-use order_book::clients::binance::binance_client::BinanceReceiveClient;
-use order_book::clients::bitstamp::bitstamp_client::BitstampReceiveClient;
-use tokio::net::{TcpListener, TcpStream, TcpSocket};
+use order_book::clients::binance::binance_client::BinanceFeed;
+use order_book::clients::relay::{RelayListener, RelaySecurity};
+use order_book::clients::bitstamp::bitstamp_client::BitstampFeed;
 use tokio::runtime::Builder;
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
-use tokio_tungstenite::accept_async;
 
-use crate::order_book::clients::coinbase::coinbase_client::CoinbaseReceiveClient;
-use crate::order_book::clients::gemini::gemini_client::GeminiReceiveClient;
-use crate::order_book::clients::kraken::kraken_client::KrakenReceiveClient;
-use crate::order_book::multi_book::MultiBook;
+use crate::order_book::clients::bybit::bybit_client::BybitFeed;
+use crate::order_book::clients::client::{ConnectionConfig, FIXClient, UDPClient};
+use crate::order_book::clients::coinbase::coinbase_client::CoinbaseFeed;
+use crate::order_book::clients::feed::{FeedDriver, MultiBookAdapter};
+use crate::order_book::clients::fix::market_data::MarketData;
+use crate::order_book::clients::fix::session::SessionConfig;
+use crate::order_book::clients::gemini::gemini_client::GeminiFeed;
+use crate::order_book::clients::kraken::kraken_client::KrakenFeed;
+use crate::order_book::clients::kraken::kraken_v2_client::KrakenV2Feed;
+use crate::order_book::clients::okx::okx_client::OkxFeed;
+use crate::order_book::clients::udp::receiver::UdpConfig;
+use crate::order_book::instruments::InstrumentRegistry;
+use crate::order_book::multi_book::{MultiBook, ThresholdScaling};
+use crate::order_book::paper::PaperExchange;
+use crate::order_book::portfolio::Portfolio;
+use crate::order_book::replay::{self, Recorder};
+use crate::order_book::volatility::VolatilityConfig;
 
-const NUM_EXCHANGES: usize = 3;
+const NUM_EXCHANGES: usize = 7;
 const NUM_EXCHANGE_PAIRS: usize = 2 * (NUM_EXCHANGES * (NUM_EXCHANGES - 1)) / 2;
-const NUM_CURRENCY_PAIRS: usize = 2;
-const CURRENCY_PAIRS: [&'static str; NUM_CURRENCY_PAIRS] = ["ETH-USD", "BTC-USD"];
-const NUM_MULTI_BOOKS: usize = NUM_EXCHANGES * NUM_CURRENCY_PAIRS;
+const EXCHANGES: [&str; NUM_EXCHANGES] = ["coinbase", "kraken", "gemini", "bitstamp", "binance", "okx", "bybit"];
+const DEFAULT_INSTRUMENTS: &str = "config/instruments.json";
+const DEFAULT_UDP_BIND: &str = "0.0.0.0:6970";
+const DEFAULT_RELAY_BIND: &str = "0.0.0.0:6969";
+const DEFAULT_PAPER_CONFIG: &str = "config/paper.json";
+const DEFAULT_CONNECTIONS: &str = "config/connections.json";
+const EVALUATOR_HORIZONS: [Duration; 3] = [
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+];
+const VOLATILITY_CONFIG: VolatilityConfig = VolatilityConfig {
+    sample_interval: Duration::from_secs(1),
+    window: 300,
+    tight_bps: 2.0,
+    wide_bps: 10.0,
+};
+const THRESHOLD_SCALING: Option<ThresholdScaling> = Some(ThresholdScaling {
+    reference_volatility: 1.0,
+    max_scale: 3.0,
+});
+fn new_multi_book(pair: &str) -> MultiBook<NUM_EXCHANGES, NUM_EXCHANGE_PAIRS> {
+    let mut multi_book = MultiBook::new(
+        heapless::String::from(pair),
+        EXCHANGES.map(heapless::String::from),
+    );
+    multi_book.set_horizons(&EVALUATOR_HORIZONS);
+    multi_book.set_volatility_config(VOLATILITY_CONFIG);
+    multi_book.set_threshold_scaling(THRESHOLD_SCALING);
+    multi_book
+}
 
-async fn init_pair(pair: heapless::String<8>, runtime: &tokio::runtime::Runtime) {
-    let runtime = Builder::new_multi_thread()
-        .worker_threads(3)
-        .thread_name("prism")
-        .thread_stack_size(64 * 1024 * 1024)
-        .enable_all()
-        .build()
-        .unwrap();
+fn run_replay(
+    path: &str,
+    registry: &InstrumentRegistry,
+    paper: Option<Arc<std::sync::Mutex<PaperExchange>>>,
+    portfolio: Option<Arc<std::sync::Mutex<Portfolio>>>,
+) {
+    let mut multi_books: Vec<MultiBook<NUM_EXCHANGES, NUM_EXCHANGE_PAIRS>> = registry.pairs().iter()
+        .map(|pair| new_multi_book(pair))
+        .collect();
+    for multi_book in multi_books.iter_mut() {
+        if let Some(paper) = &paper {
+            multi_book.set_paper(paper.clone());
+        }
+        if let Some(portfolio) = &portfolio {
+            multi_book.set_portfolio(portfolio.clone());
+        }
+    }
+    match replay::replay(path, &mut multi_books) {
+        Ok(count) => println!("Replayed {:?} events from {:?}", count, path),
+        Err(err) => println!("Error replaying {:?}: {:?}", path, err),
+    }
+    for multi_book in multi_books.iter() {
+        multi_book.print();
+    }
 }
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let instruments = match args.iter().position(|a| a == "--instruments") {
+        Some(i) => args.get(i + 1).expect("--instruments requires a path").as_str(),
+        None => DEFAULT_INSTRUMENTS,
+    };
+    let registry = match InstrumentRegistry::load(instruments) {
+        Ok(registry) => Arc::new(registry),
+        Err(err) => panic!("Failed to load instruments from {:?}: {}", instruments, err),
+    };
+    // Kraken's v1 feed stays the default until v2 has run in production.
+    let kraken_v2 = match args.iter().position(|a| a == "--kraken") {
+        Some(i) => match args.get(i + 1).map(|v| v.as_str()) {
+            Some("v1") => false,
+            Some("v2") => true,
+            other => panic!("--kraken expects v1 or v2, got {:?}", other),
+        },
+        None => false,
+    };
+    // Coinbase's FIX gateway needs API credentials, taken from the environment.
+    let coinbase_fix = match args.iter().position(|a| a == "--coinbase") {
+        Some(i) => match args.get(i + 1).map(|v| v.as_str()) {
+            Some("ws") => false,
+            Some("fix") => true,
+            other => panic!("--coinbase expects ws or fix, got {:?}", other),
+        },
+        None => false,
+    };
+    // Binance blocks some regions, so by default its data arrives from relays, either over the
+    // relay listener or as UDP packets.
+    let binance_source = match args.iter().position(|a| a == "--binance") {
+        Some(i) => match args.get(i + 1).map(|v| v.as_str()) {
+            Some(source @ ("relay" | "direct" | "udp")) => source.to_string(),
+            other => panic!("--binance expects relay, direct or udp, got {:?}", other),
+        },
+        None => "relay".to_string(),
+    };
+    let flag = |name: &str| args.iter().position(|a| a == name).and_then(|i| args.get(i + 1)).cloned();
+    // Reconnect backoff and heartbeats per venue, falling back to the file's default entry.
+    let connections_path = flag("--connections").unwrap_or(DEFAULT_CONNECTIONS.to_string());
+    let connections = match ConnectionConfig::load(&connections_path) {
+        Ok(connections) => connections,
+        Err(err) => panic!("Failed to load connection config from {:?}: {}", connections_path, err),
+    };
+    let udp_config = UdpConfig {
+        venue: "binance",
+        bind: flag("--udp-bind").unwrap_or(DEFAULT_UDP_BIND.to_string()).parse().expect("--udp-bind expects host:port"),
+        multicast: flag("--udp-group").map(|group| (group.parse().expect("--udp-group expects an IPv4 address"), Ipv4Addr::UNSPECIFIED)),
+        retransmit: flag("--udp-retransmit").map(|addr| addr.parse().expect("--udp-retransmit expects host:port")),
+    };
+    // Relays must present RELAY_TOKEN when it is set; TLS needs both the certificate and key.
+    let relay_security = RelaySecurity {
+        tls: match (flag("--relay-cert"), flag("--relay-key")) {
+            (Some(cert), Some(key)) => Some(RelaySecurity::tls(&cert, &key).unwrap_or_else(|err| panic!("Failed to load relay TLS identity: {}", err))),
+            (None, None) => None,
+            _ => panic!("--relay-cert and --relay-key must be given together"),
+        },
+        token: std::env::var("RELAY_TOKEN").ok(),
+    };
+    let relay_bind: SocketAddr = flag("--relay-bind").unwrap_or(DEFAULT_RELAY_BIND.to_string()).parse().expect("--relay-bind expects host:port");
+    // Without a token anyone who can reach the listener can write to the books, so that is only
+    // allowed on loopback unless asked for.
+    let relay_insecure = args.iter().any(|a| a == "--relay-insecure");
+    // Relays can carry any venue the core doesn't connect to itself; Binance over relays needs
+    // the listener either way.
+    let relay_listen = args.iter().any(|a| a == "--relay-listen") || binance_source == "relay";
+    // Detected opportunities are taken on a simulated venue that fills against the books.
+    // Latency and fees for the simulated venues come from --paper-config.
+    let paper = match args.iter().any(|a| a == "--paper") {
+        true => {
+            let path = flag("--paper-config").unwrap_or(DEFAULT_PAPER_CONFIG.to_string());
+            match PaperExchange::load(&path) {
+                Ok(exchange) => Some(Arc::new(std::sync::Mutex::new(exchange))),
+                Err(err) => panic!("Failed to load paper config from {:?}: {}", path, err),
+            }
+        },
+        false => None,
+    };
+    // Balances per venue that opportunity sizes are held to; paper fills move them.
+    let portfolio = flag("--portfolio").map(|path| match Portfolio::load(&path) {
+        Ok(portfolio) => Arc::new(std::sync::Mutex::new(portfolio)),
+        Err(err) => panic!("Failed to load portfolio from {:?}: {}", path, err),
+    });
+    if let (Some(paper), Some(portfolio)) = (&paper, &portfolio) {
+        paper.lock().unwrap().set_portfolio(portfolio.clone());
+    }
+    if args.len() > 2 && args[1] == "replay" {
+        run_replay(&args[2], &registry, paper, portfolio);
+        return;
+    }
+    let recorder = match args.iter().position(|a| a == "--record") {
+        Some(i) => {
+            let path = args.get(i + 1).expect("--record requires a path");
+            Some(Arc::new(std::sync::Mutex::new(Recorder::create(path).expect("Failed to create recording"))))
+        },
+        None => None,
+    };
     let runtime = Builder::new_multi_thread()
         .worker_threads(12)
         .thread_name("prism")
@@ -43,75 +194,156 @@ async fn main() {
         .enable_all()
         .build()
         .unwrap();
-    let mut pair_task_vec = heapless::Vec::<JoinHandle<()>, NUM_MULTI_BOOKS>::new();
-    let mut multi_book_vec = heapless::Vec::<Arc<Mutex<MultiBook<NUM_EXCHANGES, NUM_EXCHANGE_PAIRS>>>, NUM_CURRENCY_PAIRS>::new();
-    
-    for pair in CURRENCY_PAIRS.iter() {
-        let multi_book: MultiBook<NUM_EXCHANGES, NUM_EXCHANGE_PAIRS> = MultiBook::new(
-            heapless::String::from(pair.to_owned()),
-            [
-                heapless::String::from("coinbase"), 
-                //heapless::String::from("gemini"),
-                heapless::String::from("kraken"),
-                heapless::String::from("binance"),
-            ]
-        );
-        let multi_lock = Arc::new(Mutex::new(multi_book));
-        let _ = multi_book_vec.push(multi_lock.clone());
-        let cb_multi_lock = multi_lock.clone();
-        //let gemini_multi_lock = multi_lock.clone();
-        let kraken_multi_lock = multi_lock.clone();
-        let binance_multi_lock = multi_lock.clone();
+    let mut pair_task_vec = Vec::<JoinHandle<()>>::new();
+    let mut multi_book_vec = Vec::<Arc<Mutex<MultiBook<NUM_EXCHANGES, NUM_EXCHANGE_PAIRS>>>>::new();
     
-        let coinbase_task = runtime.spawn(async move {
-            loop {
-                let lock = cb_multi_lock.clone();
-                let pair = heapless::String::<8>::from(*pair);
-                let mut coinbase_client = CoinbaseReceiveClient::new(lock, pair).await;
-                coinbase_client.init().await;
-            }
+    for pair in registry.pairs() {
+        let mut multi_book = new_multi_book(&pair);
+        if let Some(recorder) = &recorder {
+            multi_book.set_recorder(recorder.clone());
+        }
+        if let Some(paper) = &paper {
+            multi_book.set_paper(paper.clone());
+        }
+        if let Some(portfolio) = &portfolio {
+            multi_book.set_portfolio(portfolio.clone());
+        }
+        multi_book_vec.push(Arc::new(Mutex::new(multi_book)));
+    }
+    // Each venue gets a single connection carrying every configured pair.
+    let coinbase_locks = multi_book_vec.to_vec();
+    let coinbase_registry = registry.clone();
+    let (coinbase_reconnect, coinbase_heartbeat) = (connections.reconnect("coinbase"), connections.heartbeat("coinbase"));
+    let coinbase_task = runtime.spawn(async move {
+        let adapter = MultiBookAdapter::new(coinbase_locks).await;
+        if coinbase_fix {
+            let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("--coinbase fix requires {}", name));
+            let mut config = SessionConfig::coinbase(env("COINBASE_API_KEY"), env("COINBASE_PASSPHRASE"), env("COINBASE_API_SECRET"));
+            config.sequence_file = std::env::var("COINBASE_FIX_SEQUENCE_FILE").ok().map(Into::into);
+            let market_data = MarketData::new("coinbase", coinbase_registry);
+            let mut coinbase_client = FIXClient::new(config, market_data, adapter, coinbase_reconnect);
+            coinbase_client.run().await;
+        } else {
+            let mut coinbase_driver = FeedDriver::new(CoinbaseFeed::new(coinbase_registry), adapter, coinbase_reconnect, coinbase_heartbeat);
+            coinbase_driver.run().await;
+        }
+    });
+    pair_task_vec.push(coinbase_task);
+
+    let kraken_locks = multi_book_vec.to_vec();
+    let kraken_registry = registry.clone();
+    let (kraken_reconnect, kraken_heartbeat) = (connections.reconnect("kraken"), connections.heartbeat("kraken"));
+    let kraken_task = runtime.spawn(async move {
+        let adapter = MultiBookAdapter::new(kraken_locks).await;
+        if kraken_v2 {
+            let mut kraken_driver = FeedDriver::new(KrakenV2Feed::new(kraken_registry), adapter, kraken_reconnect, kraken_heartbeat);
+            kraken_driver.run().await;
+        } else {
+            let mut kraken_driver = FeedDriver::new(KrakenFeed::new(kraken_registry), adapter, kraken_reconnect, kraken_heartbeat);
+            kraken_driver.run().await;
+        }
+    });
+    pair_task_vec.push(kraken_task);
+
+    let bitstamp_locks = multi_book_vec.to_vec();
+    let bitstamp_registry = registry.clone();
+    let (bitstamp_reconnect, bitstamp_heartbeat) = (connections.reconnect("bitstamp"), connections.heartbeat("bitstamp"));
+    let bitstamp_task = runtime.spawn(async move {
+        let adapter = MultiBookAdapter::new(bitstamp_locks).await;
+        let mut bitstamp_driver = FeedDriver::new(BitstampFeed::new(bitstamp_registry), adapter, bitstamp_reconnect, bitstamp_heartbeat);
+        bitstamp_driver.run().await;
+    });
+    pair_task_vec.push(bitstamp_task);
+
+    let okx_locks = multi_book_vec.to_vec();
+    let okx_registry = registry.clone();
+    let (okx_reconnect, okx_heartbeat) = (connections.reconnect("okx"), connections.heartbeat("okx"));
+    let okx_task = runtime.spawn(async move {
+        let adapter = MultiBookAdapter::new(okx_locks).await;
+        let mut okx_driver = FeedDriver::new(OkxFeed::new(okx_registry), adapter, okx_reconnect, okx_heartbeat);
+        okx_driver.run().await;
+    });
+    pair_task_vec.push(okx_task);
+
+    let bybit_locks = multi_book_vec.to_vec();
+    let bybit_registry = registry.clone();
+    let (bybit_reconnect, bybit_heartbeat) = (connections.reconnect("bybit"), connections.heartbeat("bybit"));
+    let bybit_task = runtime.spawn(async move {
+        let adapter = MultiBookAdapter::new(bybit_locks).await;
+        let mut bybit_driver = FeedDriver::new(BybitFeed::new(bybit_registry), adapter, bybit_reconnect, bybit_heartbeat);
+        bybit_driver.run().await;
+    });
+    pair_task_vec.push(bybit_task);
+
+    let gemini_locks = multi_book_vec.to_vec();
+    let gemini_registry = registry.clone();
+    let (gemini_reconnect, gemini_heartbeat) = (connections.reconnect("gemini"), connections.heartbeat("gemini"));
+    let gemini_task = runtime.spawn(async move {
+        let adapter = MultiBookAdapter::new(gemini_locks).await;
+        let mut gemini_driver = FeedDriver::new(GeminiFeed::new(gemini_registry), adapter, gemini_reconnect, gemini_heartbeat);
+        gemini_driver.run().await;
+    });
+    pair_task_vec.push(gemini_task);
+    if binance_source == "direct" {
+        let binance_locks = multi_book_vec.to_vec();
+        let binance_registry = registry.clone();
+    let (binance_reconnect, binance_heartbeat) = (connections.reconnect("binance"), connections.heartbeat("binance"));
+        let binance_task = runtime.spawn(async move {
+            let adapter = MultiBookAdapter::new(binance_locks).await;
+            let mut binance_driver = FeedDriver::new(BinanceFeed::new(binance_registry), adapter, binance_reconnect, binance_heartbeat);
+            binance_driver.run().await;
         });
-        
-        /*let gemini_task = runtime.spawn(async move {
-            loop {
-                let lock = gemini_multi_lock.clone();
-                let pair = heapless::String::<8>::from(*pair);
-                let mut gemini_client = GeminiReceiveClient::new(lock, pair).await;
-                gemini_client.init().await;
-            }
-        });*/
-    
-        let kraken_task = runtime.spawn(async move {
-            loop {
-                let lock = kraken_multi_lock.clone();
-                let pair = heapless::String::<8>::from(*pair);
-                let mut kraken_client = KrakenReceiveClient::new(lock, pair).await;
-                kraken_client.init().await;
+        pair_task_vec.push(binance_task);
+    }
+    let mut relay_states = None;
+    if relay_listen {
+        if relay_security.token.is_none() && !relay_bind.ip().is_loopback() {
+            if !relay_insecure {
+                panic!("Refusing to listen for relays on {:?} without RELAY_TOKEN; set it, bind to loopback, or pass --relay-insecure", relay_bind);
             }
+            println!("WARNING: listening for relays on {:?} without RELAY_TOKEN; anyone who can reach it can write to the books", relay_bind);
+        }
+        let relay_locks = multi_book_vec.to_vec();
+        let relay_registry = registry.clone();
+        let adapter = MultiBookAdapter::new(relay_locks).await;
+        let mut relay_listener = RelayListener::new(adapter, relay_registry, relay_security, relay_bind);
+        let mut direct: Vec<String> = ["coinbase", "kraken", "bitstamp", "okx", "bybit", "gemini"].iter().map(|v| v.to_string()).collect();
+        if binance_source != "relay" {
+            direct.push("binance".to_string());
+        }
+        relay_listener.set_direct(direct);
+        relay_states = Some(relay_listener.states());
+        let relay_task = runtime.spawn(async move {
+            relay_listener.run().await;
         });
-
-        let _ = pair_task_vec.push(coinbase_task);
-        //let _ = pair_task_vec.push(gemini_task);
-        let _ = pair_task_vec.push(kraken_task);
-        //let _ = pair_task_vec.push(binance_task);
+        pair_task_vec.push(relay_task);
+    }
+    if binance_source == "udp" {
+        let binance_locks = multi_book_vec.to_vec();
+        let binance_registry = registry.clone();
+        let binance_task = runtime.spawn(async move {
+            let adapter = MultiBookAdapter::new(binance_locks).await;
+            let mut binance_client = UDPClient::new(udp_config, binance_registry, adapter);
+            binance_client.run().await;
+        });
+        pair_task_vec.push(binance_task);
     }
-    let mut lock_vec = heapless::Vec::<Arc<Mutex<MultiBook<NUM_EXCHANGES, NUM_EXCHANGE_PAIRS>>>, NUM_CURRENCY_PAIRS>::new();
-    lock_vec.clone_from(&multi_book_vec);
-    let binance_task = runtime.spawn(async move {
-        //let lock = binance_multi_lock.clone();
-        let mut binance_client = BinanceReceiveClient::new(lock_vec).await;
-        binance_client.init().await;
-    });
     let monitor_task = runtime.spawn(async move {
         loop {
             tokio::time::sleep(Duration::from_secs(60 * 10)).await;
             for lock in multi_book_vec.iter() {
                 lock.lock().await.print()
             }
+            if let Some(relay_states) = &relay_states {
+                for state in relay_states.snapshot() {
+                    println!("{:?}", state);
+                }
+            }
+            if let Some(recorder) = &recorder {
+                recorder.lock().unwrap().flush();
+            }
         }
     });
-    binance_task.await.unwrap();
-
     for pair_task in pair_task_vec {
         pair_task.await.unwrap();
     }
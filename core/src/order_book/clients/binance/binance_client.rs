@@ -1,90 +1,348 @@
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
 
-use chrono::Utc;
-use futures_util::StreamExt;
-use tokio::{net::{TcpStream, TcpSocket}, sync::Mutex};
-use tokio_tungstenite::{WebSocketStream, accept_async};
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
 
-use crate::order_book::{multi_book::MultiBook, clients::binance::data_types::InboundMessage};
+use crate::order_book::clients::feed::{DecodeError, ExchangeFeed, FeedEvent};
+use crate::order_book::data_types::{Change, Match, PriceLevel, Side, Snapshot, to_level};
+use crate::order_book::instruments::InstrumentRegistry;
 
-use super::{binance_adapter::BinanceAdapter, data_types::{Update, Change, Side, PriceLevel}};
+use super::data_types::{AggTrade, DepthSnapshot, DepthUpdate, Header, PriceLevel as BinanceLevel, ServerTime};
 
-pub struct BinanceReceiveClient {
-    adapter: BinanceAdapter,
-    stream: WebSocketStream<TcpStream>,
+// Depth events held while the REST snapshot is in flight.
+const MAX_BUFFERED: usize = 4096;
+
+// Direct depth feed, for deployments that can reach Binance without the relay. Follows the
+// documented sync: buffer `depthUpdate`s, fetch a REST snapshot, drop events with `u` at or
+// below its `lastUpdateId`, require the first applied event to straddle `lastUpdateId + 1`,
+// then require each event's `U` to follow the previous `u`.
+pub struct BinanceFeed {
+    symbols: Vec<Symbol>,
+    ws_url: String,
+    rest_url: String,
+}
+
+// Sync state for one Binance symbol on the shared connection.
+struct Symbol {
+    symbol: String,
+    pair: heapless::String<8>,
+    depth_channel: String,
+    trade_channel: String,
+    subscribed: bool,
+    requested: bool,
+    last_update_id: Option<u64>,
+    bridged: bool,
+    buffer: Vec<DepthUpdate>,
 }
 
-impl BinanceReceiveClient {
-    pub async fn new(books: heapless::Vec::<Arc<Mutex<MultiBook<3, 6>>>, 2>) -> BinanceReceiveClient {
-        let addr = "0.0.0.0:6969".parse().unwrap();
-        let socket = TcpSocket::new_v4().expect("Error creating socket");
-        socket.set_nodelay(true).unwrap();
-        socket.bind(addr).unwrap();
-        let (connection, _) = socket.listen(1024).expect("No connections to accept").accept().await.expect("Error accepting");
-        let stream = accept_async(connection).await.expect("Failed to accept connection");
-        return BinanceReceiveClient {
-            adapter: BinanceAdapter::new(books),
-            stream: stream,
+impl BinanceFeed {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> BinanceFeed {
+        let symbols = registry.venue_listings("binance").into_iter().map(|(pair, listing)| {
+            let stream = listing.symbol.to_lowercase();
+            Symbol {
+                symbol: listing.symbol.clone(),
+                pair,
+                depth_channel: match listing.channel("depth") {
+                    Some(channel) => channel.to_string(),
+                    None => format!("{}@depth@100ms", stream),
+                },
+                trade_channel: match listing.channel("trades") {
+                    Some(channel) => channel.to_string(),
+                    None => format!("{}@aggTrade", stream),
+                },
+                subscribed: false,
+                requested: false,
+                last_update_id: None,
+                bridged: false,
+                buffer: Vec::new(),
+            }
+        }).collect();
+        BinanceFeed {
+            symbols,
+            ws_url: "wss://stream.binance.com:9443/ws".to_string(),
+            rest_url: "https://api.binance.com/api/v3".to_string(),
         }
     }
 
-    pub async fn init(&mut self) {
-        self.receive().await;
-    }
-    
-    async fn receive(&mut self) {
-        let mut total = 0;
-        let mut count = 0;
-        while let Some(msg) = self.stream.next().await {
-            let (message, _) = serde_json_core::from_str::<InboundMessage>(msg.unwrap().to_text().unwrap()).unwrap();
-            match message.sent {
-                Some(t) => {
-                    self.handle_trade(message).await;
-                    let now = Utc::now();
-                    let dur = now.timestamp_millis() - t;
-                    count = count + 1;
-                    total = total + dur;
-                    let avg: f64 = (total as f64) / (count as f64);
-                    if count % 1000 == 1 {
-                        println!("Binance avg. sent to handled time: {:?}", Duration::new(0, (avg * 1000000.0) as u32));
-                    }
-                },
-                None => self.handle_book_update(message).await
+    pub fn set_endpoints(&mut self, ws_url: String, rest_url: String) {
+        self.ws_url = ws_url;
+        self.rest_url = rest_url;
+    }
+
+    fn snapshot_url(&self, symbol: &str) -> String {
+        format!("{}/depth?symbol={}&limit=1000", self.rest_url, symbol)
+    }
+
+    fn symbol(&mut self, symbol: Option<&str>) -> Option<&mut Symbol> {
+        let symbol = symbol?;
+        self.symbols.iter_mut().find(|s| s.symbol == symbol)
+    }
+
+    fn changes(side: Side, levels: &[BinanceLevel]) -> impl Iterator<Item = Change> + '_ {
+        levels.iter().map(move |l| Change { side, price_level: PriceLevel { level: l.level, amount: l.amount, sequence: 0 } })
+    }
+}
+
+impl Symbol {
+    fn apply(&mut self, update: DepthUpdate, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let last = self.last_update_id.unwrap();
+        if update.final_update_id <= last {
+            return Ok(());
+        }
+        if self.bridged && update.first_update_id != last + 1 {
+            return Err(DecodeError::OutOfSync(format!("expected update {:?}, got {:?}", last + 1, update.first_update_id)));
+        }
+        if !self.bridged && update.first_update_id > last + 1 {
+            return Err(DecodeError::OutOfSync(format!("first update {:?} is past snapshot {:?}", update.first_update_id, last)));
+        }
+        self.bridged = true;
+        self.last_update_id = Some(update.final_update_id);
+        // A 100ms batch can carry more levels than one delta holds.
+        let mut changes = heapless::Vec::<Change, 512>::new();
+        let all = BinanceFeed::changes(Side::Buy, &update.bids).chain(BinanceFeed::changes(Side::Sell, &update.asks));
+        for change in all {
+            if changes.is_full() {
+                events.push(FeedEvent::Delta { pair: self.pair.clone(), changes: std::mem::take(&mut changes) });
             }
+            let _ = changes.push(change);
         }
+        events.push(FeedEvent::Delta { pair: self.pair.clone(), changes });
+        Ok(())
     }
 
-    async fn handle_book_update(&mut self, message: InboundMessage) {
-        let update = Update {
-            best_bid: Change {
-                side: Side::Buy,
-                level: PriceLevel {
-                    level: (message.bid_level.unwrap().parse::<f64>().unwrap() * 100.) as usize,
-                    amount: message.bid_amount.unwrap().parse::<f64>().unwrap(),
-                }
+    fn trade(&self, trade: AggTrade, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let price = trade.price.parse::<f64>().map_err(|e| DecodeError::Unexpected(e.to_string()))?;
+        let size = trade.amount.parse::<f64>().map_err(|e| DecodeError::Unexpected(e.to_string()))?;
+        events.push(FeedEvent::Trade {
+            pair: self.pair.clone(),
+            trade: Match {
+                side: match trade.buyer_is_maker {
+                    true => Side::Sell,
+                    false => Side::Buy,
+                },
+                price: to_level(price),
+                size,
             },
-            best_ask: Change {
-                side: Side::Sell,
-                level: PriceLevel {
-                    level: (message.ask_level.unwrap().parse::<f64>().unwrap() * 100.) as usize,
-                    amount: message.ask_amount.unwrap().parse::<f64>().unwrap(),
+            time: Some(trade.sent * 1_000_000),
+        });
+        Ok(())
+    }
+}
+
+impl ExchangeFeed for BinanceFeed {
+    fn name(&self) -> &'static str {
+        "binance"
+    }
+
+    fn url(&self) -> String {
+        self.ws_url.clone()
+    }
+
+    fn time_url(&self) -> Option<String> {
+        Some(format!("{}/time", self.rest_url))
+    }
+
+    fn decode_time(&self, body: &str) -> Result<i64, DecodeError> {
+        Ok(serde_json::from_str::<ServerTime>(body)?.server_time * 1_000_000)
+    }
+
+    fn subscriptions(&self) -> Vec<WsMessage> {
+        if self.symbols.is_empty() {
+            println!("Binance: no listings configured");
+            return Vec::new();
+        }
+        let mut params = Vec::new();
+        for symbol in self.symbols.iter() {
+            params.push(format!("{:?}", symbol.depth_channel));
+            params.push(format!("{:?}", symbol.trade_channel));
+        }
+        let sub_message = format!("{{\"method\": \"SUBSCRIBE\", \"params\": [{}], \"id\": 1}}", params.join(", "));
+        vec![WsMessage::Text(sub_message)]
+    }
+
+    fn decode(&mut self, frame: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let (header, _) = serde_json_core::from_str::<Header>(frame)?;
+        if header.event_type.is_none() {
+            // The SUBSCRIBE acknowledgement covers every stream on the connection.
+            if header.id.is_some() {
+                for symbol in self.symbols.iter_mut() {
+                    symbol.subscribed = true;
                 }
             }
+            return Ok(());
+        }
+        let symbol = match self.symbol(header.symbol) {
+            Some(symbol) => symbol,
+            None => return Ok(()),
         };
-        self.adapter.handle_book_update(update, &message.pair).await;
+        match header.event_type {
+            Some("depthUpdate") => {
+                symbol.subscribed = true;
+                let update = serde_json::from_str::<DepthUpdate>(frame)?;
+                if symbol.last_update_id.is_some() {
+                    return symbol.apply(update, events);
+                }
+                if symbol.buffer.len() >= MAX_BUFFERED {
+                    return Err(DecodeError::OutOfSync(format!("{:?} updates buffered waiting for a snapshot", symbol.buffer.len())));
+                }
+                symbol.buffer.push(update);
+            },
+            Some("aggTrade") | Some("trade") => {
+                let (trade, _) = serde_json_core::from_str::<AggTrade>(frame)?;
+                symbol.trade(trade, events)?;
+            },
+            _ => (),
+        }
+        Ok(())
     }
 
-    async fn handle_trade(&mut self, message: InboundMessage) {
-        let trade = Change {
-            side: match message.buy.unwrap() {
-                true => Side::Buy,
-                false => Side::Sell,
-            },
-            level: PriceLevel {
-                level: (message.price.unwrap().parse::<f64>().unwrap() * 100.) as usize,
-                amount: message.amount.unwrap().parse::<f64>().unwrap(),
-            }
+    fn snapshot_request(&mut self) -> Option<String> {
+        let symbol = self.symbols.iter_mut().find(|s| s.subscribed && !s.requested)?;
+        symbol.requested = true;
+        let symbol = symbol.symbol.clone();
+        Some(self.snapshot_url(&symbol))
+    }
+
+    fn decode_snapshot(&mut self, url: &str, body: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let index = match self.symbols.iter().position(|s| self.snapshot_url(&s.symbol) == url) {
+            Some(index) => index,
+            None => return Err(DecodeError::Unexpected(format!("snapshot for unknown url {:?}", url))),
         };
-        self.adapter.handle_trade(trade, &message.pair).await;
+        let symbol = &mut self.symbols[index];
+        let snapshot = serde_json::from_str::<DepthSnapshot>(body)?;
+        let mut initial_book = Snapshot::new();
+        for bid in snapshot.bids.iter() {
+            let _ = initial_book.bids.push(PriceLevel { level: bid.level, amount: bid.amount, sequence: 0 });
+        }
+        for ask in snapshot.asks.iter() {
+            let _ = initial_book.asks.push(PriceLevel { level: ask.level, amount: ask.amount, sequence: 0 });
+        }
+        events.push(FeedEvent::Snapshot { pair: symbol.pair.clone(), snapshot: initial_book });
+        symbol.last_update_id = Some(snapshot.last_update_id);
+        symbol.bridged = false;
+        for update in std::mem::take(&mut symbol.buffer) {
+            symbol.apply(update, events)?;
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        for symbol in self.symbols.iter_mut() {
+            symbol.subscribed = false;
+            symbol.requested = false;
+            symbol.last_update_id = None;
+            symbol.bridged = false;
+            symbol.buffer.clear();
+        }
     }
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_sequence_gap_is_out_of_sync() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = BinanceFeed::new(Arc::new(registry));
+    let mut events = Vec::new();
+    feed.decode(r#"{"e":"depthUpdate","E":1,"s":"ETHUSDT","U":95,"u":100,"b":[["1897.00","1.0"]],"a":[]}"#, &mut events).unwrap();
+    feed.decode(r#"{"e":"depthUpdate","E":2,"s":"ETHUSDT","U":101,"u":105,"b":[["1897.10","1.0"]],"a":[]}"#, &mut events).unwrap();
+    assert_eq!(Some("https://api.binance.com/api/v3/depth?symbol=ETHUSDT&limit=1000".to_string()), feed.snapshot_request());
+    assert_eq!(None, feed.snapshot_request());
+    assert!(events.is_empty());
+    let url = "https://api.binance.com/api/v3/depth?symbol=ETHUSDT&limit=1000";
+    feed.decode_snapshot(url, r#"{"lastUpdateId":102,"bids":[["1897.00","2.0"]],"asks":[["1897.30","1.0"]]}"#, &mut events).unwrap();
+    assert_eq!(2, events.len());
+    assert!(matches!(&events[1], FeedEvent::Delta { changes, .. } if changes[0].price_level.level == 189710));
+    feed.decode(r#"{"e":"depthUpdate","E":3,"s":"ETHUSDT","U":106,"u":108,"b":[],"a":[["1897.30","0"]]}"#, &mut events).unwrap();
+    assert_eq!(3, events.len());
+    let gap = feed.decode(r#"{"e":"depthUpdate","E":4,"s":"ETHUSDT","U":110,"u":111,"b":[],"a":[]}"#, &mut events);
+    assert!(matches!(gap, Err(DecodeError::OutOfSync(_))));
+}
+
+#[test]
+fn test_trade_side_is_the_taker() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = BinanceFeed::new(Arc::new(registry));
+    let mut events = Vec::new();
+    feed.decode(r#"{"e":"aggTrade","E":1689025543609,"s":"ETHUSDT","a":12345,"p":"1897.10","q":"0.5","f":100,"l":105,"T":1689025543605,"m":true,"M":true}"#, &mut events).unwrap();
+    feed.decode(r#"{"e":"aggTrade","E":1689025543610,"s":"ETHUSDT","a":12346,"p":"1897.20","q":"0.25","f":106,"l":106,"T":1689025543606,"m":false,"M":true}"#, &mut events).unwrap();
+    assert!(matches!(&events[0], FeedEvent::Trade { trade: Match { side: Side::Sell, price: 189710, .. }, time: Some(1689025543609000000), .. }));
+    assert!(matches!(&events[1], FeedEvent::Trade { trade: Match { side: Side::Buy, price: 189720, .. }, .. }));
+}
+
+#[test]
+fn test_snapshot_must_bridge_first_update() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = BinanceFeed::new(Arc::new(registry));
+    let mut events = Vec::new();
+    feed.decode(r#"{"e":"depthUpdate","E":1,"s":"ETHUSDT","U":110,"u":115,"b":[],"a":[]}"#, &mut events).unwrap();
+    let url = "https://api.binance.com/api/v3/depth?symbol=ETHUSDT&limit=1000";
+    let result = feed.decode_snapshot(url, r#"{"lastUpdateId":102,"bids":[],"asks":[]}"#, &mut events);
+    assert!(matches!(result, Err(DecodeError::OutOfSync(_))));
+}
+
+#[tokio::test]
+async fn test_sync_from_depth_snapshot() {
+    use futures_util::{SinkExt, StreamExt};
+    use crate::order_book::clients::client::{Heartbeat, ReconnectPolicy};
+    use crate::order_book::clients::feed::FeedDriver;
+    use crate::order_book::clients::test_support::{serve_http, RecordingAdapter};
+
+    let snapshot = r#"{"lastUpdateId":102,"bids":[["30000.00","1.0"],["29999.00","2.0"]],"asks":[["30001.00","1.5"]]}"#;
+    let (http_addr, requests) = serve_http(snapshot.to_string()).await;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let ws_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _subscribe = ws.next().await;
+        for frame in [
+            r#"{"result":null,"id":1}"#,
+            r#"{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":95,"u":100,"b":[["30000.00","0"]],"a":[]}"#,
+            r#"{"e":"depthUpdate","E":2,"s":"BTCUSDT","U":101,"u":105,"b":[],"a":[["30002.00","0.5"]]}"#,
+        ] {
+            ws.send(WsMessage::Text(frame.to_string())).await.unwrap();
+        }
+        while let Some(Ok(_)) = ws.next().await {}
+    });
+
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = BinanceFeed::new(Arc::new(registry));
+    feed.set_endpoints(format!("ws://{}", ws_addr), format!("http://{}", http_addr));
+    let adapter = RecordingAdapter::default();
+    let mut driver = FeedDriver::new(feed, adapter.clone(), ReconnectPolicy::default(), Heartbeat::default());
+    tokio::select! {
+        _ = driver.run() => panic!("Driver exited"),
+        _ = adapter.wait_for(3) => (),
+    }
+
+    // The subscription ack covers both configured symbols, so each gets its own snapshot.
+    // The driver also polls the server time, which may or may not have landed yet.
+    let mut requests = requests.lock().unwrap().clone();
+    requests.retain(|request| !request.starts_with("GET /time "));
+    requests.sort();
+    assert_eq!(vec![
+        "GET /depth?symbol=BTCUSDT&limit=1000 HTTP/1.1".to_string(),
+        "GET /depth?symbol=ETHUSDT&limit=1000 HTTP/1.1".to_string(),
+    ], requests);
+    let recorded = adapter.events.lock().unwrap();
+    let events: Vec<&FeedEvent> = recorded.iter()
+        .map(|(_, event)| event)
+        .filter(|event| event.pair() == "BTC-USD")
+        .collect();
+    assert_eq!(2, events.len());
+    assert!(matches!(events[0], FeedEvent::Snapshot { snapshot, .. } if snapshot.bids.len() == 2));
+    match events[1] {
+        FeedEvent::Delta { changes, .. } => {
+            assert_eq!(1, changes.len());
+            assert_eq!(Side::Sell, changes[0].side);
+            assert_eq!(3000200, changes[0].price_level.level);
+        },
+        other => panic!("Expected delta, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_decode_server_time() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let feed = BinanceFeed::new(Arc::new(registry));
+    assert_eq!(Some("https://api.binance.com/api/v3/time".to_string()), feed.time_url());
+    assert_eq!(1499827319559000000, feed.decode_time(r#"{"serverTime":1499827319559}"#).unwrap());
+}
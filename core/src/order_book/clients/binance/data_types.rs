@@ -1,39 +1,107 @@
-use serde::Deserialize;
+use serde::{de::{self, Visitor, SeqAccess}, Deserializer, Deserialize};
 
+use crate::order_book::data_types::to_level;
 #[derive(Debug, Deserialize, PartialEq)]
-pub struct InboundMessage {
-    pub message_type: heapless::String<8>,
-    pub pair: heapless::String<8>,
-    pub sent: Option<i64>,
-    pub price: Option<heapless::String<16>>,
-    pub amount: Option<heapless::String<16>>,
-    pub bid_level: Option<heapless::String<16>>,
-    pub ask_level: Option<heapless::String<16>>,
-    pub bid_amount: Option<heapless::String<16>>,
-    pub ask_amount: Option<heapless::String<16>>,
-    pub buy: Option<bool>,
+pub struct Header<'a> {
+    #[serde(rename = "e")]
+    pub event_type: Option<&'a str>,
+    #[serde(rename = "s")]
+    pub symbol: Option<&'a str>,
+    pub id: Option<u64>,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Update {
-    pub best_bid: Change,
-    pub best_ask: Change,
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct DepthUpdate {
+    #[serde(rename = "U")]
+    pub first_update_id: u64,
+    #[serde(rename = "u")]
+    pub final_update_id: u64,
+    #[serde(rename = "b")]
+    pub bids: Vec<PriceLevel>,
+    #[serde(rename = "a")]
+    pub asks: Vec<PriceLevel>,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Change {
-    pub side: Side,
-    pub level: PriceLevel,
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct DepthSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    pub last_update_id: u64,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Side {
-    Buy,
-    Sell,
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct AggTrade<'a> {
+    #[serde(rename = "E")]
+    pub sent: i64,
+    #[serde(rename = "p")]
+    pub price: &'a str,
+    #[serde(rename = "q")]
+    pub amount: &'a str,
+    // The buyer's order was resting, so the taker sold.
+    #[serde(rename = "m")]
+    pub buyer_is_maker: bool,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct ServerTime {
+    #[serde(rename = "serverTime")]
+    pub server_time: i64,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct PriceLevel {
     pub level: usize,
     pub amount: f64,
-}
\ No newline at end of file
+}
+
+impl<'de> Deserialize<'de> for PriceLevel {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(PriceLevelVisitor)
+    }
+}
+
+struct PriceLevelVisitor;
+
+impl<'de> Visitor<'de> for PriceLevelVisitor {
+    type Value = PriceLevel;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("A Binance [price, quantity] pair")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let price = seq.next_element::<&str>()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let amount = seq.next_element::<&str>()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(PriceLevel {
+            level: to_level(price.parse::<f64>().map_err(de::Error::custom)?),
+            amount: amount.parse::<f64>().map_err(de::Error::custom)?,
+        })
+    }
+}
+
+#[test]
+fn test_depth_update() {
+    let input = r#"{"e":"depthUpdate","E":1689025543609,"s":"ETHUSDT","U":157,"u":160,"b":[["1897.10000000","10.00000000"]],"a":[["1897.20000000","0.00000000"]]}"#;
+    let (header, _) = serde_json_core::from_str::<Header>(input).unwrap();
+    assert_eq!((Some("depthUpdate"), Some("ETHUSDT")), (header.event_type, header.symbol));
+    let update = serde_json::from_str::<DepthUpdate>(input).unwrap();
+    assert_eq!((157, 160), (update.first_update_id, update.final_update_id));
+    assert_eq!(PriceLevel { level: 189710, amount: 10.0 }, update.bids[0]);
+    assert_eq!(0.0, update.asks[0].amount);
+    let (header, _) = serde_json_core::from_str::<Header>(r#"{"result":null,"id":1}"#).unwrap();
+    assert_eq!(Header { event_type: None, symbol: None, id: Some(1) }, header);
+}
+
+#[test]
+fn test_agg_trade() {
+    let input = r#"{"e":"aggTrade","E":123456789,"s":"BNBBTC","a":12345,"p":"0.001","q":"100","f":100,"l":105,"T":123456785,"m":true,"M":true}"#;
+    let (trade, _) = serde_json_core::from_str::<AggTrade>(input).unwrap();
+    assert_eq!(AggTrade { sent: 123456789, price: "0.001", amount: "100", buyer_is_maker: true }, trade);
+}
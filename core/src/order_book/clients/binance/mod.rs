@@ -1,3 +1,2 @@
-pub mod binance_adapter;
 pub mod binance_client;
 pub mod data_types;
\ No newline at end of file
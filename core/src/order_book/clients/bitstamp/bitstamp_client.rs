@@ -1,91 +1,280 @@
-use std::{time::Duration, sync::Arc};
+use std::sync::Arc;
 
-use tokio::{time::Instant, sync::Mutex};
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
 
-use crate::order_book::{clients::{client::WebSocketClient}, multi_book::MultiBook};
+use crate::order_book::clients::feed::{DecodeError, ExchangeFeed, FeedEvent};
+use crate::order_book::instruments::InstrumentRegistry;
+use crate::order_book::data_types::{Change, PriceLevel, Side, Snapshot};
 
-use super::{bitstamp_adapter::BitstampAdapter, data_types::{Update, Message}};
+use super::data_types::{BookSnapshot, Message, PriceLevel as BitstampLevel};
 
-pub struct BitstampReceiveClient {
-    adapter: BitstampAdapter,
-    client: WebSocketClient,
+// Diffs held while the REST snapshot is in flight. Past this the snapshot is taking too long to
+// be worth reconciling against.
+const MAX_BUFFERED: usize = 4096;
+
+// Snapshot sync state for one Bitstamp channel on the shared connection.
+struct Symbol {
+    symbol: String,
+    channel: String,
     pair: heapless::String<8>,
+    subscribed: bool,
+    requested: bool,
+    snapshot_time: Option<u64>,
+    buffer: Vec<(u64, Vec<Change>)>,
+}
+
+pub struct BitstampFeed {
+    symbols: Vec<Symbol>,
+    ws_url: String,
+    rest_url: String,
 }
 
-impl<'a> BitstampReceiveClient {
-    pub async fn new(book: Arc<Mutex<MultiBook<3, 6>>>, pair: heapless::String<8>) -> BitstampReceiveClient {
-        return BitstampReceiveClient {
-            adapter: BitstampAdapter::new(book).await,
-            client: WebSocketClient::new("wss://ws.bitstamp.net".to_string()).await,
-            pair: pair,
+impl BitstampFeed {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> BitstampFeed {
+        let symbols = registry.venue_listings("bitstamp").into_iter().map(|(pair, listing)| Symbol {
+            symbol: listing.symbol.clone(),
+            channel: match listing.channel("book") {
+                Some(channel) => channel.to_string(),
+                None => format!("diff_order_book_{}", listing.symbol),
+            },
+            pair,
+            subscribed: false,
+            requested: false,
+            snapshot_time: None,
+            buffer: Vec::new(),
+        }).collect();
+        BitstampFeed {
+            symbols,
+            ws_url: "wss://ws.bitstamp.net".to_string(),
+            rest_url: "https://www.bitstamp.net/api/v2".to_string(),
         }
     }
 
-    pub async fn init(&mut self) {
-        let p = match self.pair.as_str() {
-            "ETH-USD" => "ethusd",
-            "BTC-USD" => "btcusd",
-            "ETH-USDT" => "ethusdt",
-            "BTC-USDT" => "btcusdt",
-            _ => panic!("Bad pair: {:?}", self.pair),
-        };
-        let sub_message: String = format!("{{\"event\": \"bts:subscribe\",\"data\": {{\"channel\": \"diff_order_book_{}\"}}}}", p).to_string();
-        self.client.send(tokio_tungstenite::tungstenite::protocol::Message::Text(sub_message)).await;
-        self.receive().await;
-    }
-
-    async fn receive(&mut self) {
-        let mut count: usize = 0;
-        let mut total: usize = 0;
-        let mut init = false;
-        while let Some(msg) = self.client.receive().await {
-            let start = Instant::now();
-            match msg {
-                Ok(msg) => {
-                    match serde_json_core::from_str::<Message>(&msg.to_text().unwrap()) {
-                        Ok((message, _)) => {
-                            let update = message.data;
-                            if init {
-                                self.handle_update(update).await;
-                                let duration = start.elapsed();
-                                count = count + 1;
-                                total = total + duration.as_nanos() as usize;
-                                let avg: f64 = (total as f64) / (count as f64);
-                                //println!("Bitstamp: message handled in {:?}", duration);
-                                //println!("Bitstamp: average message handle time for {:?} messages: {:?}", count, Duration::new(0, avg as u32));
-                            } else {
-                                self.handle_snapshot(update).await;
-                                init = true;
-                            }
-                        },
-                        Err(_) => (),
-                    }
+    pub fn set_endpoints(&mut self, ws_url: String, rest_url: String) {
+        self.ws_url = ws_url;
+        self.rest_url = rest_url;
+    }
+
+    fn microtimestamp(time: Option<&str>) -> Result<u64, DecodeError> {
+        time.and_then(|t| t.parse::<u64>().ok())
+            .ok_or_else(|| DecodeError::Unexpected(format!("bad microtimestamp {:?}", time)))
+    }
+
+    fn changes(bids: &[BitstampLevel], asks: &[BitstampLevel]) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for bid in bids.iter() {
+            changes.push(Change {
+                side: Side::Buy,
+                price_level: PriceLevel {
+                    level: bid.level,
+                    amount: bid.amount,
+                    sequence: 0
                 },
-                Err(err) => {
-                    println!("Bitstamp: {:?}\nAttempting reset.", err);
-                    return
+            });
+        }
+        for ask in asks.iter() {
+            changes.push(Change {
+                side: Side::Sell,
+                price_level: PriceLevel {
+                    level: ask.level,
+                    amount: ask.amount,
+                    sequence: 0
+                },
+            });
+        }
+        changes
+    }
+
+    // A diff with more changes than a delta holds goes out as several deltas.
+    fn deltas(pair: &heapless::String<8>, diff: Vec<Change>, events: &mut Vec<FeedEvent>) {
+        let mut changes = heapless::Vec::new();
+        for change in diff {
+            if let Err(change) = changes.push(change) {
+                events.push(FeedEvent::Delta { pair: pair.clone(), changes: std::mem::take(&mut changes) });
+                let _ = changes.push(change);
+            }
+        }
+        events.push(FeedEvent::Delta { pair: pair.clone(), changes });
+    }
+}
+
+impl ExchangeFeed for BitstampFeed {
+    fn name(&self) -> &'static str {
+        "bitstamp"
+    }
+
+    fn url(&self) -> String {
+        self.ws_url.clone()
+    }
+
+    fn subscriptions(&self) -> Vec<WsMessage> {
+        if self.symbols.is_empty() {
+            println!("Bitstamp: no listings configured");
+        }
+        // Bitstamp takes one channel per subscribe message.
+        self.symbols.iter().map(|symbol| {
+            let sub_message: String = format!("{{\"event\": \"bts:subscribe\",\"data\": {{\"channel\": {:?}}}}}", symbol.channel).to_string();
+            WsMessage::Text(sub_message)
+        }).collect()
+    }
+
+    // Diffs are buffered until the REST snapshot arrives, then any at or before the snapshot's
+    // microtimestamp are dropped.
+    fn decode(&mut self, frame: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let (message, _) = serde_json_core::from_str::<Message>(frame)?;
+        if message.event == "bts:request_reconnect" {
+            return Err(DecodeError::OutOfSync("venue requested a reconnect".to_string()));
+        }
+        let symbol = match self.symbols.iter_mut().find(|s| Some(s.channel.as_str()) == message.channel) {
+            Some(symbol) => symbol,
+            None => return Ok(()),
+        };
+        match message.event {
+            "bts:subscription_succeeded" => symbol.subscribed = true,
+            "data" => {
+                symbol.subscribed = true;
+                let time = BitstampFeed::microtimestamp(message.data.microtimestamp)?;
+                let changes = BitstampFeed::changes(&message.data.bids, &message.data.asks);
+                match symbol.snapshot_time {
+                    Some(snapshot_time) => {
+                        if time > snapshot_time {
+                            BitstampFeed::deltas(&symbol.pair, changes, events);
+                        }
+                    },
+                    None => {
+                        if symbol.buffer.len() >= MAX_BUFFERED {
+                            return Err(DecodeError::OutOfSync(format!("{:?} diffs buffered waiting for a snapshot", symbol.buffer.len())));
+                        }
+                        symbol.buffer.push((time, changes));
+                    },
                 }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn snapshot_request(&mut self) -> Option<String> {
+        let symbol = self.symbols.iter_mut().find(|s| s.subscribed && !s.requested)?;
+        symbol.requested = true;
+        Some(format!("{}/order_book/{}/", self.rest_url, symbol.symbol))
+    }
+
+    fn decode_snapshot(&mut self, url: &str, body: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let rest_url = &self.rest_url;
+        let symbol = match self.symbols.iter_mut().find(|s| format!("{}/order_book/{}/", rest_url, s.symbol) == url) {
+            Some(symbol) => symbol,
+            None => return Err(DecodeError::Unexpected(format!("snapshot for unknown url {:?}", url))),
+        };
+        let snapshot = serde_json::from_str::<BookSnapshot>(body)?;
+        let snapshot_time = BitstampFeed::microtimestamp(Some(snapshot.microtimestamp))?;
+        let mut initial_book = Snapshot::new();
+        for bid in snapshot.bids.iter() {
+            let _ = initial_book.bids.push(PriceLevel {level: bid.level, amount: bid.amount, sequence: 0});
+        }
+        for ask in snapshot.asks.iter() {
+            let _ = initial_book.asks.push(PriceLevel {level: ask.level, amount: ask.amount, sequence: 0});
+        }
+        events.push(FeedEvent::Snapshot { pair: symbol.pair.clone(), snapshot: initial_book });
+        for (time, changes) in symbol.buffer.drain(..) {
+            if time > snapshot_time {
+                BitstampFeed::deltas(&symbol.pair, changes, events);
             }
         }
+        symbol.snapshot_time = Some(snapshot_time);
+        Ok(())
     }
 
-    async fn handle_snapshot(&mut self, snapshot: Update) {
-        self.adapter.init_order_book(snapshot).await;
+    fn keepalive(&self) -> Option<WsMessage> {
+        Some(WsMessage::Text("{\"event\": \"bts:heartbeat\"}".to_string()))
     }
 
-    async fn handle_update(&mut self, update: Update) {
-        self.adapter.update(update).await;
+    fn reset(&mut self) {
+        for symbol in self.symbols.iter_mut() {
+            symbol.subscribed = false;
+            symbol.requested = false;
+            symbol.snapshot_time = None;
+            symbol.buffer.clear();
+        }
     }
 }
 
-pub struct BitstampSendClient {
-    client: WebSocketClient,
-}
+#[tokio::test]
+async fn test_sync_from_rest_snapshot() {
+    use futures_util::{SinkExt, StreamExt};
+    use crate::order_book::clients::client::{Heartbeat, ReconnectPolicy};
+    use crate::order_book::clients::feed::FeedDriver;
+    use crate::order_book::clients::test_support::{serve_http, RecordingAdapter};
 
-impl BitstampSendClient {
-    pub async fn new() -> Self {
-        return BitstampSendClient {
-            client: WebSocketClient::new("wss://ws.bitstamp.net".to_string()).await
+    let snapshot = r#"{"timestamp":"0","microtimestamp":"200","bids":[["30000","1.0"],["29999","2.0"]],"asks":[["30001","1.5"]]}"#;
+    let (http_addr, requests) = serve_http(snapshot.to_string()).await;
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let ws_addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let _subscribe = ws.next().await;
+        for frame in [
+            r#"{"event":"bts:subscription_succeeded","channel":"diff_order_book_btcusd","data":{}}"#,
+            r#"{"data":{"microtimestamp":"100","bids":[["30000","0"]],"asks":[]},"channel":"diff_order_book_btcusd","event":"data"}"#,
+            r#"{"data":{"microtimestamp":"300","bids":[],"asks":[["30002","0.5"]]},"channel":"diff_order_book_btcusd","event":"data"}"#,
+        ] {
+            ws.send(WsMessage::Text(frame.to_string())).await.unwrap();
         }
+        while let Some(Ok(_)) = ws.next().await {}
+    });
+
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = BitstampFeed::new(Arc::new(registry));
+    feed.set_endpoints(format!("ws://{}", ws_addr), format!("http://{}", http_addr));
+    let adapter = RecordingAdapter::default();
+    let mut driver = FeedDriver::new(feed, adapter.clone(), ReconnectPolicy::default(), Heartbeat::default());
+    tokio::select! {
+        _ = driver.run() => panic!("Driver exited"),
+        _ = adapter.wait_for(2) => (),
     }
-}
\ No newline at end of file
+
+    assert_eq!(vec!["GET /order_book/btcusd/ HTTP/1.1".to_string()], *requests.lock().unwrap());
+    let events = adapter.events.lock().unwrap();
+    assert_eq!(2, events.len());
+    match &events[0].1 {
+        FeedEvent::Snapshot { pair, snapshot } => {
+            assert_eq!("BTC-USD", pair.as_str());
+            assert_eq!(2, snapshot.bids.len());
+            assert_eq!(3000000, snapshot.bids[0].level);
+        },
+        other => panic!("Expected snapshot, got {:?}", other),
+    }
+    match &events[1].1 {
+        FeedEvent::Delta { changes, .. } => {
+            assert_eq!(1, changes.len());
+            assert_eq!(Side::Sell, changes[0].side);
+            assert_eq!(3000200, changes[0].price_level.level);
+        },
+        other => panic!("Expected delta, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_large_diff_split_into_deltas() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = BitstampFeed::new(Arc::new(registry));
+    let mut events = Vec::new();
+    feed.decode(r#"{"event":"bts:subscription_succeeded","channel":"diff_order_book_btcusd","data":{}}"#, &mut events).unwrap();
+    let url = feed.snapshot_request().unwrap();
+    feed.decode_snapshot(&url, r#"{"timestamp":"0","microtimestamp":"200","bids":[["30000","1.0"]],"asks":[["30001","1.5"]]}"#, &mut events).unwrap();
+    let bids: Vec<String> = (0..300).map(|i| format!("[\"{}\",\"1.0\"]", 29999 - i)).collect();
+    let asks: Vec<String> = (0..300).map(|i| format!("[\"{}\",\"1.0\"]", 30002 + i)).collect();
+    let diff = format!(
+        "{{\"data\":{{\"microtimestamp\":\"300\",\"bids\":[{}],\"asks\":[{}]}},\"channel\":\"diff_order_book_btcusd\",\"event\":\"data\"}}",
+        bids.join(","),
+        asks.join(","),
+    );
+    feed.decode(&diff, &mut events).unwrap();
+    let sizes: Vec<usize> = events[1..].iter().map(|event| match event {
+        FeedEvent::Delta { changes, .. } => changes.len(),
+        other => panic!("Expected delta, got {:?}", other),
+    }).collect();
+    assert_eq!(vec![512, 88], sizes);
+    assert!(matches!(&events[2], FeedEvent::Delta { changes, .. } if changes[87].side == Side::Sell && changes[87].price_level.level == 3030100));
+}
@@ -1,14 +1,29 @@
 use serde::{de::{Visitor, SeqAccess}, Deserializer, Deserialize};
 
+use crate::order_book::data_types::to_level;
 #[derive(Debug, Deserialize)]
-pub struct Message {
-    pub data: Update,
+pub struct Message<'a> {
+    pub event: &'a str,
+    pub channel: Option<&'a str>,
+    #[serde(borrow)]
+    pub data: Update<'a>,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct Update {
-    pub bids: heapless::Vec<PriceLevel, 128>,
-    pub asks: heapless::Vec<PriceLevel, 128>,
+pub struct Update<'a> {
+    pub microtimestamp: Option<&'a str>,
+    #[serde(default)]
+    pub bids: Vec<PriceLevel>,
+    #[serde(default)]
+    pub asks: Vec<PriceLevel>,
+}
+
+// Response from the REST `order_book` endpoint, which can run to thousands of levels.
+#[derive(Debug, Deserialize)]
+pub struct BookSnapshot<'a> {
+    pub microtimestamp: &'a str,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
 }
 
 #[derive(Debug)]
@@ -39,11 +54,23 @@ impl<'de> Visitor<'de> for PriceLevelVisitor {
     where
         A: SeqAccess<'de>,
     {
-        let level = (seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap() * 100.) as usize;
+        let level = to_level(seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap());
         let amount = seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap();
         Ok(PriceLevel {
-            level: level,
-            amount: amount,
+            level,
+            amount,
         })
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_message() {
+    let diff = r#"{"data":{"timestamp":"1689025543","microtimestamp":"1689025543609620","bids":[["30000","0.50000000"]],"asks":[]},"channel":"diff_order_book_btcusd","event":"data"}"#;
+    let (message, _) = serde_json_core::from_str::<Message>(diff).unwrap();
+    assert_eq!("data", message.event);
+    assert_eq!(Some("1689025543609620"), message.data.microtimestamp);
+    assert_eq!(3000000, message.data.bids[0].level);
+    let subscribed = r#"{"event":"bts:subscription_succeeded","channel":"diff_order_book_btcusd","data":{}}"#;
+    let (message, _) = serde_json_core::from_str::<Message>(subscribed).unwrap();
+    assert_eq!("bts:subscription_succeeded", message.event);
+    assert!(message.data.bids.is_empty());
+}
@@ -1,3 +1,2 @@
-pub mod bitstamp_adapter;
 pub mod bitstamp_client;
 pub mod data_types;
\ No newline at end of file
@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
+
+use crate::order_book::{self, data_types::{Change, PriceLevel}};
+use crate::order_book::clients::feed::{DecodeError, ExchangeFeed, FeedEvent};
+use crate::order_book::instruments::InstrumentRegistry;
+
+use super::data_types::{Book, BookMessage, Message, Side, TimeMessage, TradeMessage};
+
+// Sync state for one Bybit symbol on the shared connection.
+struct Symbol {
+    symbol: String,
+    pair: heapless::String<8>,
+    update_id: Option<u64>,
+    seq: u64,
+}
+
+pub struct BybitFeed {
+    symbols: Vec<Symbol>,
+    book_channel: String,
+    trade_channel: String,
+}
+
+impl BybitFeed {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> BybitFeed {
+        let listings = registry.venue_listings("bybit");
+        let channel = |name: &str, default: &str| listings.first()
+            .and_then(|(_, listing)| listing.channel(name))
+            .unwrap_or(default)
+            .to_string();
+        let book_channel = channel("book", "orderbook.200");
+        let trade_channel = channel("trades", "publicTrade");
+        BybitFeed {
+            symbols: listings.iter().map(|(pair, listing)| Symbol {
+                symbol: listing.symbol.clone(),
+                pair: pair.clone(),
+                update_id: None,
+                seq: 0,
+            }).collect(),
+            book_channel,
+            trade_channel,
+        }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Option<&mut Symbol> {
+        self.symbols.iter_mut().find(|s| s.symbol == symbol)
+    }
+
+    fn side(side: &Side) -> order_book::data_types::Side {
+        match side {
+            Side::Buy => order_book::data_types::Side::Buy,
+            Side::Sell => order_book::data_types::Side::Sell,
+        }
+    }
+
+}
+
+impl Symbol {
+    fn snapshot(&mut self, book: Book, events: &mut Vec<FeedEvent>) {
+        let mut initial_book = order_book::data_types::Snapshot::new();
+        for bid in book.bids.iter() {
+            let _ = initial_book.bids.push(PriceLevel {level: bid.level, amount: bid.amount, sequence: 0});
+        }
+        for ask in book.asks.iter() {
+            let _ = initial_book.asks.push(PriceLevel {level: ask.level, amount: ask.amount, sequence: 0});
+        }
+        self.update_id = Some(book.update_id);
+        self.seq = book.seq;
+        events.push(FeedEvent::Snapshot { pair: self.pair.clone(), snapshot: initial_book });
+    }
+
+    // Deltas must carry the next update id; anything older than the snapshot is dropped.
+    fn update(&mut self, book: Book, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let last = match self.update_id {
+            Some(last) => last,
+            None => return Ok(()),
+        };
+        if book.update_id <= last || book.seq < self.seq {
+            return Ok(());
+        }
+        if book.update_id != last + 1 {
+            self.update_id = None;
+            return Err(DecodeError::OutOfSync(format!("expected update {:?}, got {:?}", last + 1, book.update_id)));
+        }
+        self.update_id = Some(book.update_id);
+        self.seq = book.seq;
+        let mut changes = heapless::Vec::<Change, 512>::new();
+        for bid in book.bids.iter() {
+            let _ = changes.push(Change {
+                side: order_book::data_types::Side::Buy,
+                price_level: PriceLevel {level: bid.level, amount: bid.amount, sequence: 0},
+            });
+        }
+        for ask in book.asks.iter() {
+            let _ = changes.push(Change {
+                side: order_book::data_types::Side::Sell,
+                price_level: PriceLevel {level: ask.level, amount: ask.amount, sequence: 0},
+            });
+        }
+        events.push(FeedEvent::Delta { pair: self.pair.clone(), changes });
+        Ok(())
+    }
+}
+
+impl ExchangeFeed for BybitFeed {
+    fn name(&self) -> &'static str {
+        "bybit"
+    }
+
+    fn url(&self) -> String {
+        "wss://stream.bybit.com/v5/public/spot".to_string()
+    }
+
+    fn time_url(&self) -> Option<String> {
+        Some("https://api.bybit.com/v5/market/time".to_string())
+    }
+
+    fn decode_time(&self, body: &str) -> Result<i64, DecodeError> {
+        let (message, _) = serde_json_core::from_str::<TimeMessage>(body)?;
+        message.result.time_nano.parse::<i64>().map_err(|e| DecodeError::Unexpected(e.to_string()))
+    }
+
+    fn subscriptions(&self) -> Vec<WsMessage> {
+        if self.symbols.is_empty() {
+            println!("Bybit: no listings configured");
+            return Vec::new();
+        }
+        let mut args = Vec::new();
+        for symbol in self.symbols.iter() {
+            args.push(format!("\"{}.{}\"", self.book_channel, symbol.symbol));
+            args.push(format!("\"{}.{}\"", self.trade_channel, symbol.symbol));
+        }
+        let sub_message = format!("{{\"op\": \"subscribe\", \"args\": [{}]}}", args.join(", "));
+        vec![WsMessage::Text(sub_message)]
+    }
+
+    fn decode(&mut self, frame: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let (message, _) = serde_json_core::from_str::<Message>(frame)?;
+        if message.success == Some(false) {
+            return Err(DecodeError::Unexpected(format!("{:?} failed: {:?}", message.op, message.ret_msg)));
+        }
+        let topic = match message.topic {
+            Some(topic) => topic,
+            None => return Ok(()),
+        };
+        if topic.starts_with("orderbook.") {
+            let (book, _) = serde_json_core::from_str::<BookMessage>(frame)?;
+            let symbol = match self.symbol(book.data.symbol) {
+                Some(symbol) => symbol,
+                None => return Ok(()),
+            };
+            // Bybit resends a snapshot, with `u` restarting at 1, whenever its side resets.
+            if message.msg_type == Some("snapshot") || book.data.update_id == 1 {
+                symbol.snapshot(book.data, events);
+            } else {
+                symbol.update(book.data, events)?;
+            }
+        } else if topic.starts_with("publicTrade.") {
+            let (trades, _) = serde_json_core::from_str::<TradeMessage>(frame)?;
+            for trade in trades.data.iter() {
+                let pair = match self.symbol(trade.symbol) {
+                    Some(symbol) => symbol.pair.clone(),
+                    None => continue,
+                };
+                let price = trade.price.parse::<f64>().map_err(|e| DecodeError::Unexpected(e.to_string()))?;
+                let size = trade.size.parse::<f64>().map_err(|e| DecodeError::Unexpected(e.to_string()))?;
+                events.push(FeedEvent::Trade {
+                    pair,
+                    trade: order_book::data_types::Match { side: BybitFeed::side(&trade.side), size, price: order_book::data_types::to_level(price) },
+                    time: Some(trade.time * 1_000_000),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    // Bybit closes connections that go 10 minutes without an application-level ping.
+    fn keepalive(&self) -> Option<WsMessage> {
+        Some(WsMessage::Text("{\"op\": \"ping\"}".to_string()))
+    }
+
+    fn reset(&mut self) {
+        for symbol in self.symbols.iter_mut() {
+            symbol.update_id = None;
+            symbol.seq = 0;
+        }
+    }
+}
+
+#[test]
+fn test_decode_snapshot_then_deltas() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = BybitFeed::new(Arc::new(registry));
+    let mut events = Vec::new();
+    feed.decode(r#"{"success":true,"ret_msg":"subscribe","conn_id":"c1","op":"subscribe"}"#, &mut events).unwrap();
+    feed.decode(r#"{"topic":"orderbook.200.BTCUSDT","ts":1,"type":"snapshot","data":{"s":"BTCUSDT","b":[["16493.50","0.006"]],"a":[["16611.00","0.029"]],"u":100,"seq":5000},"cts":1}"#, &mut events).unwrap();
+    feed.decode(r#"{"topic":"orderbook.200.BTCUSDT","ts":2,"type":"delta","data":{"s":"BTCUSDT","b":[["16493.50","0"]],"a":[],"u":101,"seq":5001},"cts":2}"#, &mut events).unwrap();
+    feed.decode(r#"{"topic":"publicTrade.BTCUSDT","type":"snapshot","ts":3,"data":[{"T":1672304486865,"s":"BTCUSDT","S":"Sell","v":"0.001","p":"16578.50","L":"PlusTick","i":"x","BT":false}]}"#, &mut events).unwrap();
+    assert_eq!(3, events.len());
+    assert!(matches!(&events[0], FeedEvent::Snapshot { pair, snapshot } if pair == "BTC-USD" && snapshot.bids[0].level == 1649350));
+    assert!(matches!(&events[1], FeedEvent::Delta { changes, .. } if changes[0].price_level.amount == 0.0));
+    assert!(matches!(&events[2], FeedEvent::Trade { trade, .. } if trade.side == order_book::data_types::Side::Sell && trade.price == 1657850));
+    let gap = feed.decode(r#"{"topic":"orderbook.200.BTCUSDT","ts":4,"type":"delta","data":{"s":"BTCUSDT","b":[],"a":[],"u":103,"seq":5003},"cts":4}"#, &mut events);
+    assert!(matches!(gap, Err(DecodeError::OutOfSync(_))));
+}
+
+#[test]
+fn test_decode_routes_symbols() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = BybitFeed::new(Arc::new(registry));
+    match &feed.subscriptions()[0] {
+        WsMessage::Text(text) => assert!(text.contains("\"orderbook.200.ETHUSDT\"") && text.contains("\"publicTrade.BTCUSDT\"")),
+        other => panic!("Expected text subscription, got {:?}", other),
+    }
+    let mut events = Vec::new();
+    feed.decode(r#"{"topic":"orderbook.200.ETHUSDT","ts":1,"type":"snapshot","data":{"s":"ETHUSDT","b":[["1897.10","1.0"]],"a":[["1897.20","2.0"]],"u":10,"seq":1},"cts":1}"#, &mut events).unwrap();
+    feed.decode(r#"{"topic":"orderbook.200.BTCUSDT","ts":1,"type":"snapshot","data":{"s":"BTCUSDT","b":[["16493.50","0.006"]],"a":[["16611.00","0.029"]],"u":100,"seq":5000},"cts":1}"#, &mut events).unwrap();
+    feed.decode(r#"{"topic":"orderbook.200.ETHUSDT","ts":2,"type":"delta","data":{"s":"ETHUSDT","b":[["1897.10","0"]],"a":[],"u":11,"seq":2},"cts":2}"#, &mut events).unwrap();
+    assert_eq!(["ETH-USD", "BTC-USD", "ETH-USD"], [events[0].pair(), events[1].pair(), events[2].pair()]);
+}
+
+#[test]
+fn test_decode_server_time() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let feed = BybitFeed::new(Arc::new(registry));
+    let body = r#"{"retCode":0,"retMsg":"OK","result":{"timeSecond":"1688639403","timeNano":"1688639403423213947"},"retExtInfo":{},"time":1688639403423}"#;
+    assert_eq!(1688639403423213947, feed.decode_time(body).unwrap());
+}
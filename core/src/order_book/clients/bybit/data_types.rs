@@ -0,0 +1,186 @@
+use serde::{de::{self, Visitor, SeqAccess}, Deserializer, Deserialize};
+
+use crate::order_book::data_types::to_level;
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Message<'a> {
+    pub topic: Option<&'a str>,
+    #[serde(rename = "type")]
+    pub msg_type: Option<&'a str>,
+    pub op: Option<&'a str>,
+    pub success: Option<bool>,
+    pub ret_msg: Option<&'a str>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct TimeMessage<'a> {
+    #[serde(borrow)]
+    pub result: ServerTime<'a>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct ServerTime<'a> {
+    #[serde(rename = "timeNano")]
+    pub time_nano: &'a str,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct BookMessage<'a> {
+    #[serde(borrow)]
+    pub data: Book<'a>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Book<'a> {
+    #[serde(rename = "s")]
+    pub symbol: &'a str,
+    #[serde(rename = "b")]
+    pub bids: heapless::Vec<PriceLevel, 256>,
+    #[serde(rename = "a")]
+    pub asks: heapless::Vec<PriceLevel, 256>,
+    #[serde(rename = "u")]
+    pub update_id: u64,
+    pub seq: u64,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct TradeMessage<'a> {
+    #[serde(borrow)]
+    pub data: heapless::Vec<Trade<'a>, 64>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Trade<'a> {
+    #[serde(rename = "T")]
+    pub time: i64,
+    #[serde(rename = "s")]
+    pub symbol: &'a str,
+    #[serde(rename = "S")]
+    pub side: Side,
+    #[serde(rename = "v")]
+    pub size: &'a str,
+    #[serde(rename = "p")]
+    pub price: &'a str,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct PriceLevel {
+    pub level: usize,
+    pub amount: f64,
+}
+
+impl<'de> Deserialize<'de> for PriceLevel {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(PriceLevelVisitor)
+    }
+}
+
+struct PriceLevelVisitor;
+
+impl<'de> Visitor<'de> for PriceLevelVisitor {
+    type Value = PriceLevel;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("A Bybit [price, size] level")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let price = seq.next_element::<&str>()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let amount = seq.next_element::<&str>()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        Ok(PriceLevel {
+            level: to_level(price.parse::<f64>().map_err(de::Error::custom)?),
+            amount: amount.parse::<f64>().map_err(de::Error::custom)?,
+        })
+    }
+}
+
+#[test]
+fn test_message() {
+    let input = r#"{"topic":"orderbook.200.BTCUSDT","ts":1672304484978,"type":"delta","data":{"s":"BTCUSDT","b":[],"a":[],"u":18521289,"seq":7961638725},"cts":1672304484976}"#;
+    let result = serde_json_core::from_str::<Message>(input);
+    match &result {
+        Err(e) => println!("{:?}", e.to_string()),
+        Ok(r) => println!("{:?}", r),
+    }
+    assert!(result.is_ok());
+    assert_eq!(Some("delta"), result.unwrap().0.msg_type);
+}
+
+#[test]
+fn test_message_pong() {
+    let input = r#"{"success":true,"ret_msg":"pong","conn_id":"0970e817-426e-429a-a679-ff7f55e0b16a","op":"ping"}"#;
+    let result = serde_json_core::from_str::<Message>(input);
+    match &result {
+        Err(e) => println!("{:?}", e.to_string()),
+        Ok(r) => println!("{:?}", r),
+    }
+    assert!(result.is_ok());
+    assert_eq!(Some("ping"), result.unwrap().0.op);
+}
+
+#[test]
+fn test_book_snapshot() {
+    let input = r#"
+    {
+        "topic": "orderbook.200.BTCUSDT",
+        "ts": 1672304484978,
+        "type": "snapshot",
+        "data": {
+            "s": "BTCUSDT",
+            "b": [
+                ["16493.50", "0.006"],
+                ["16493.00", "0.100"]
+            ],
+            "a": [
+                ["16611.00", "0.029"],
+                ["16612.00", "0.213"]
+            ],
+            "u": 18521288,
+            "seq": 7961638724
+        },
+        "cts": 1672304484976
+    }"#;
+    let result = serde_json_core::from_str::<BookMessage>(input);
+    match &result {
+        Err(e) => println!("{:?}", e.to_string()),
+        Ok(r) => println!("{:?}", r),
+    }
+    assert!(result.is_ok());
+    let book = result.unwrap().0.data;
+    assert_eq!(PriceLevel { level: 1649350, amount: 0.006 }, book.bids[0]);
+    assert_eq!((18521288, 7961638724), (book.update_id, book.seq));
+}
+
+#[test]
+fn test_trade() {
+    let input = r#"{"topic":"publicTrade.BTCUSDT","type":"snapshot","ts":1672304486868,"data":[{"T":1672304486865,"s":"BTCUSDT","S":"Buy","v":"0.001","p":"16578.50","L":"PlusTick","i":"20f43950-d8dd-5b31-9112-a178eb6023af","BT":false}]}"#;
+    let result = serde_json_core::from_str::<TradeMessage>(input);
+    match &result {
+        Err(e) => println!("{:?}", e.to_string()),
+        Ok(r) => println!("{:?}", r),
+    }
+    assert!(result.is_ok());
+    assert_eq!(Side::Buy, result.unwrap().0.data[0].side);
+}
+
+#[test]
+fn test_price_level() {
+    let input = r#"["16493.50", "0.006"]"#;
+    let result = serde_json_core::from_str::<PriceLevel>(input);
+    match &result {
+        Err(e) => println!("{:?}", e.to_string()),
+        Ok(r) => println!("{:?}", r),
+    }
+    assert!(result.is_ok());
+}
@@ -0,0 +1,2 @@
+pub mod bybit_client;
+pub mod data_types;
\ No newline at end of file
@@ -1,31 +1,47 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::Duration;
+
 use futures_util::{StreamExt, SinkExt};
+use rand::Rng;
+use serde::Deserialize;
 use tokio::net::TcpStream;
+use tokio::time::Instant;
 use tokio_tungstenite::tungstenite::Error;
-use tokio_tungstenite::{ MaybeTlsStream, WebSocketStream, connect_async, connect_async_with_config};
+use tokio_tungstenite::{ MaybeTlsStream, WebSocketStream, connect_async_with_config};
 use tokio_tungstenite::tungstenite::protocol::{Message, WebSocketConfig};
 
-pub struct FIXClient {
-
-}
+use crate::order_book::instruments::ConfigError;
 
-impl FIXClient {
-
-}
+pub use super::fix::FIXClient;
 
-pub struct UDPClient {
+pub use super::udp::UDPClient;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Heartbeat {
+    pub ping_interval: Duration,
+    pub idle_timeout: Duration,
 }
 
-impl UDPClient {
-
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat {
+            ping_interval: Duration::from_secs(15),
+            idle_timeout: Duration::from_secs(30),
+        }
+    }
 }
 
 pub struct WebSocketClient {
     ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    heartbeat: Option<Heartbeat>,
+    keepalive: Option<Message>,
+    last_received: Instant,
+    next_ping: Instant,
 }
 
 impl WebSocketClient {
-    pub async fn new(address: String) -> Self {
+    pub async fn new(address: String) -> Result<Self, Error> {
         let config = WebSocketConfig {
             max_send_queue: None,
             max_frame_size: None,
@@ -37,13 +53,23 @@ impl WebSocketClient {
             Some(config),
             true
         ).await;
-        match result {
-            Ok((ws_stream, _)) => return WebSocketClient {ws_stream},
-            Err(result) => {
-                println!("Error connecting: {:?}", result.to_string());
-                panic!();
-            }
-        };
+        let (ws_stream, _) = result?;
+        Ok(WebSocketClient {
+            ws_stream,
+            heartbeat: None,
+            keepalive: None,
+            last_received: Instant::now(),
+            next_ping: Instant::now(),
+        })
+    }
+
+    // Sends a WebSocket ping, plus the venue's own heartbeat message if it has one, every
+    // `ping_interval`, and fails `receive` once nothing at all has arrived for `idle_timeout`.
+    pub fn set_heartbeat(&mut self, heartbeat: Heartbeat, keepalive: Option<Message>) {
+        self.heartbeat = Some(heartbeat);
+        self.keepalive = keepalive;
+        self.last_received = Instant::now();
+        self.next_ping = Instant::now() + heartbeat.ping_interval;
     }
 
     pub async fn send(&mut self, msg: Message) {
@@ -53,6 +79,277 @@ impl WebSocketClient {
     }
 
     pub async fn receive(&mut self) -> Option<Result<Message, Error>> {
-        return self.ws_stream.next().await;
+        let heartbeat = match self.heartbeat {
+            Some(heartbeat) => heartbeat,
+            None => return self.ws_stream.next().await,
+        };
+        loop {
+            let idle_deadline = self.last_received + heartbeat.idle_timeout;
+            tokio::select! {
+                msg = self.ws_stream.next() => {
+                    self.last_received = Instant::now();
+                    if let Some(Ok(Message::Ping(_))) = &msg {
+                        // tungstenite queues the pong itself; flushing sends it now rather than
+                        // on our next write.
+                        if let Err(err) = self.ws_stream.flush().await {
+                            return Some(Err(err));
+                        }
+                    }
+                    return msg;
+                },
+                _ = tokio::time::sleep_until(self.next_ping) => {
+                    self.next_ping += heartbeat.ping_interval;
+                    self.send(Message::Ping(Vec::new())).await;
+                    if let Some(keepalive) = self.keepalive.clone() {
+                        self.send(keepalive).await;
+                    }
+                },
+                _ = tokio::time::sleep_until(idle_deadline) => {
+                    let err = std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        format!("no data received for {:?}", heartbeat.idle_timeout));
+                    return Some(Err(Error::Io(err)));
+                },
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    // Fraction of the delay added or removed at random, so venues that drop everyone at once
+    // don't see every client come back in lockstep.
+    pub jitter: f64,
+    pub max_attempts: Option<u32>,
+    pub cooldown: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: Some(10),
+            cooldown: Duration::from_secs(300),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    pub fn base_delay(&self, attempt: u32) -> Duration {
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(attempt as i32);
+        Duration::from_secs_f64(delay.min(self.max_delay.as_secs_f64()))
+    }
+
+    // Delay before reconnect attempt `attempt` (0-based), or the cooldown once the attempt
+    // budget is spent.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        if let Some(max_attempts) = self.max_attempts {
+            if attempt >= max_attempts {
+                return self.cooldown;
+            }
+        }
+        let base = self.base_delay(attempt).as_secs_f64();
+        let jitter = if self.jitter > 0.0 {
+            rand::thread_rng().gen_range(-self.jitter..=self.jitter)
+        } else {
+            0.0
+        };
+        Duration::from_secs_f64((base * (1.0 + jitter)).max(0.0))
+    }
+}
+
+// One entry of the connection config file. Whatever a venue leaves out comes from the default
+// entry, and whatever that leaves out from `ReconnectPolicy::default()` and `Heartbeat::default()`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConnectionSettings {
+    initial_delay_ms: Option<u64>,
+    max_delay_ms: Option<u64>,
+    multiplier: Option<f64>,
+    jitter: Option<f64>,
+    // 0 keeps retrying without a cooldown.
+    max_attempts: Option<u32>,
+    cooldown_ms: Option<u64>,
+    ping_interval_ms: Option<u64>,
+    idle_timeout_ms: Option<u64>,
+}
+
+impl ConnectionSettings {
+    fn over(&self, base: (ReconnectPolicy, Heartbeat)) -> Result<(ReconnectPolicy, Heartbeat), ConfigError> {
+        let (reconnect, heartbeat) = base;
+        let reconnect = ReconnectPolicy {
+            initial_delay: self.initial_delay_ms.map_or(reconnect.initial_delay, Duration::from_millis),
+            max_delay: self.max_delay_ms.map_or(reconnect.max_delay, Duration::from_millis),
+            multiplier: self.multiplier.unwrap_or(reconnect.multiplier),
+            jitter: self.jitter.unwrap_or(reconnect.jitter),
+            max_attempts: match self.max_attempts {
+                Some(0) => None,
+                Some(max_attempts) => Some(max_attempts),
+                None => reconnect.max_attempts,
+            },
+            cooldown: self.cooldown_ms.map_or(reconnect.cooldown, Duration::from_millis),
+        };
+        let heartbeat = Heartbeat {
+            ping_interval: self.ping_interval_ms.map_or(heartbeat.ping_interval, Duration::from_millis),
+            idle_timeout: self.idle_timeout_ms.map_or(heartbeat.idle_timeout, Duration::from_millis),
+        };
+        if reconnect.multiplier < 1.0 {
+            return Err(ConfigError::Invalid(format!("multiplier {:?} is below 1", reconnect.multiplier)));
+        }
+        if !(0.0..1.0).contains(&reconnect.jitter) {
+            return Err(ConfigError::Invalid(format!("jitter {:?} is not between 0 and 1", reconnect.jitter)));
+        }
+        // A pong has to be able to arrive before the connection counts as idle.
+        if heartbeat.idle_timeout <= heartbeat.ping_interval {
+            return Err(ConfigError::Invalid(format!("idle timeout {:?} is not longer than the ping interval {:?}", heartbeat.idle_timeout, heartbeat.ping_interval)));
+        }
+        Ok((reconnect, heartbeat))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ConnectionFile {
+    #[serde(default)]
+    default: ConnectionSettings,
+    #[serde(default)]
+    venues: HashMap<String, ConnectionSettings>,
+}
+
+// Reconnect and heartbeat settings for each venue's connection.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConnectionConfig {
+    default: (ReconnectPolicy, Heartbeat),
+    venues: HashMap<String, (ReconnectPolicy, Heartbeat)>,
+}
+
+impl ConnectionConfig {
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        ConnectionConfig::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let file: ConnectionFile = serde_json::from_str(contents).map_err(ConfigError::Parse)?;
+        let default = file.default.over((ReconnectPolicy::default(), Heartbeat::default()))?;
+        let mut venues = HashMap::new();
+        for (venue, settings) in file.venues.iter() {
+            let settings = settings.over(default).map_err(|err| ConfigError::Invalid(format!("{}: {}", venue, err)))?;
+            venues.insert(venue.to_string(), settings);
+        }
+        Ok(ConnectionConfig { default, venues })
+    }
+
+    pub fn reconnect(&self, venue: &str) -> ReconnectPolicy {
+        self.venues.get(venue).unwrap_or(&self.default).0
+    }
+
+    pub fn heartbeat(&self, venue: &str) -> Heartbeat {
+        self.venues.get(venue).unwrap_or(&self.default).1
+    }
+}
+
+#[test]
+fn test_reconnect_policy_backoff() {
+    let policy = ReconnectPolicy {
+        initial_delay: Duration::from_millis(100),
+        max_delay: Duration::from_secs(1),
+        multiplier: 2.0,
+        jitter: 0.0,
+        max_attempts: Some(6),
+        cooldown: Duration::from_secs(60),
+    };
+    assert_eq!(Duration::from_millis(100), policy.delay(0));
+    assert_eq!(Duration::from_millis(400), policy.delay(2));
+    assert_eq!(Duration::from_secs(1), policy.delay(5));
+    assert_eq!(Duration::from_secs(60), policy.delay(6));
+}
+
+#[test]
+fn test_reconnect_policy_jitter() {
+    let policy = ReconnectPolicy { jitter: 0.5, max_attempts: None, ..Default::default() };
+    for attempt in 0..20 {
+        let base = policy.base_delay(attempt).as_secs_f64();
+        let delay = policy.delay(attempt).as_secs_f64();
+        assert!(delay >= base * 0.5 - 1e-9 && delay <= base * 1.5 + 1e-9);
+    }
+}
+
+#[test]
+fn test_connection_config_per_venue() {
+    let config = ConnectionConfig::parse(r#"{
+        "default": {"initial_delay_ms": 1000, "max_delay_ms": 60000},
+        "venues": {
+            "coinbase": {"initial_delay_ms": 500, "max_attempts": 0},
+            "bybit": {"ping_interval_ms": 20000, "idle_timeout_ms": 40000}
+        }
+    }"#).unwrap();
+    assert_eq!(ReconnectPolicy { initial_delay: Duration::from_secs(1), max_delay: Duration::from_secs(60), ..ReconnectPolicy::default() }, config.reconnect("kraken"));
+    assert_eq!(ReconnectPolicy { initial_delay: Duration::from_millis(500), max_delay: Duration::from_secs(60), max_attempts: None, ..ReconnectPolicy::default() }, config.reconnect("coinbase"));
+    assert_eq!(Heartbeat::default(), config.heartbeat("coinbase"));
+    assert_eq!(Heartbeat { ping_interval: Duration::from_secs(20), idle_timeout: Duration::from_secs(40) }, config.heartbeat("bybit"));
+    assert_eq!(ConnectionConfig::default().reconnect("okx"), ReconnectPolicy::default());
+    assert!(matches!(ConnectionConfig::parse(r#"{"venues": {"okx": {"ping_interval_ms": 30000}}}"#), Err(ConfigError::Invalid(_))));
+}
+
+#[tokio::test]
+async fn test_idle_timeout_and_ping() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        ws.send(Message::Ping(vec![1])).await.unwrap();
+        let mut seen = Vec::new();
+        while let Some(Ok(msg)) = ws.next().await {
+            seen.push(msg);
+        }
+        seen
+    });
+    let mut client = WebSocketClient::new(format!("ws://{}", addr)).await.unwrap();
+    client.set_heartbeat(Heartbeat {
+        ping_interval: Duration::from_millis(50),
+        idle_timeout: Duration::from_secs(1),
+    }, Some(Message::Text("keepalive".to_string())));
+    assert_eq!(Message::Ping(vec![1]), client.receive().await.unwrap().unwrap());
+    assert_eq!(Message::Pong(vec![]), client.receive().await.unwrap().unwrap());
+    client.set_heartbeat(Heartbeat {
+        ping_interval: Duration::from_secs(10),
+        idle_timeout: Duration::from_millis(200),
+    }, None);
+    match client.receive().await {
+        Some(Err(Error::Io(err))) => assert_eq!(std::io::ErrorKind::TimedOut, err.kind()),
+        other => panic!("Expected idle timeout, got {:?}", other),
+    }
+    drop(client);
+    let seen = server.await.unwrap();
+    assert!(seen.contains(&Message::Pong(vec![1])));
+    assert!(seen.iter().any(|m| matches!(m, Message::Ping(_))));
+    assert!(seen.contains(&Message::Text("keepalive".to_string())));
+}
+
+#[tokio::test]
+async fn test_pong_resets_idle_deadline() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        // Only ever answers our pings.
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        while let Some(Ok(_)) = ws.next().await {}
+    });
+    let mut client = WebSocketClient::new(format!("ws://{}", addr)).await.unwrap();
+    client.set_heartbeat(Heartbeat {
+        ping_interval: Duration::from_millis(50),
+        idle_timeout: Duration::from_millis(150),
+    }, None);
+    let until = Instant::now() + Duration::from_millis(600);
+    while Instant::now() < until {
+        assert_eq!(Message::Pong(vec![]), client.receive().await.unwrap().unwrap());
+    }
+}
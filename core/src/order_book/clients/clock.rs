@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Samples kept per remote clock; the estimate uses the fastest of them.
+const CLOCK_SAMPLES: usize = 8;
+
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64
+}
+
+// One NTP-style exchange with a remote clock, in nanoseconds. `offset` is the remote clock
+// minus ours.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClockSample {
+    pub offset: i64,
+    pub rtt: i64,
+}
+
+impl ClockSample {
+    // `origin` and `returned` are our times for sending the request and getting the reply;
+    // `received` and `sent` are the remote's for the same two events. Venues that only report one
+    // server time pass it as both.
+    pub fn new(origin: i64, received: i64, sent: i64, returned: i64) -> ClockSample {
+        ClockSample {
+            offset: ((received - origin) + (sent - returned)) / 2,
+            rtt: (returned - origin) - (sent - received),
+        }
+    }
+}
+
+// Offset of a remote clock, estimated from recent samples. Queueing only ever adds delay, so the
+// sample with the lowest round trip bounds the offset error most tightly.
+#[derive(Clone, Debug, Default)]
+pub struct ClockEstimate {
+    samples: VecDeque<ClockSample>,
+}
+
+impl ClockEstimate {
+    pub fn add(&mut self, sample: ClockSample) {
+        if sample.rtt < 0 {
+            return;
+        }
+        self.samples.push_back(sample);
+        if self.samples.len() > CLOCK_SAMPLES {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn best(&self) -> Option<ClockSample> {
+        self.samples.iter().min_by_key(|sample| sample.rtt).copied()
+    }
+
+    // Zero until the first sample, leaving latency uncorrected.
+    pub fn offset(&self) -> i64 {
+        self.best().map_or(0, |sample| sample.offset)
+    }
+}
+
+#[test]
+fn test_clock_sample() {
+    // Remote clock 5ms ahead, 1ms each way, 0.5ms to answer.
+    let sample = ClockSample::new(1_000_000_000, 1_006_000_000, 1_006_500_000, 1_002_500_000);
+    assert_eq!(ClockSample { offset: 5_000_000, rtt: 2_000_000 }, sample);
+    // A single server time halfway through the round trip.
+    assert_eq!(ClockSample { offset: -3_000_000, rtt: 4_000_000 }, ClockSample::new(10_000_000, 9_000_000, 9_000_000, 14_000_000));
+}
+
+#[test]
+fn test_clock_estimate_prefers_fastest_sample() {
+    let mut clock = ClockEstimate::default();
+    assert_eq!(0, clock.offset());
+    clock.add(ClockSample { offset: 900, rtt: 5000 });
+    clock.add(ClockSample { offset: 1000, rtt: 200 });
+    clock.add(ClockSample { offset: 1200, rtt: 800 });
+    clock.add(ClockSample { offset: 0, rtt: -1 });
+    assert_eq!(1000, clock.offset());
+    for _ in 0..CLOCK_SAMPLES {
+        clock.add(ClockSample { offset: 1500, rtt: 900 });
+    }
+    assert_eq!(1500, clock.offset());
+}
@@ -1,135 +1,168 @@
-use std::{time::Duration, sync::Arc, str::FromStr};
+use std::str::FromStr;
+use std::sync::Arc;
 
 use chrono::Utc;
-use tokio::{time::Instant, sync::Mutex};
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
 
-use crate::order_book::{clients::client::WebSocketClient, multi_book::MultiBook};
+use crate::order_book::{self, data_types::PriceLevel};
+use crate::order_book::clients::feed::{DecodeError, ExchangeFeed, FeedEvent};
+use crate::order_book::instruments::InstrumentRegistry;
 
-use super::{coinbase_adapter::CoinbaseAdapter, data_types::{Snapshot, Message, Change, Match}, data_types::Update};
+use super::data_types::{Snapshot, Message, Match, ServerTime, Side, Update};
 
-pub struct CoinbaseReceiveClient {
-    adapter: CoinbaseAdapter,
-    client: WebSocketClient,
-    pair: heapless::String<8>,
+// One connection carries every configured Coinbase product; messages are routed by `product_id`.
+pub struct CoinbaseFeed {
+    symbols: Vec<(String, heapless::String<8>)>,
+    book_channel: String,
+    trade_channel: String,
 }
 
-impl<'a> CoinbaseReceiveClient {
-    pub async fn new(book: Arc<Mutex<MultiBook<3, 6>>>, pair: heapless::String<8>) -> CoinbaseReceiveClient {
-        return CoinbaseReceiveClient {
-            adapter: CoinbaseAdapter::new(book).await,
-            client: WebSocketClient::new("wss://ws-feed.exchange.coinbase.com".to_string()).await,
-            pair: pair,
+impl CoinbaseFeed {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> CoinbaseFeed {
+        let listings = registry.venue_listings("coinbase");
+        let channel = |name: &str, default: &str| listings.first()
+            .and_then(|(_, listing)| listing.channel(name))
+            .unwrap_or(default)
+            .to_string();
+        let book_channel = channel("book", "level2");
+        let trade_channel = channel("trades", "matches");
+        CoinbaseFeed {
+            symbols: listings.iter().map(|(pair, listing)| (listing.symbol.clone(), pair.clone())).collect(),
+            book_channel,
+            trade_channel,
         }
     }
 
-    pub async fn init(&mut self) {
-        let p = match self.pair.as_str() {
-            "ETH-USD" => "ETH-USD",
-            "BTC-USD" => "BTC-USD",
-            "ETH-USDT" => "ETH-USDT",
-            "BTC-USDT" => "BTC-USDT",
-            _ => panic!("Bad pair: {:?}", self.pair),
-        };
-        let sub_message: String = format!("{{\"type\":\"subscribe\",\"product_ids\":[{:?}],\"channels\":[\"level2\",\"matches\"]}}", p).to_string();
-        self.client.send(tokio_tungstenite::tungstenite::protocol::Message::Text(sub_message)).await;
-        self.receive().await;
+    fn pair(&self, product_id: Option<&str>) -> Option<heapless::String<8>> {
+        let product_id = product_id?;
+        self.symbols.iter().find(|(symbol, _)| symbol == product_id).map(|(_, pair)| pair.clone())
     }
 
-    async fn receive(&mut self) {
-        let mut count: usize = 0;
-        let mut total: usize = 0;
-        while let Some(msg) = self.client.receive().await {
-            let start = Instant::now();
-            match msg {
-                Ok(msg) => {
-                    match serde_json_core::from_str::<Message>(&msg.to_text().unwrap()) {
-                        Ok((message, _)) => {
-                            match message.msg_type {
-                                "subscriptions" => {
-                                    ()
-                                },
-                                "snapshot" => {
-                                    self.handle_snapshot(&msg.to_text().unwrap()).await
-                                },
-                                "l2update" => {
-                                    self.handle_update(&msg.to_text().unwrap(), start).await;
-                                    let duration = start.elapsed();
-                                    //println!("Coinbase: message handled in {:?}", duration);
-                                    //println!("Coinbase: average message handle time for {:?} messages: {:?}", count, Duration::new(0, avg as u32));
-                                },
-                                "match" => {
-                                    self.handle_match(&msg.to_text().unwrap()).await;
-                                    let now = Utc::now();
-                                    let sent = chrono::DateTime::<Utc>::from_str(message.time).unwrap();
-                                    let elapsed = (now - sent).to_std();
-                                    match elapsed {
-                                        Ok(e) => {
-                                            count = count + 1;
-                                            total = total + e.as_nanos() as usize;
-                                            let avg: f64 = (total as f64) / (count as f64);
-                                            if count % 1000 == 1 {
-                                                println!("Coinbase avg. sent to handled time: {:?}", Duration::new(0, avg as u32));
-                                            }
-                                        },
-                                        Err(_) => (),
-                                    }
-                                },
-                                "last_match" => (),
-                                other => println!("Unknown message type {:?}: {:?}", other, msg),
-                            }
-                        },
-                        Err(e) => {
-                            println!("Coinbase parsing error for {:?}: {:?}", msg, e);
-                        },
-                    }
-                },
-                Err(err) => {
-                    println!("Coinbase: {:?}\nAttempting reset.", err);
-                    return;
-                }
-            }
+    fn side(side: Side) -> order_book::data_types::Side {
+        match side {
+            Side::Buy => order_book::data_types::Side::Buy,
+            Side::Sell => order_book::data_types::Side::Sell,
         }
     }
 
-    async fn handle_snapshot(&mut self, snapshot: &str) {
-        let result = serde_json_core::from_str::<Snapshot>(snapshot);
-        match result {
-            Ok((snapshot, _)) => {
-                self.adapter.init_order_book(snapshot).await
-            },
-            Err(err) => println!("Error parsing: {:?} for {:?}", err, snapshot),
-        }        
+    fn snapshot(pair: heapless::String<8>, snapshot: Snapshot, events: &mut Vec<FeedEvent>) {
+        let mut initial_book = order_book::data_types::Snapshot::new();
+        for bid in snapshot.bids.iter() {
+            let _ = initial_book.bids.push(PriceLevel {level: bid.level, amount: bid.amount, sequence: 0});
+        }
+        for ask in snapshot.asks.iter() {
+            let _ = initial_book.asks.push(PriceLevel {level: ask.level, amount: ask.amount, sequence: 0});
+        }
+        events.push(FeedEvent::Snapshot { pair, snapshot: initial_book });
+    }
+
+    fn update(pair: heapless::String<8>, update: Update, events: &mut Vec<FeedEvent>) {
+        let mut changes = heapless::Vec::new();
+        for change in update.changes {
+            let _ = changes.push(order_book::data_types::Change {
+                side: CoinbaseFeed::side(change.side),
+                price_level: PriceLevel {
+                    level: change.price_level.level,
+                    amount: change.price_level.amount,
+                    sequence: 0
+                }});
+        }
+        events.push(FeedEvent::Delta { pair, changes });
+    }
+
+    fn match_(pair: heapless::String<8>, match_: Match, time: Option<&str>, events: &mut Vec<FeedEvent>) {
+        let trade = order_book::data_types::Match {side: CoinbaseFeed::side(match_.side), size: match_.size, price: match_.price};
+        let time = time.and_then(|time| chrono::DateTime::<Utc>::from_str(time).ok())
+            .map(|t| t.timestamp() * 1_000_000_000 + t.timestamp_subsec_nanos() as i64);
+        events.push(FeedEvent::Trade { pair, trade, time });
+    }
+}
+
+impl ExchangeFeed for CoinbaseFeed {
+    fn name(&self) -> &'static str {
+        "coinbase"
+    }
+
+    fn url(&self) -> String {
+        "wss://ws-feed.exchange.coinbase.com".to_string()
+    }
+
+    fn time_url(&self) -> Option<String> {
+        Some("https://api.exchange.coinbase.com/time".to_string())
     }
 
-    async fn handle_update(&mut self, update: &str, start: Instant) -> Duration {
-        let result = serde_json_core::from_str::<Update>(update);
-        match result {
-            Ok((update, _)) => {
-                self.adapter.update(update).await
-            }
-            Err(err) => println!("Error parsing: {:?} for {:?}", err, update),
+    fn decode_time(&self, body: &str) -> Result<i64, DecodeError> {
+        let (time, _) = serde_json_core::from_str::<ServerTime>(body)?;
+        let time = chrono::DateTime::<Utc>::from_str(time.iso).map_err(|e| DecodeError::Unexpected(e.to_string()))?;
+        Ok(time.timestamp() * 1_000_000_000 + time.timestamp_subsec_nanos() as i64)
+    }
+
+    fn subscriptions(&self) -> Vec<WsMessage> {
+        if self.symbols.is_empty() {
+            println!("Coinbase: no listings configured");
+            return Vec::new();
         }
-        start.elapsed()
+        let product_ids: Vec<String> = self.symbols.iter().map(|(symbol, _)| format!("{:?}", symbol)).collect();
+        let sub_message: String = format!(
+            "{{\"type\":\"subscribe\",\"product_ids\":[{}],\"channels\":[{:?},{:?},\"heartbeat\"]}}",
+            product_ids.join(","),
+            self.book_channel,
+            self.trade_channel,
+        ).to_string();
+        vec![WsMessage::Text(sub_message)]
     }
-    async fn handle_match(&mut self, msg: &str) {
-        let result = serde_json_core::from_str::<Match>(msg);
-        match result {
-            Ok((update, _)) => {
-                self.adapter.match_(update).await
+
+    fn decode(&mut self, frame: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let (message, _) = serde_json_core::from_str::<Message>(frame)?;
+        let pair = match message.msg_type {
+            "subscriptions" | "last_match" | "heartbeat" => return Ok(()),
+            _ => match self.pair(message.product_id) {
+                Some(pair) => pair,
+                None => return Ok(()),
             },
-            Err(err) => println!("Error parsing: {:?} for {:?}", err, msg),
+        };
+        match message.msg_type {
+            "snapshot" => {
+                let (snapshot, _) = serde_json_core::from_str::<Snapshot>(frame)?;
+                CoinbaseFeed::snapshot(pair, snapshot, events);
+            },
+            "l2update" => {
+                let (update, _) = serde_json_core::from_str::<Update>(frame)?;
+                CoinbaseFeed::update(pair, update, events);
+            },
+            "match" => {
+                let (match_, _) = serde_json_core::from_str::<Match>(frame)?;
+                CoinbaseFeed::match_(pair, match_, message.time, events);
+            },
+            other => println!("Unknown message type {:?}: {:?}", other, frame),
         }
+        Ok(())
     }
 }
 
-pub struct CoinbaseSendClient {
-    client: WebSocketClient,
+#[test]
+fn test_decode_routes_products() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = CoinbaseFeed::new(Arc::new(registry));
+    let subscription = match &feed.subscriptions()[0] {
+        WsMessage::Text(text) => text.clone(),
+        other => panic!("Expected text subscription, got {:?}", other),
+    };
+    assert!(subscription.contains("\"product_ids\":[\"ETH-USD\",\"BTC-USD\"]"));
+    let mut events = Vec::new();
+    feed.decode(r#"{"type":"snapshot","product_id":"BTC-USD","bids":[["30000.00","1.0"]],"asks":[["30001.00","2.0"]]}"#, &mut events).unwrap();
+    feed.decode(r#"{"type":"l2update","product_id":"ETH-USD","changes":[["sell","1897.20","0.5"]],"time":"2023-07-10T22:08:08.932974Z"}"#, &mut events).unwrap();
+    feed.decode(r#"{"type":"match","product_id":"BTC-USD","side":"buy","size":"0.1","price":"30000.00","time":"2023-07-10T22:08:09.000000Z"}"#, &mut events).unwrap();
+    feed.decode(r#"{"type":"heartbeat","product_id":"BTC-USD","sequence":1,"time":"2023-07-10T22:08:09.000000Z"}"#, &mut events).unwrap();
+    assert_eq!(3, events.len());
+    assert_eq!(["BTC-USD", "ETH-USD", "BTC-USD"], [events[0].pair(), events[1].pair(), events[2].pair()]);
+    assert!(matches!(&events[2], FeedEvent::Trade { time: Some(_), .. }));
 }
 
-impl CoinbaseSendClient {
-    pub async fn new() -> Self {
-        return CoinbaseSendClient {
-            client: WebSocketClient::new("wss://ws-feed.exchange.coinbase.com".to_string()).await
-        }
-    }
-}
\ No newline at end of file
+#[test]
+fn test_decode_server_time() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let feed = CoinbaseFeed::new(Arc::new(registry));
+    let time = feed.decode_time(r#"{"iso":"2015-01-07T23:47:25.201Z","epoch":1420674445.201}"#).unwrap();
+    assert_eq!(1420674445201000000, time);
+}
@@ -0,0 +1,245 @@
+use std::sync::Arc;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode};
+use sha2::Sha256;
+
+use crate::order_book::clients::clock;
+use crate::order_book::clients::orders::{OrderClient, OrderError, OrderEvent, OrderRequest, TimeInForce};
+use crate::order_book::data_types;
+use crate::order_book::instruments::InstrumentRegistry;
+
+use super::data_types::{ErrorMessage, Order, Side};
+
+pub struct CoinbaseCredentials {
+    pub api_key: String,
+    pub passphrase: String,
+    // Base64, as Coinbase issues it.
+    pub secret: String,
+}
+
+// Order entry over the Coinbase Exchange REST API. Every request is signed with an HMAC-SHA256
+// of timestamp, method, path and body under the decoded secret. Coinbase has no amend, so a
+// replace is a cancel followed by a new order. Client ids must be UUIDs.
+pub struct CoinbaseSendClient {
+    http: reqwest::Client,
+    rest_url: String,
+    credentials: CoinbaseCredentials,
+    registry: Arc<InstrumentRegistry>,
+}
+
+impl CoinbaseSendClient {
+    pub fn new(credentials: CoinbaseCredentials, registry: Arc<InstrumentRegistry>) -> CoinbaseSendClient {
+        CoinbaseSendClient {
+            http: reqwest::Client::new(),
+            rest_url: "https://api.exchange.coinbase.com".to_string(),
+            credentials,
+            registry,
+        }
+    }
+
+    pub fn set_endpoint(&mut self, rest_url: String) {
+        self.rest_url = rest_url;
+    }
+
+    fn sign(&self, timestamp: &str, method: &str, path: &str, body: &str) -> String {
+        let key = STANDARD.decode(&self.credentials.secret).unwrap_or_else(|_| self.credentials.secret.as_bytes().to_vec());
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC accepts any key length");
+        mac.update(format!("{}{}{}{}", timestamp, method, path, body).as_bytes());
+        STANDARD.encode(mac.finalize().into_bytes())
+    }
+
+    async fn request(&self, method: Method, path: &str, body: String) -> Result<(StatusCode, String), OrderError> {
+        let timestamp = (clock::now() / 1_000_000_000).to_string();
+        let signature = self.sign(&timestamp, method.as_str(), path, &body);
+        let response = self.http.request(method, format!("{}{}", self.rest_url, path))
+            .header("CB-ACCESS-KEY", &self.credentials.api_key)
+            .header("CB-ACCESS-SIGN", signature)
+            .header("CB-ACCESS-TIMESTAMP", timestamp)
+            .header("CB-ACCESS-PASSPHRASE", &self.credentials.passphrase)
+            .header("Content-Type", "application/json")
+            .header("User-Agent", "prism")
+            .body(body)
+            .send()
+            .await?;
+        let status = response.status();
+        Ok((status, response.text().await?))
+    }
+
+    // The venue's reason for refusing a request, or an error if it didn't give one.
+    fn refusal(status: StatusCode, body: &str) -> Result<String, OrderError> {
+        if !status.is_client_error() {
+            return Err(OrderError::Unexpected(format!("{}: {}", status, body)));
+        }
+        match serde_json::from_str::<ErrorMessage>(body) {
+            Ok(error) => Ok(error.message),
+            Err(_) => Err(OrderError::Unexpected(format!("{}: {}", status, body))),
+        }
+    }
+
+    fn order_events(&self, client_id: String, order: Order) -> Result<Vec<OrderEvent>, OrderError> {
+        if order.status == "rejected" {
+            return Ok(vec![OrderEvent::Rejected { client_id, reason: order.reject_reason.unwrap_or(order.status) }]);
+        }
+        let mut events = vec![OrderEvent::Accepted { client_id, order_id: order.id.clone() }];
+        let amount = |value: &Option<String>| value.as_deref().and_then(|v| v.parse::<f64>().ok()).unwrap_or(0.);
+        let filled = amount(&order.filled_size);
+        if filled > 0. {
+            let pair = self.registry.venue_listings("coinbase").into_iter()
+                .find(|(_, listing)| listing.symbol == order.product_id)
+                .map(|(pair, _)| pair)
+                .ok_or_else(|| OrderError::Pair(order.product_id.clone()))?;
+            events.push(OrderEvent::Fill {
+                order_id: order.id.clone(),
+                pair,
+                side: match order.side {
+                    Side::Buy => data_types::Side::Buy,
+                    Side::Sell => data_types::Side::Sell,
+                },
+                price: data_types::to_level(amount(&order.executed_value) / filled),
+                size: filled,
+                fee: amount(&order.fill_fees),
+            });
+        }
+        if order.status == "done" && order.done_reason.as_deref() == Some("canceled") {
+            events.push(OrderEvent::Canceled { order_id: order.id });
+        }
+        Ok(events)
+    }
+}
+
+impl OrderClient for CoinbaseSendClient {
+    async fn place(&mut self, order: OrderRequest) -> Result<Vec<OrderEvent>, OrderError> {
+        let listing = self.registry.listing("coinbase", &order.pair).ok_or_else(|| OrderError::Pair(order.pair.to_string()))?;
+        let body = serde_json::json!({
+            "client_oid": order.client_id,
+            "product_id": listing.symbol,
+            "side": order.side,
+            "type": "limit",
+            "price": listing.format_price(order.price),
+            "size": listing.format_qty(order.size),
+            "time_in_force": match order.time_in_force {
+                TimeInForce::Gtc => "GTC",
+                TimeInForce::Ioc => "IOC",
+            },
+        });
+        let (status, body) = self.request(Method::POST, "/orders", body.to_string()).await?;
+        if !status.is_success() {
+            let reason = CoinbaseSendClient::refusal(status, &body)?;
+            return Ok(vec![OrderEvent::Rejected { client_id: order.client_id, reason }]);
+        }
+        let placed = serde_json::from_str::<Order>(&body).map_err(|_| OrderError::Unexpected(body))?;
+        self.order_events(order.client_id, placed)
+    }
+
+    async fn cancel(&mut self, order_id: &str) -> Result<Vec<OrderEvent>, OrderError> {
+        let (status, body) = self.request(Method::DELETE, &format!("/orders/{}", order_id), String::new()).await?;
+        if !status.is_success() {
+            let reason = CoinbaseSendClient::refusal(status, &body)?;
+            return Ok(vec![OrderEvent::CancelRejected { order_id: order_id.to_string(), reason }]);
+        }
+        Ok(vec![OrderEvent::Canceled { order_id: order_id.to_string() }])
+    }
+
+    async fn replace(&mut self, order_id: &str, order: OrderRequest) -> Result<Vec<OrderEvent>, OrderError> {
+        let mut events = self.cancel(order_id).await?;
+        if matches!(events[..], [OrderEvent::Canceled { .. }]) {
+            events.extend(self.place(order).await?);
+        }
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+const TEST_SECRET: &str = "c2VjcmV0LWtleQ==";
+
+// Answers like Coinbase once the request's signature checks out.
+#[cfg(test)]
+fn coinbase_mock(request: &crate::order_book::clients::test_support::HttpRequest) -> (u16, String) {
+    let prehash = format!("{}{}{}{}", request.header("cb-access-timestamp").unwrap_or(""), request.method, request.path, request.body);
+    let mut mac = Hmac::<Sha256>::new_from_slice(&STANDARD.decode(TEST_SECRET).unwrap()).unwrap();
+    mac.update(prehash.as_bytes());
+    let signature = request.header("cb-access-sign").and_then(|s| STANDARD.decode(s).ok()).unwrap_or_default();
+    if mac.verify_slice(&signature).is_err() || request.header("cb-access-passphrase") != Some("phrase") {
+        return (401, r#"{"message":"invalid signature"}"#.to_string());
+    }
+    let body: serde_json::Value = serde_json::from_str(&request.body).unwrap_or_default();
+    match (request.method.as_str(), request.path.as_str()) {
+        ("POST", "/orders") if body["size"] == "100.00000000" => (400, r#"{"message":"Insufficient funds"}"#.to_string()),
+        ("POST", "/orders") if body["time_in_force"] == "IOC" => (200, r#"{"id":"o-2","price":"30000.00","size":"1","product_id":"BTC-USD","side":"buy","type":"limit","time_in_force":"IOC","status":"done","done_reason":"canceled","filled_size":"0.25","executed_value":"7500.00","fill_fees":"3.75"}"#.to_string()),
+        ("POST", "/orders") => (200, r#"{"id":"o-1","price":"30000.00","size":"1","product_id":"BTC-USD","side":"buy","type":"limit","post_only":false,"status":"pending","settled":false,"filled_size":"0","executed_value":"0","fill_fees":"0"}"#.to_string()),
+        ("DELETE", "/orders/o-1") => (200, r#""o-1""#.to_string()),
+        ("DELETE", _) => (404, r#"{"message":"order not found"}"#.to_string()),
+        _ => (404, r#"{"message":"NotFound"}"#.to_string()),
+    }
+}
+
+#[cfg(test)]
+async fn coinbase_client(secret: &str) -> (CoinbaseSendClient, std::sync::Arc<std::sync::Mutex<Vec<crate::order_book::clients::test_support::HttpRequest>>>) {
+    let (addr, requests) = crate::order_book::clients::test_support::serve_rest(coinbase_mock).await;
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let credentials = CoinbaseCredentials { api_key: "key".to_string(), passphrase: "phrase".to_string(), secret: secret.to_string() };
+    let mut client = CoinbaseSendClient::new(credentials, Arc::new(registry));
+    client.set_endpoint(format!("http://{}", addr));
+    (client, requests)
+}
+
+#[cfg(test)]
+fn order(client_id: &str, size: f64, time_in_force: TimeInForce) -> OrderRequest {
+    OrderRequest {
+        client_id: client_id.to_string(),
+        pair: heapless::String::from("BTC-USD"),
+        side: data_types::Side::Buy,
+        price: 3000000,
+        size,
+        time_in_force,
+    }
+}
+
+#[tokio::test]
+async fn test_place_signed_orders() {
+    let (mut client, requests) = coinbase_client(TEST_SECRET).await;
+    let events = client.place(order("c-1", 1.0, TimeInForce::Gtc)).await.unwrap();
+    assert_eq!(vec![OrderEvent::Accepted { client_id: "c-1".to_string(), order_id: "o-1".to_string() }], events);
+    let body: serde_json::Value = serde_json::from_str(&requests.lock().unwrap()[0].body).unwrap();
+    assert_eq!(serde_json::json!({"client_oid": "c-1", "product_id": "BTC-USD", "side": "buy", "type": "limit", "price": "30000.00", "size": "1.00000000", "time_in_force": "GTC"}), body);
+
+    // An IOC that partly filled and had its remainder canceled.
+    let events = client.place(order("c-2", 1.0, TimeInForce::Ioc)).await.unwrap();
+    assert_eq!(vec![
+        OrderEvent::Accepted { client_id: "c-2".to_string(), order_id: "o-2".to_string() },
+        OrderEvent::Fill { order_id: "o-2".to_string(), pair: heapless::String::from("BTC-USD"), side: data_types::Side::Buy, price: 3000000, size: 0.25, fee: 3.75 },
+        OrderEvent::Canceled { order_id: "o-2".to_string() },
+    ], events);
+
+    let events = client.place(order("c-3", 100.0, TimeInForce::Gtc)).await.unwrap();
+    assert_eq!(vec![OrderEvent::Rejected { client_id: "c-3".to_string(), reason: "Insufficient funds".to_string() }], events);
+    let mut unlisted = order("c-4", 1.0, TimeInForce::Gtc);
+    unlisted.pair = heapless::String::from("DOGE-USD");
+    assert!(matches!(client.place(unlisted).await, Err(OrderError::Pair(_))));
+}
+
+#[tokio::test]
+async fn test_cancel_and_replace() {
+    let (mut client, requests) = coinbase_client(TEST_SECRET).await;
+    assert_eq!(vec![OrderEvent::CancelRejected { order_id: "o-9".to_string(), reason: "order not found".to_string() }], client.cancel("o-9").await.unwrap());
+    // The new order only goes out once the old one is gone.
+    let events = client.replace("o-9", order("c-5", 1.0, TimeInForce::Gtc)).await.unwrap();
+    assert_eq!(1, events.len());
+    let events = client.replace("o-1", order("c-5", 1.0, TimeInForce::Gtc)).await.unwrap();
+    assert_eq!(vec![
+        OrderEvent::Canceled { order_id: "o-1".to_string() },
+        OrderEvent::Accepted { client_id: "c-5".to_string(), order_id: "o-1".to_string() },
+    ], events);
+    let paths: Vec<String> = requests.lock().unwrap().iter().map(|r| format!("{} {}", r.method, r.path)).collect();
+    assert_eq!(vec!["DELETE /orders/o-9", "DELETE /orders/o-9", "DELETE /orders/o-1", "POST /orders"], paths);
+}
+
+#[tokio::test]
+async fn test_bad_signature_is_not_an_acknowledgement() {
+    let (mut client, _) = coinbase_client("d3Jvbmc=").await;
+    let events = client.place(order("c-1", 1.0, TimeInForce::Gtc)).await.unwrap();
+    assert_eq!(vec![OrderEvent::Rejected { client_id: "c-1".to_string(), reason: "invalid signature".to_string() }], events);
+}
@@ -1,16 +1,23 @@
 use serde::{Deserialize, Deserializer, de::{Visitor, SeqAccess}};
 
+use crate::order_book::data_types::to_level;
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct Message<'a> {
     #[serde(rename = "type")]
     pub msg_type: &'a str,
-    pub time: &'a str,
+    pub product_id: Option<&'a str>,
+    pub time: Option<&'a str>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct ServerTime<'a> {
+    pub iso: &'a str,
 }
 
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct Snapshot {
-    pub bids: Box<heapless::Vec<PriceLevel, 65536>>,
-    pub asks: Box<heapless::Vec<PriceLevel, 65536>>,
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
 }
 
 #[derive(Deserialize, Debug, PartialEq)]
@@ -37,6 +44,25 @@ pub enum Side {
     Sell,
 }
 
+// An order as the REST API returns it from placement.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Order {
+    pub id: String,
+    pub product_id: String,
+    pub side: Side,
+    pub status: String,
+    pub done_reason: Option<String>,
+    pub reject_reason: Option<String>,
+    pub filled_size: Option<String>,
+    pub executed_value: Option<String>,
+    pub fill_fees: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct ErrorMessage {
+    pub message: String,
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct Match {
     pub side: Side,
@@ -49,13 +75,13 @@ pub struct Match {
 pub fn match_price<'de, D>(deserializer: D) -> Result<usize, D::Error>
 where D: Deserializer<'de> {
     let input = heapless::String::<16>::deserialize(deserializer).unwrap();
-    return Ok((input.parse::<f64>().unwrap() * 100.0) as usize)
+    Ok(to_level(input.parse::<f64>().unwrap()))
 }
 
 pub fn match_amount<'de, D>(deserializer: D) -> Result<f64, D::Error>
 where D: Deserializer<'de> {
     let input = heapless::String::<16>::deserialize(deserializer).unwrap();
-    return Ok(input.parse::<f64>().unwrap())
+    Ok(input.parse::<f64>().unwrap())
 }
 
 impl<'de> Deserialize<'de> for PriceLevel {
@@ -80,11 +106,11 @@ impl<'de> Visitor<'de> for PriceLevelVisitor {
     where
         A: SeqAccess<'de>,
     {
-        let level = (seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap() * 100.) as usize;
+        let level = to_level(seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap());
         let amount = seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap();
         Ok(PriceLevel {
-            level: level,
-            amount: amount,
+            level,
+            amount,
             sequence: 0,
             })
     }
@@ -113,13 +139,13 @@ impl<'de> Visitor<'de> for ChangeVisitor {
         A: SeqAccess<'de>,
     {
         let side = seq.next_element().unwrap().unwrap();
-        let level = (seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap() * 100.) as usize;
+        let level = to_level(seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap());
         let amount = seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap();
         Ok(Change {
-            side: side,
+            side,
             price_level: PriceLevel {
-                level: level,
-                amount: amount,
+                level,
+                amount,
                 sequence: 0,
             },
         })
@@ -128,7 +154,12 @@ impl<'de> Visitor<'de> for ChangeVisitor {
 
 #[test]
 fn test_message() {
-
+    let input = r#"{"type":"l2update","product_id":"BTC-USD","changes":[["buy","22356.27","0.00000000"]],"time":"2022-08-04T15:25:05.010758Z"}"#;
+    let result = serde_json_core::from_str::<Message>(input);
+    assert!(result.is_ok());
+    assert_eq!(Some("BTC-USD"), result.unwrap().0.product_id);
+    let input = r#"{"type":"subscriptions","channels":[{"name":"level2","product_ids":["BTC-USD"]}]}"#;
+    assert!(serde_json_core::from_str::<Message>(input).is_ok());
 }
 
 #[test]
@@ -1,3 +1,3 @@
-pub mod coinbase_adapter;
 pub mod coinbase_client;
-pub mod data_types;
\ No newline at end of file
+pub mod coinbase_send_client;
+pub mod data_types;
@@ -0,0 +1,418 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures_util::StreamExt;
+use futures_util::stream::FuturesUnordered;
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::Error;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::order_book::data_types::{Change, Match, PriceLevel, Side, Snapshot, Update};
+use crate::order_book::multi_book::MultiBook;
+
+use super::client::{Heartbeat, ReconnectPolicy, WebSocketClient};
+use super::clock::{self, ClockEstimate, ClockSample};
+
+const TIME_SYNC_INTERVAL: Duration = Duration::from_secs(60);
+
+// Deltas are kept inline so decoding a message doesn't allocate.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, PartialEq)]
+pub enum FeedEvent {
+    Snapshot {
+        pair: heapless::String<8>,
+        snapshot: Snapshot,
+    },
+    Delta {
+        pair: heapless::String<8>,
+        changes: heapless::Vec<Change, 512>,
+    },
+    // Feeds that only publish the best bid and ask; the previous best levels are dropped when
+    // the top of book moves.
+    TopOfBook {
+        pair: heapless::String<8>,
+        bid: PriceLevel,
+        ask: PriceLevel,
+    },
+    Trade {
+        pair: heapless::String<8>,
+        trade: Match,
+        time: Option<i64>,
+    },
+}
+
+impl FeedEvent {
+    pub fn pair(&self) -> &str {
+        match self {
+            FeedEvent::Snapshot { pair, .. }
+            | FeedEvent::Delta { pair, .. }
+            | FeedEvent::TopOfBook { pair, .. }
+            | FeedEvent::Trade { pair, .. } => pair,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Json(serde_json_core::de::Error),
+    Value(serde_json::Error),
+    Unexpected(String),
+    // The local book no longer matches the venue's (failed checksum, sequence gap); the only
+    // way back is a fresh snapshot.
+    OutOfSync(String),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Json(err) => write!(f, "{}", err),
+            DecodeError::Value(err) => write!(f, "{}", err),
+            DecodeError::Unexpected(msg) => write!(f, "{}", msg),
+            DecodeError::OutOfSync(msg) => write!(f, "out of sync: {}", msg),
+        }
+    }
+}
+
+impl From<serde_json_core::de::Error> for DecodeError {
+    fn from(err: serde_json_core::de::Error) -> Self {
+        DecodeError::Json(err)
+    }
+}
+
+impl From<serde_json::Error> for DecodeError {
+    fn from(err: serde_json::Error) -> Self {
+        DecodeError::Value(err)
+    }
+}
+
+pub trait ExchangeFeed {
+    fn name(&self) -> &'static str;
+
+    fn url(&self) -> String;
+
+    fn subscriptions(&self) -> Vec<Message>;
+
+    fn decode(&mut self, frame: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError>;
+
+    // Application-level heartbeat the venue expects from us alongside WebSocket pings.
+    fn keepalive(&self) -> Option<Message> {
+        None
+    }
+
+    // Called before every new connection so per-connection state such as "snapshot seen" starts over.
+    fn reset(&mut self) {}
+
+    // For venues whose stream only carries deltas. Polled after every frame until it returns None;
+    // each URL is a REST snapshot to fetch, and its body is handed back to `decode_snapshot` along
+    // with the URL. Frames keep being decoded while requests are in flight.
+    fn snapshot_request(&mut self) -> Option<String> {
+        None
+    }
+
+    fn decode_snapshot(&mut self, _url: &str, _body: &str, _events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        Ok(())
+    }
+
+    // REST endpoint reporting the venue's clock, polled to correct event times for clock skew.
+    fn time_url(&self) -> Option<String> {
+        None
+    }
+
+    // The server time in `time_url`'s response, as nanoseconds since the epoch.
+    fn decode_time(&self, body: &str) -> Result<i64, DecodeError> {
+        Err(DecodeError::Unexpected(body.to_string()))
+    }
+}
+
+pub trait BookAdapter {
+    fn apply(&mut self, venue: &str, event: FeedEvent) -> impl Future<Output = ()> + Send;
+
+    fn set_stale(&mut self, venue: &str, stale: bool) -> impl Future<Output = ()> + Send;
+}
+
+pub struct MultiBookAdapter<const S: usize, const T: usize> {
+    books: Vec<(heapless::String<8>, Arc<Mutex<MultiBook<S, T>>>)>,
+}
+
+impl<const S: usize, const T: usize> MultiBookAdapter<S, T> {
+    pub async fn new(books: Vec<Arc<Mutex<MultiBook<S, T>>>>) -> Self {
+        let mut pairs = Vec::new();
+        for book in books {
+            let pair = book.lock().await.pair.clone();
+            pairs.push((pair, book));
+        }
+        MultiBookAdapter { books: pairs }
+    }
+
+    fn book(&self, pair: &str) -> Option<&Arc<Mutex<MultiBook<S, T>>>> {
+        self.books.iter().find(|(p, _)| p.as_str() == pair).map(|(_, book)| book)
+    }
+}
+
+impl<const S: usize, const T: usize> BookAdapter for MultiBookAdapter<S, T> {
+    async fn apply(&mut self, venue: &str, event: FeedEvent) {
+        let lock = match self.book(event.pair()) {
+            Some(lock) => lock,
+            None => return,
+        };
+        let mut guard = lock.lock().await;
+        let book_idx = match guard.book_idx(venue) {
+            Some(idx) => idx,
+            None => return,
+        };
+        if guard.books[book_idx].stale {
+            match event {
+                FeedEvent::Snapshot { .. } | FeedEvent::TopOfBook { .. } => guard.set_stale(book_idx, false),
+                _ => (),
+            }
+        }
+        match event {
+            FeedEvent::Snapshot { snapshot, .. } => {
+                guard.books[book_idx].init(snapshot);
+                guard.update_spread(book_idx);
+            },
+            FeedEvent::Delta { changes, .. } => {
+                guard.books[book_idx].update(Update { product_id: "", time: "", changes });
+                guard.update_spread(book_idx);
+            },
+            FeedEvent::TopOfBook { bid, ask, .. } => {
+                let book = &mut guard.books[book_idx];
+                let mut changes = heapless::Vec::<Change, 512>::new();
+                if let Some(curr_bid) = book.best_bid {
+                    if curr_bid != bid.level {
+                        let _ = changes.push(Change { side: Side::Buy, price_level: PriceLevel { level: curr_bid, amount: 0.0, sequence: 0 } });
+                    }
+                }
+                if let Some(curr_ask) = book.best_ask {
+                    if curr_ask != ask.level {
+                        let _ = changes.push(Change { side: Side::Sell, price_level: PriceLevel { level: curr_ask, amount: 0.0, sequence: 0 } });
+                    }
+                }
+                let _ = changes.push(Change { side: Side::Buy, price_level: bid });
+                let _ = changes.push(Change { side: Side::Sell, price_level: ask });
+                book.update(Update { product_id: "", time: "", changes });
+                guard.update_spread(book_idx);
+            },
+            FeedEvent::Trade { trade, .. } => {
+                let book = &mut guard.books[book_idx];
+                if book.best_bid.is_some() && book.best_ask.is_some() {
+                    book.update_impulse(trade);
+                    guard.update_spread(book_idx);
+                }
+            },
+        }
+    }
+
+    async fn set_stale(&mut self, venue: &str, stale: bool) {
+        for (_, lock) in self.books.iter() {
+            let mut guard = lock.lock().await;
+            if let Some(book_idx) = guard.book_idx(venue) {
+                guard.set_stale(book_idx, stale);
+            }
+        }
+    }
+}
+
+pub struct LatencyStats {
+    name: &'static str,
+    count: usize,
+    total: u128,
+    offset: i64,
+}
+
+impl LatencyStats {
+    pub fn new(name: &'static str) -> Self {
+        LatencyStats { name, count: 0, total: 0, offset: 0 }
+    }
+
+    pub fn record(&mut self, sent: i64) {
+        self.record_from(sent, 0);
+    }
+
+    // `sent` is read off a clock `offset` nanoseconds ahead of ours, so the recorded time is the
+    // one-way latency rather than latency plus skew.
+    pub fn record_from(&mut self, sent: i64, offset: i64) {
+        let now = clock::now();
+        let sent = sent - offset;
+        self.offset = offset;
+        if now < sent {
+            return;
+        }
+        self.count += 1;
+        self.total += (now - sent) as u128;
+        if self.count % 1000 == 1 {
+            let avg = self.total / self.count as u128;
+            println!("{} avg. sent to handled time: {:?} (clock offset {:?}us)", self.name, Duration::from_nanos(avg as u64), self.offset / 1000);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Disconnect {
+    Connect(Error),
+    Stream(Error),
+    Snapshot(reqwest::Error),
+    OutOfSync(String),
+    Closed,
+}
+
+impl std::fmt::Display for Disconnect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Disconnect::Connect(err) => write!(f, "connect failed: {}", err),
+            Disconnect::Stream(err) => write!(f, "stream error: {}", err),
+            Disconnect::Snapshot(err) => write!(f, "snapshot request failed: {}", err),
+            Disconnect::OutOfSync(msg) => write!(f, "book out of sync: {}", msg),
+            Disconnect::Closed => write!(f, "stream closed"),
+        }
+    }
+}
+
+pub struct FeedDriver<F: ExchangeFeed, A: BookAdapter> {
+    feed: F,
+    adapter: A,
+    latency: LatencyStats,
+    // The venue's clock, when it publishes one.
+    clock: ClockEstimate,
+    policy: ReconnectPolicy,
+    heartbeat: Heartbeat,
+}
+
+impl<F: ExchangeFeed, A: BookAdapter> FeedDriver<F, A> {
+    pub fn new(feed: F, adapter: A, policy: ReconnectPolicy, heartbeat: Heartbeat) -> Self {
+        let name = feed.name();
+        FeedDriver {
+            feed,
+            adapter,
+            latency: LatencyStats::new(name),
+            clock: ClockEstimate::default(),
+            policy,
+            heartbeat,
+        }
+    }
+
+    // Keeps the feed connected for the life of the process. The venue's books are marked stale
+    // from the moment a connection drops until the next one delivers a fresh book.
+    pub async fn run(&mut self) {
+        let name = self.feed.name();
+        let mut attempt = 0;
+        loop {
+            let reason = self.session(&mut attempt).await;
+            self.adapter.set_stale(name, true).await;
+            let delay = self.policy.delay(attempt);
+            let cooldown = self.policy.max_attempts.is_some_and(|max| attempt >= max);
+            if cooldown {
+                println!("{}: {}, {:?} attempts failed, cooling down for {:?}", name, reason, attempt, delay);
+            } else {
+                println!("{}: {}, reconnecting in {:?} (attempt {:?})", name, reason, delay, attempt + 1);
+            }
+            tokio::time::sleep(delay).await;
+            attempt = if cooldown { 0 } else { attempt + 1 };
+        }
+    }
+
+    async fn session(&mut self, attempt: &mut u32) -> Disconnect {
+        let name = self.feed.name();
+        self.feed.reset();
+        let mut client = match WebSocketClient::new(self.feed.url()).await {
+            Ok(client) => client,
+            Err(err) => return Disconnect::Connect(err),
+        };
+        client.set_heartbeat(self.heartbeat, self.feed.keepalive());
+        for subscription in self.feed.subscriptions() {
+            client.send(subscription).await;
+        }
+        let mut events = Vec::new();
+        let mut snapshots = FuturesUnordered::<Pin<Box<dyn Future<Output = (String, Result<String, reqwest::Error>)> + Send>>>::new();
+        let time_url = self.feed.time_url();
+        let mut time_sync = tokio::time::interval(TIME_SYNC_INTERVAL);
+        let mut time_requests = FuturesUnordered::new();
+        loop {
+            let msg = tokio::select! {
+                msg = client.receive() => msg,
+                _ = time_sync.tick(), if time_url.is_some() => {
+                    if let Some(url) = &time_url {
+                        time_requests.push(fetch_time(url.clone()));
+                    }
+                    continue;
+                },
+                Some((origin, body, returned)) = time_requests.next(), if !time_requests.is_empty() => {
+                    match body.map(|body| self.feed.decode_time(&body)) {
+                        Ok(Ok(time)) => self.clock.add(ClockSample::new(origin, time, time, returned)),
+                        Ok(Err(err)) => println!("{} server time parsing error: {}", name, err),
+                        Err(err) => println!("{} server time request failed: {}", name, err),
+                    }
+                    continue;
+                },
+                Some((url, body)) = snapshots.next(), if !snapshots.is_empty() => {
+                    let body = match body {
+                        Ok(body) => body,
+                        Err(err) => return Disconnect::Snapshot(err),
+                    };
+                    match self.feed.decode_snapshot(&url, &body, &mut events) {
+                        Ok(()) => (),
+                        Err(DecodeError::OutOfSync(msg)) => return Disconnect::OutOfSync(msg),
+                        Err(err) => println!("{} snapshot parsing error: {}", name, err),
+                    }
+                    if self.dispatch(&mut events).await {
+                        *attempt = 0;
+                    }
+                    continue;
+                },
+            };
+            match msg {
+                Some(Ok(Message::Text(text))) => {
+                    match self.feed.decode(&text, &mut events) {
+                        Ok(()) => (),
+                        Err(DecodeError::OutOfSync(msg)) => return Disconnect::OutOfSync(msg),
+                        Err(err) => println!("{} parsing error for {:?}: {}", name, text, err),
+                    }
+                    if self.dispatch(&mut events).await {
+                        *attempt = 0;
+                    }
+                    while let Some(url) = self.feed.snapshot_request() {
+                        snapshots.push(Box::pin(fetch(url)));
+                    }
+                },
+                Some(Ok(_)) => (),
+                Some(Err(err)) => return Disconnect::Stream(err),
+                None => return Disconnect::Closed,
+            }
+        }
+    }
+
+    // Applies decoded events, returning whether any of them was a snapshot or delta. Backoff only
+    // resets once a connection has delivered book data, so one that accepts and then drops us
+    // keeps backing off.
+    async fn dispatch(&mut self, events: &mut Vec<FeedEvent>) -> bool {
+        let name = self.feed.name();
+        let mut book_data = false;
+        for event in events.drain(..) {
+            match event {
+                FeedEvent::Trade { time: Some(time), .. } => self.latency.record_from(time, self.clock.offset()),
+                FeedEvent::Snapshot { .. } | FeedEvent::Delta { .. } => book_data = true,
+                _ => (),
+            }
+            self.adapter.apply(name, event).await;
+        }
+        book_data
+    }
+}
+
+// Our send and receive times around a server time request, with its body.
+async fn fetch_time(url: String) -> (i64, Result<String, reqwest::Error>, i64) {
+    let origin = clock::now();
+    let body = async {
+        reqwest::get(url.as_str()).await?.error_for_status()?.text().await
+    }.await;
+    (origin, body, clock::now())
+}
+
+async fn fetch(url: String) -> (String, Result<String, reqwest::Error>) {
+    let body = async {
+        reqwest::get(url.as_str()).await?.error_for_status()?.text().await
+    }.await;
+    (url, body)
+}
@@ -0,0 +1,285 @@
+use std::sync::Arc;
+
+use chrono::{NaiveDateTime, TimeZone, Utc};
+
+use crate::order_book::clients::feed::{DecodeError, FeedEvent};
+use crate::order_book::data_types::{Change, Match, PriceLevel, Side, Snapshot, to_level};
+use crate::order_book::instruments::InstrumentRegistry;
+
+use super::message::FixMessage;
+
+// One MDEntry from a snapshot or incremental refresh.
+#[derive(Default)]
+struct Entry<'a> {
+    action: Option<&'a str>,
+    entry_type: Option<&'a str>,
+    symbol: Option<&'a str>,
+    price: Option<&'a str>,
+    size: Option<&'a str>,
+    side: Option<&'a str>,
+    time: Option<&'a str>,
+}
+
+impl<'a> Entry<'a> {
+    fn set(&mut self, tag: u32, value: &'a str) {
+        match tag {
+            279 => self.action = Some(value),
+            269 => self.entry_type = Some(value),
+            55 => self.symbol = Some(value),
+            270 => self.price = Some(value),
+            271 => self.size = Some(value),
+            // AggressorSide, falling back to Side for venues that reuse it on trades.
+            2446 => self.side = Some(value),
+            54 => self.side = self.side.or(Some(value)),
+            60 => self.time = Some(value),
+            _ => (),
+        }
+    }
+}
+
+// Subscribes to and decodes FIX MarketDataSnapshotFullRefresh (W) and
+// MarketDataIncrementalRefresh (X) for every pair the registry lists on `venue`.
+pub struct MarketData {
+    venue: &'static str,
+    symbols: Vec<(String, heapless::String<8>)>,
+    requests: u64,
+}
+
+impl MarketData {
+    pub fn new(venue: &'static str, registry: Arc<InstrumentRegistry>) -> MarketData {
+        return MarketData {
+            venue,
+            symbols: registry.venue_listings(venue).iter().map(|(pair, listing)| (listing.symbol.clone(), pair.clone())).collect(),
+            requests: 0,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.venue
+    }
+
+    // MarketDataRequest for full depth snapshots plus incremental updates and trades. Each call
+    // uses a fresh MDReqID, so it can also be used to resubscribe after a gap.
+    pub fn request(&mut self) -> FixMessage {
+        self.requests += 1;
+        let mut message = FixMessage::new("V");
+        message.push(262, &format!("{}-md-{}", self.venue, self.requests));
+        message.push(263, "1");
+        message.push(264, "0");
+        message.push(265, "1");
+        message.push(267, "3");
+        for entry_type in ["0", "1", "2"] {
+            message.push(269, entry_type);
+        }
+        message.push(146, &self.symbols.len().to_string());
+        for (symbol, _) in self.symbols.iter() {
+            message.push(55, symbol);
+        }
+        message
+    }
+
+    fn pair(&self, symbol: Option<&str>) -> Option<heapless::String<8>> {
+        let symbol = symbol?;
+        self.symbols.iter().find(|(s, _)| s == symbol).map(|(_, pair)| pair.clone())
+    }
+
+    // Splits the NoMDEntries group into entries, each starting at `first_tag`.
+    fn entries(message: &FixMessage, first_tag: u32) -> Vec<Entry<'_>> {
+        let mut entries = Vec::new();
+        let mut in_group = false;
+        for (tag, value) in message.fields.iter() {
+            if *tag == 268 {
+                in_group = true;
+                continue;
+            }
+            if !in_group {
+                continue;
+            }
+            if *tag == first_tag {
+                entries.push(Entry::default());
+            }
+            if let Some(entry) = entries.last_mut() {
+                entry.set(*tag, value);
+            }
+        }
+        entries
+    }
+
+    fn parse(value: Option<&str>, name: &str) -> Result<f64, DecodeError> {
+        value.ok_or_else(|| DecodeError::Unexpected(format!("MDEntry without {}", name)))?
+            .parse::<f64>()
+            .map_err(|e| DecodeError::Unexpected(e.to_string()))
+    }
+
+    fn time(value: Option<&str>) -> Option<i64> {
+        let value = value?;
+        let time = NaiveDateTime::parse_from_str(value, "%Y%m%d-%H:%M:%S%.f").ok()?;
+        let time = Utc.from_utc_datetime(&time);
+        Some(time.timestamp() * 1_000_000_000 + time.timestamp_subsec_nanos() as i64)
+    }
+
+    fn trade(pair: heapless::String<8>, entry: &Entry, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let side = match entry.side {
+            Some("1") => Side::Buy,
+            Some("2") => Side::Sell,
+            _ => return Ok(()),
+        };
+        let price = MarketData::parse(entry.price, "MDEntryPx")?;
+        let size = MarketData::parse(entry.size, "MDEntrySize")?;
+        events.push(FeedEvent::Trade {
+            pair,
+            trade: Match { side, size, price: to_level(price) },
+            time: MarketData::time(entry.time),
+        });
+        Ok(())
+    }
+
+    pub fn decode(&mut self, message: &FixMessage, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        match message.msg_type.as_str() {
+            "W" => self.snapshot(message, events),
+            "X" => self.update(message, events),
+            "Y" => Err(DecodeError::Unexpected(format!(
+                "market data request {:?} rejected: {:?}",
+                message.get(262),
+                message.get(58),
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn snapshot(&mut self, message: &FixMessage, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let pair = match self.pair(message.get(55)) {
+            Some(pair) => pair,
+            None => return Ok(()),
+        };
+        let mut initial_book = Snapshot::new();
+        let mut trades = Vec::new();
+        for entry in MarketData::entries(message, 269) {
+            if entry.entry_type == Some("2") {
+                trades.push(entry);
+                continue;
+            }
+            let price_level = PriceLevel {
+                level: to_level(MarketData::parse(entry.price, "MDEntryPx")?),
+                amount: MarketData::parse(entry.size, "MDEntrySize")?,
+                sequence: 0,
+            };
+            let _ = match entry.entry_type {
+                Some("0") => initial_book.bids.push(price_level),
+                Some("1") => initial_book.asks.push(price_level),
+                _ => Ok(()),
+            };
+        }
+        events.push(FeedEvent::Snapshot { pair: pair.clone(), snapshot: initial_book });
+        for entry in trades.iter() {
+            MarketData::trade(pair.clone(), entry, events)?;
+        }
+        Ok(())
+    }
+
+    // Incremental entries may cover several symbols; consecutive book entries for a pair become one
+    // delta, and trades keep their place between them.
+    fn update(&mut self, message: &FixMessage, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let default_symbol = message.get(55);
+        let mut pending: Option<(heapless::String<8>, heapless::Vec<Change, 512>)> = None;
+        for entry in MarketData::entries(message, 279) {
+            let pair = match self.pair(entry.symbol.or(default_symbol)) {
+                Some(pair) => pair,
+                None => continue,
+            };
+            if entry.entry_type == Some("2") {
+                if let Some((pair, changes)) = pending.take() {
+                    events.push(FeedEvent::Delta { pair, changes });
+                }
+                MarketData::trade(pair, &entry, events)?;
+                continue;
+            }
+            let side = match entry.entry_type {
+                Some("0") => Side::Buy,
+                Some("1") => Side::Sell,
+                _ => continue,
+            };
+            let amount = match entry.action {
+                Some("2") => 0.0,
+                _ => MarketData::parse(entry.size, "MDEntrySize")?,
+            };
+            let change = Change {
+                side,
+                price_level: PriceLevel {
+                    level: to_level(MarketData::parse(entry.price, "MDEntryPx")?),
+                    amount,
+                    sequence: 0,
+                },
+            };
+            let flush = match &pending {
+                Some((current, changes)) => *current != pair || changes.is_full(),
+                None => false,
+            };
+            if flush {
+                let (pair, changes) = pending.take().unwrap();
+                events.push(FeedEvent::Delta { pair, changes });
+            }
+            let (_, changes) = pending.get_or_insert_with(|| (pair, heapless::Vec::new()));
+            let _ = changes.push(change);
+        }
+        if let Some((pair, changes)) = pending {
+            events.push(FeedEvent::Delta { pair, changes });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+fn message(msg_type: &str, fields: &str) -> FixMessage {
+    let mut message = FixMessage::new(msg_type);
+    for field in fields.split('|').filter(|f| !f.is_empty()) {
+        let (tag, value) = field.split_once('=').unwrap();
+        message.push(tag.parse().unwrap(), value);
+    }
+    message
+}
+
+#[test]
+fn test_request_lists_every_symbol() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut market_data = MarketData::new("coinbase", Arc::new(registry));
+    let request = market_data.request();
+    assert_eq!(Some("coinbase-md-1"), request.get(262));
+    assert_eq!(Some("2"), request.get(146));
+    let symbols: Vec<&str> = request.fields.iter().filter(|(tag, _)| *tag == 55).map(|(_, v)| v.as_str()).collect();
+    assert_eq!(vec!["ETH-USD", "BTC-USD"], symbols);
+    assert_eq!(Some("coinbase-md-2"), market_data.request().get(262));
+}
+
+#[test]
+fn test_decode_snapshot_and_incremental() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut market_data = MarketData::new("coinbase", Arc::new(registry));
+    let mut events = Vec::new();
+    let snapshot = message("W", "34=2|55=BTC-USD|268=3|269=0|270=30000.00|271=1.5|269=1|270=30001.00|271=2|269=0|270=29999.00|271=0.25");
+    market_data.decode(&snapshot, &mut events).unwrap();
+    match &events[0] {
+        FeedEvent::Snapshot { pair, snapshot } => {
+            assert_eq!("BTC-USD", pair.as_str());
+            assert_eq!(vec![3000000, 2999900], snapshot.bids.iter().map(|l| l.level).collect::<Vec<_>>());
+            assert_eq!(2.0, snapshot.asks[0].amount);
+        },
+        other => panic!("Expected snapshot, got {:?}", other),
+    }
+    events.clear();
+    let update = message("X", "34=3|268=4|279=0|269=0|55=BTC-USD|270=30000.50|271=0.1|279=2|269=1|55=BTC-USD|270=30001.00|279=1|269=1|55=ETH-USD|270=1897.20|271=3|279=0|269=2|55=BTC-USD|270=30000.50|271=0.05|2446=2|60=20230710-22:08:09.100");
+    market_data.decode(&update, &mut events).unwrap();
+    assert_eq!(3, events.len());
+    match &events[0] {
+        FeedEvent::Delta { pair, changes } => {
+            assert_eq!("BTC-USD", pair.as_str());
+            assert_eq!(2, changes.len());
+            assert_eq!((Side::Sell, 0.0), (changes[1].side, changes[1].price_level.amount));
+        },
+        other => panic!("Expected delta, got {:?}", other),
+    }
+    assert!(matches!(&events[1], FeedEvent::Delta { pair, changes } if pair == "ETH-USD" && changes[0].price_level.level == 189720));
+    assert!(matches!(&events[2], FeedEvent::Trade { trade: Match { side: Side::Sell, price: 3000050, .. }, time: Some(1689026889100000000), .. }));
+    let reject = message("Y", "262=coinbase-md-1|281=0|58=Unknown symbol");
+    assert!(matches!(market_data.decode(&reject, &mut events), Err(DecodeError::Unexpected(_))));
+}
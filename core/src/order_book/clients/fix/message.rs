@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+pub const SOH: u8 = 0x01;
+
+#[derive(Debug)]
+pub enum FixError {
+    Io(std::io::Error),
+    Tls(tokio_native_tls::native_tls::Error),
+    Malformed(String),
+    Checksum { expected: u8, actual: u8 },
+    // The counterparty broke the session protocol: rejected logon, logged out, or sent a
+    // sequence number lower than expected without PossDupFlag.
+    Session(String),
+    Timeout(Duration),
+    OutOfSync(String),
+    Closed,
+}
+
+impl std::fmt::Display for FixError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FixError::Io(err) => write!(f, "io error: {}", err),
+            FixError::Tls(err) => write!(f, "tls error: {}", err),
+            FixError::Malformed(msg) => write!(f, "malformed message: {}", msg),
+            FixError::Checksum { expected, actual } => write!(f, "checksum {:03} != {:03}", actual, expected),
+            FixError::Session(msg) => write!(f, "session error: {}", msg),
+            FixError::Timeout(after) => write!(f, "no response to test request after {:?}", after),
+            FixError::OutOfSync(msg) => write!(f, "book out of sync: {}", msg),
+            FixError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl From<std::io::Error> for FixError {
+    fn from(err: std::io::Error) -> Self {
+        FixError::Io(err)
+    }
+}
+
+impl From<tokio_native_tls::native_tls::Error> for FixError {
+    fn from(err: tokio_native_tls::native_tls::Error) -> Self {
+        FixError::Tls(err)
+    }
+}
+
+// Header fields written by `encode` for every outgoing message.
+pub struct Header<'a> {
+    pub begin_string: &'a str,
+    pub sender_comp_id: &'a str,
+    pub target_comp_id: &'a str,
+    pub seq_num: u64,
+    pub sending_time: &'a str,
+    pub poss_dup: bool,
+}
+
+// A tag=value message. Fields are kept in wire order so repeating groups can be walked by
+// watching for each group's first tag. On decode this includes the standard header fields
+// after MsgType; BeginString, BodyLength, MsgType and CheckSum are held separately or dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixMessage {
+    pub msg_type: String,
+    pub fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> FixMessage {
+        FixMessage {
+            msg_type: msg_type.to_string(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, tag: u32, value: &str) {
+        self.fields.push((tag, value.to_string()));
+    }
+
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|(t, _)| *t == tag).map(|(_, v)| v.as_str())
+    }
+
+    pub fn get_u64(&self, tag: u32) -> Option<u64> {
+        self.get(tag).and_then(|v| v.parse::<u64>().ok())
+    }
+
+    pub fn encode(&self, header: &Header) -> Vec<u8> {
+        let mut body = format!(
+            "35={}\x0149={}\x0156={}\x0134={}\x0152={}\x01",
+            self.msg_type,
+            header.sender_comp_id,
+            header.target_comp_id,
+            header.seq_num,
+            header.sending_time,
+        );
+        if header.poss_dup {
+            body.push_str("43=Y\x01");
+        }
+        for (tag, value) in self.fields.iter() {
+            body.push_str(&format!("{}={}\x01", tag, value));
+        }
+        let mut out = format!("8={}\x019={}\x01{}", header.begin_string, body.len(), body).into_bytes();
+        let checksum = checksum(&out);
+        out.extend_from_slice(format!("10={:03}\x01", checksum).as_bytes());
+        out
+    }
+
+    // Takes the first complete message off the front of `buf`, or returns None until one has
+    // fully arrived.
+    pub fn decode(buf: &mut Vec<u8>) -> Result<Option<FixMessage>, FixError> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+        if !buf.starts_with(b"8=") {
+            let skipped = buf.len();
+            buf.clear();
+            return Err(FixError::Malformed(format!("expected BeginString, discarded {:?} bytes", skipped)));
+        }
+        let begin_end = match buf.iter().position(|b| *b == SOH) {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        let length_end = match buf[begin_end + 1..].iter().position(|b| *b == SOH) {
+            Some(i) => begin_end + 1 + i,
+            None => return Ok(None),
+        };
+        let length_field = String::from_utf8_lossy(&buf[begin_end + 1..length_end]).to_string();
+        let body_length = match length_field.strip_prefix("9=").and_then(|v| v.parse::<usize>().ok()) {
+            Some(length) => length,
+            None => {
+                buf.clear();
+                return Err(FixError::Malformed(format!("bad BodyLength {:?}", length_field)));
+            },
+        };
+        let body_end = length_end + 1 + body_length;
+        // "10=NNN" plus its delimiter.
+        let total = body_end + 7;
+        if buf.len() < total {
+            return Ok(None);
+        }
+        let frame: Vec<u8> = buf.drain(..total).collect();
+        let trailer = std::str::from_utf8(&frame[body_end..total - 1]).unwrap_or("");
+        let expected = match trailer.strip_prefix("10=").and_then(|v| v.parse::<u8>().ok()) {
+            Some(expected) => expected,
+            None => return Err(FixError::Malformed(format!("bad CheckSum field {:?}", trailer))),
+        };
+        let actual = checksum(&frame[..body_end]);
+        if actual != expected {
+            return Err(FixError::Checksum { expected, actual });
+        }
+        let body = std::str::from_utf8(&frame[length_end + 1..body_end])
+            .map_err(|e| FixError::Malformed(e.to_string()))?;
+        let mut fields = Vec::new();
+        for field in body.split('\x01').filter(|f| !f.is_empty()) {
+            let (tag, value) = field.split_once('=')
+                .ok_or_else(|| FixError::Malformed(format!("field without '=': {:?}", field)))?;
+            let tag = tag.parse::<u32>().map_err(|_| FixError::Malformed(format!("bad tag {:?}", tag)))?;
+            fields.push((tag, value.to_string()));
+        }
+        if fields.first().map(|(tag, _)| *tag) != Some(35) {
+            return Err(FixError::Malformed("MsgType is not the first body field".to_string()));
+        }
+        let (_, msg_type) = fields.remove(0);
+        Ok(Some(FixMessage { msg_type, fields }))
+    }
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0u32, |sum, b| sum + *b as u32) as u8
+}
+
+// UTCTimestamp with milliseconds, as used in SendingTime.
+pub fn timestamp(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%Y%m%d-%H:%M:%S%.3f").to_string()
+}
+
+#[cfg(test)]
+fn header(seq_num: u64) -> Header<'static> {
+    Header {
+        begin_string: "FIX.4.4",
+        sender_comp_id: "CLIENT",
+        target_comp_id: "Coinbase",
+        seq_num,
+        sending_time: "20230710-22:08:08.932",
+        poss_dup: false,
+    }
+}
+
+#[test]
+fn test_encode() {
+    let mut message = FixMessage::new("0");
+    message.push(112, "TEST");
+    let encoded = String::from_utf8(message.encode(&header(2))).unwrap().replace('\x01', "|");
+    assert_eq!("8=FIX.4.4|9=66|35=0|49=CLIENT|56=Coinbase|34=2|52=20230710-22:08:08.932|112=TEST|10=207|", encoded);
+}
+
+#[test]
+fn test_decode_round_trip_and_partial() {
+    let mut message = FixMessage::new("W");
+    message.push(55, "BTC-USD");
+    message.push(268, "1");
+    message.push(269, "0");
+    message.push(270, "30000.00");
+    let encoded = message.encode(&header(7));
+    let mut buf = encoded[..20].to_vec();
+    assert_eq!(None, FixMessage::decode(&mut buf).unwrap());
+    buf.extend_from_slice(&encoded[20..]);
+    buf.extend_from_slice(&encoded[..5]);
+    let decoded = FixMessage::decode(&mut buf).unwrap().unwrap();
+    assert_eq!("W", decoded.msg_type);
+    assert_eq!(Some(7), decoded.get_u64(34));
+    assert_eq!(Some("30000.00"), decoded.get(270));
+    assert_eq!(5, buf.len());
+}
+
+#[test]
+fn test_decode_rejects_bad_checksum() {
+    let mut encoded = FixMessage::new("0").encode(&header(1));
+    let at = encoded.len() - 2;
+    encoded[at] = if encoded[at] == b'0' { b'1' } else { b'0' };
+    let mut buf = encoded;
+    assert!(matches!(FixMessage::decode(&mut buf), Err(FixError::Checksum { .. })));
+    assert!(buf.is_empty());
+}
@@ -0,0 +1,5 @@
+pub mod market_data;
+pub mod message;
+pub mod session;
+
+pub use session::FIXClient;
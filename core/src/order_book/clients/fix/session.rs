@@ -0,0 +1,535 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+
+use crate::order_book::clients::client::ReconnectPolicy;
+use crate::order_book::clients::feed::{BookAdapter, DecodeError, FeedEvent, LatencyStats};
+
+use super::market_data::MarketData;
+use super::message::{FixError, FixMessage, Header, timestamp};
+
+// Messages held while a resend request is outstanding. Past this the gap is not going to be
+// filled in time to be worth waiting for.
+const MAX_QUEUED: usize = 4096;
+
+pub enum LogonAuth {
+    None,
+    // Sent as-is on every Logon, e.g. Username(553) and Password(554).
+    Fields(Vec<(u32, String)>),
+    // Coinbase signs SendingTime, MsgType, MsgSeqNum, SenderCompID, TargetCompID and the
+    // passphrase with the base64 API secret; the signature goes in RawData(96).
+    CoinbaseHmac { passphrase: String, secret: String },
+}
+
+pub struct SessionConfig {
+    // host:port of the acceptor.
+    pub address: String,
+    pub tls: bool,
+    pub begin_string: String,
+    pub sender_comp_id: String,
+    pub target_comp_id: String,
+    pub heartbeat_interval: Duration,
+    // Sends ResetSeqNumFlag(141) on Logon so both sides start again from 1.
+    pub reset_on_logon: bool,
+    pub auth: LogonAuth,
+    // Where the next sender and target sequence numbers are kept between connections. Without
+    // one they only survive reconnects within this process.
+    pub sequence_file: Option<PathBuf>,
+}
+
+impl SessionConfig {
+    pub fn coinbase(api_key: String, passphrase: String, secret: String) -> SessionConfig {
+        SessionConfig {
+            address: "fix-md.exchange.coinbase.com:6121".to_string(),
+            tls: true,
+            begin_string: "FIX.4.4".to_string(),
+            sender_comp_id: api_key,
+            target_comp_id: "Coinbase".to_string(),
+            heartbeat_interval: Duration::from_secs(30),
+            reset_on_logon: true,
+            auth: LogonAuth::CoinbaseHmac { passphrase, secret },
+            sequence_file: None,
+        }
+    }
+
+    fn logon_fields(&self, sending_time: &str, seq_num: u64) -> Vec<(u32, String)> {
+        match &self.auth {
+            LogonAuth::None => Vec::new(),
+            LogonAuth::Fields(fields) => fields.clone(),
+            LogonAuth::CoinbaseHmac { passphrase, secret } => {
+                let prehash = [
+                    sending_time,
+                    "A",
+                    &seq_num.to_string(),
+                    &self.sender_comp_id,
+                    &self.target_comp_id,
+                    passphrase,
+                ].join("\x01");
+                let key = STANDARD.decode(secret).unwrap_or_else(|_| secret.as_bytes().to_vec());
+                let mut mac = Hmac::<Sha256>::new_from_slice(&key).expect("HMAC accepts any key length");
+                mac.update(prehash.as_bytes());
+                let signature = STANDARD.encode(mac.finalize().into_bytes());
+                vec![
+                    (554, passphrase.clone()),
+                    (95, signature.len().to_string()),
+                    (96, signature),
+                ]
+            },
+        }
+    }
+}
+
+pub struct SequenceStore {
+    path: Option<PathBuf>,
+    pub next_sender: u64,
+    pub next_target: u64,
+}
+
+impl SequenceStore {
+    // A missing or unreadable file starts both sides at 1.
+    pub fn load(path: Option<PathBuf>) -> SequenceStore {
+        let (next_sender, next_target) = path.as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|contents| {
+                let mut parts = contents.split_whitespace().map(|p| p.parse::<u64>().ok());
+                Some((parts.next()??, parts.next()??))
+            })
+            .unwrap_or((1, 1));
+        SequenceStore { path, next_sender, next_target }
+    }
+
+    pub fn reset(&mut self) {
+        self.next_sender = 1;
+        self.next_target = 1;
+        self.save();
+    }
+
+    pub fn save(&self) {
+        if let Some(path) = &self.path {
+            if let Err(err) = std::fs::write(path, format!("{} {}\n", self.next_sender, self.next_target)) {
+                println!("Error saving FIX sequence numbers to {:?}: {:?}", path, err);
+            }
+        }
+    }
+}
+
+trait FixStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> FixStream for T {}
+
+struct Connection {
+    stream: Box<dyn FixStream>,
+    buffer: Vec<u8>,
+    last_sent: Instant,
+    last_received: Instant,
+    // TestReqID and when it was sent, while we wait for the matching Heartbeat.
+    test_request: Option<(String, Instant)>,
+    logged_on: bool,
+    subscribed: bool,
+    // Messages past a gap, held until the resend fills it.
+    queued: BTreeMap<u64, FixMessage>,
+    resend_requested: bool,
+}
+
+pub struct FIXClient<A: BookAdapter> {
+    config: SessionConfig,
+    market_data: MarketData,
+    adapter: A,
+    policy: ReconnectPolicy,
+    store: SequenceStore,
+    latency: LatencyStats,
+    test_requests: u64,
+}
+
+impl<A: BookAdapter> FIXClient<A> {
+    pub fn new(config: SessionConfig, market_data: MarketData, adapter: A, policy: ReconnectPolicy) -> Self {
+        let store = SequenceStore::load(config.sequence_file.clone());
+        let name = market_data.name();
+        FIXClient {
+            config,
+            market_data,
+            adapter,
+            policy,
+            store,
+            latency: LatencyStats::new(name),
+            test_requests: 0,
+        }
+    }
+
+    // Keeps the session logged on for the life of the process, marking the venue's books stale
+    // between a disconnect and the next snapshot.
+    pub async fn run(&mut self) {
+        let name = self.market_data.name();
+        let mut attempt = 0;
+        loop {
+            let reason = self.session(&mut attempt).await;
+            self.adapter.set_stale(name, true).await;
+            let delay = self.policy.delay(attempt);
+            let cooldown = self.policy.max_attempts.is_some_and(|max| attempt >= max);
+            if cooldown {
+                println!("{} FIX: {}, {:?} attempts failed, cooling down for {:?}", name, reason, attempt, delay);
+            } else {
+                println!("{} FIX: {}, reconnecting in {:?} (attempt {:?})", name, reason, delay, attempt + 1);
+            }
+            tokio::time::sleep(delay).await;
+            attempt = if cooldown { 0 } else { attempt + 1 };
+        }
+    }
+
+    async fn connect(&self) -> Result<Box<dyn FixStream>, FixError> {
+        let stream = TcpStream::connect(&self.config.address).await?;
+        stream.set_nodelay(true)?;
+        if !self.config.tls {
+            return Ok(Box::new(stream));
+        }
+        let host = self.config.address.rsplit_once(':').map_or(self.config.address.as_str(), |(host, _)| host);
+        let connector = tokio_native_tls::TlsConnector::from(tokio_native_tls::native_tls::TlsConnector::new()?);
+        Ok(Box::new(connector.connect(host, stream).await?))
+    }
+
+    async fn session(&mut self, attempt: &mut u32) -> FixError {
+        let stream = match self.connect().await {
+            Ok(stream) => stream,
+            Err(err) => return err,
+        };
+        let mut conn = Connection {
+            stream,
+            buffer: Vec::new(),
+            last_sent: Instant::now(),
+            last_received: Instant::now(),
+            test_request: None,
+            logged_on: false,
+            subscribed: false,
+            queued: BTreeMap::new(),
+            resend_requested: false,
+        };
+        if self.config.reset_on_logon {
+            self.store.reset();
+        }
+        let mut logon = FixMessage::new("A");
+        logon.push(98, "0");
+        logon.push(108, &self.config.heartbeat_interval.as_secs().to_string());
+        if self.config.reset_on_logon {
+            logon.push(141, "Y");
+        }
+        if let Err(err) = self.send(&mut conn, logon).await {
+            return err;
+        }
+        match self.receive(&mut conn, attempt).await {
+            Ok(()) => FixError::Closed,
+            Err(err) => err,
+        }
+    }
+
+    async fn receive(&mut self, conn: &mut Connection, attempt: &mut u32) -> Result<(), FixError> {
+        let interval = self.config.heartbeat_interval;
+        let mut chunk = [0u8; 8192];
+        loop {
+            // A TestRequest goes out once the counterparty has been quiet for a little over one
+            // heartbeat interval, and the session is dropped if it goes unanswered for another.
+            let test_deadline = match &conn.test_request {
+                Some((_, sent)) => *sent + interval,
+                None => conn.last_received + interval + interval / 5,
+            };
+            tokio::select! {
+                read = conn.stream.read(&mut chunk) => {
+                    let n = read?;
+                    if n == 0 {
+                        return Err(FixError::Closed);
+                    }
+                    conn.last_received = Instant::now();
+                    conn.buffer.extend_from_slice(&chunk[..n]);
+                    while let Some(message) = FixMessage::decode(&mut conn.buffer)? {
+                        self.handle(conn, message).await?;
+                    }
+                    if conn.logged_on && !conn.subscribed {
+                        // Logon was acknowledged, so this connection counts as a success.
+                        *attempt = 0;
+                        conn.subscribed = true;
+                        let request = self.market_data.request();
+                        self.send(conn, request).await?;
+                    }
+                },
+                _ = tokio::time::sleep_until(conn.last_sent + interval) => {
+                    self.send(conn, FixMessage::new("0")).await?;
+                },
+                _ = tokio::time::sleep_until(test_deadline) => {
+                    if conn.test_request.is_some() {
+                        return Err(FixError::Timeout(interval));
+                    }
+                    self.test_requests += 1;
+                    let id = format!("TEST{}", self.test_requests);
+                    let mut test_request = FixMessage::new("1");
+                    test_request.push(112, &id);
+                    self.send(conn, test_request).await?;
+                    conn.test_request = Some((id, Instant::now()));
+                },
+            }
+        }
+    }
+
+    async fn send(&mut self, conn: &mut Connection, message: FixMessage) -> Result<(), FixError> {
+        let seq_num = self.store.next_sender;
+        self.write(conn, &message, seq_num, false).await?;
+        self.store.next_sender += 1;
+        self.store.save();
+        Ok(())
+    }
+
+    async fn write(&mut self, conn: &mut Connection, message: &FixMessage, seq_num: u64, poss_dup: bool) -> Result<(), FixError> {
+        let sending_time = timestamp(chrono::Utc::now());
+        let mut message = message.clone();
+        if message.msg_type == "A" {
+            message.fields.extend(self.config.logon_fields(&sending_time, seq_num));
+        }
+        let header = Header {
+            begin_string: &self.config.begin_string,
+            sender_comp_id: &self.config.sender_comp_id,
+            target_comp_id: &self.config.target_comp_id,
+            seq_num,
+            sending_time: &sending_time,
+            poss_dup,
+        };
+        conn.stream.write_all(&message.encode(&header)).await?;
+        conn.last_sent = Instant::now();
+        Ok(())
+    }
+
+    // Enforces MsgSeqNum ordering, then processes the message and anything queued behind it.
+    async fn handle(&mut self, conn: &mut Connection, message: FixMessage) -> Result<(), FixError> {
+        let seq_num = message.get_u64(34)
+            .ok_or_else(|| FixError::Malformed(format!("{:?} without MsgSeqNum", message.msg_type)))?;
+        // SequenceReset in Reset mode applies regardless of its own sequence number.
+        if message.msg_type == "4" && message.get(123) != Some("Y") {
+            self.reset_target(conn, &message)?;
+            return Ok(());
+        }
+        let expected = self.store.next_target;
+        if seq_num < expected {
+            if message.get(43) == Some("Y") {
+                return Ok(());
+            }
+            return Err(FixError::Session(format!("MsgSeqNum {:?} lower than expected {:?}", seq_num, expected)));
+        }
+        if seq_num > expected {
+            // A Logout is honoured even out of order; there is no point asking for a resend.
+            if message.msg_type == "5" {
+                return self.process(conn, message).await;
+            }
+            if conn.queued.len() >= MAX_QUEUED {
+                return Err(FixError::OutOfSync(format!("{:?} messages queued behind gap at {:?}", conn.queued.len(), expected)));
+            }
+            conn.queued.insert(seq_num, message);
+            if !conn.resend_requested {
+                conn.resend_requested = true;
+                let mut resend = FixMessage::new("2");
+                resend.push(7, &expected.to_string());
+                resend.push(16, "0");
+                self.send(conn, resend).await?;
+            }
+            return Ok(());
+        }
+        self.process(conn, message).await?;
+        while let Some(message) = conn.queued.remove(&self.store.next_target) {
+            self.process(conn, message).await?;
+        }
+        let next_target = self.store.next_target;
+        conn.queued.retain(|seq_num, _| *seq_num >= next_target);
+        if conn.queued.is_empty() {
+            conn.resend_requested = false;
+        }
+        Ok(())
+    }
+
+    fn reset_target(&mut self, conn: &mut Connection, message: &FixMessage) -> Result<(), FixError> {
+        let new_seq_no = message.get_u64(36)
+            .ok_or_else(|| FixError::Malformed("SequenceReset without NewSeqNo".to_string()))?;
+        self.store.next_target = new_seq_no;
+        self.store.save();
+        conn.queued.retain(|seq_num, _| *seq_num >= new_seq_no);
+        Ok(())
+    }
+
+    async fn process(&mut self, conn: &mut Connection, message: FixMessage) -> Result<(), FixError> {
+        if let Some(seq_num) = message.get_u64(34) {
+            self.store.next_target = self.store.next_target.max(seq_num + 1);
+            self.store.save();
+        }
+        match message.msg_type.as_str() {
+            "A" => conn.logged_on = true,
+            "0" => {
+                if message.get(112).is_some() && message.get(112) == conn.test_request.as_ref().map(|(id, _)| id.as_str()) {
+                    conn.test_request = None;
+                }
+            },
+            "1" => {
+                let mut heartbeat = FixMessage::new("0");
+                if let Some(id) = message.get(112) {
+                    heartbeat.push(112, id);
+                }
+                self.send(conn, heartbeat).await?;
+            },
+            // We only ever send session messages and a MarketDataRequest, so a resend is answered
+            // by gap-filling the whole range up to our next sequence number.
+            "2" => {
+                let begin = message.get_u64(7).unwrap_or(1);
+                let mut gap_fill = FixMessage::new("4");
+                gap_fill.push(123, "Y");
+                gap_fill.push(36, &self.store.next_sender.to_string());
+                self.write(conn, &gap_fill, begin, true).await?;
+            },
+            "3" => println!("{} FIX: reject of our message {:?}: {:?}", self.market_data.name(), message.get(45), message.get(58)),
+            "4" => {
+                self.reset_target(conn, &message)?;
+                // The skipped range may have held market data, so start the books over.
+                if conn.subscribed {
+                    let request = self.market_data.request();
+                    self.send(conn, request).await?;
+                }
+            },
+            "5" => {
+                if conn.logged_on {
+                    self.send(conn, FixMessage::new("5")).await?;
+                }
+                return Err(FixError::Session(format!("logout: {:?}", message.get(58).unwrap_or(""))));
+            },
+            _ => {
+                let mut events = Vec::new();
+                match self.market_data.decode(&message, &mut events) {
+                    Ok(()) => (),
+                    Err(DecodeError::OutOfSync(msg)) => return Err(FixError::OutOfSync(msg)),
+                    Err(err) => println!("{} FIX: {}", self.market_data.name(), err),
+                }
+                let name = self.market_data.name();
+                for event in events {
+                    if let FeedEvent::Trade { time: Some(time), .. } = event {
+                        self.latency.record(time);
+                    }
+                    self.adapter.apply(name, event).await;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_sequence_store_round_trip() {
+    let path = std::env::temp_dir().join(format!("prism-fix-seq-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut store = SequenceStore::load(Some(path.clone()));
+    assert_eq!((1, 1), (store.next_sender, store.next_target));
+    store.next_sender = 42;
+    store.next_target = 17;
+    store.save();
+    let store = SequenceStore::load(Some(path.clone()));
+    assert_eq!((42, 17), (store.next_sender, store.next_target));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_coinbase_logon_signature() {
+    let config = SessionConfig::coinbase("key".to_string(), "phrase".to_string(), STANDARD.encode("secret"));
+    let fields = config.logon_fields("20230710-22:08:08.932", 1);
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+    mac.update(b"20230710-22:08:08.932\x01A\x011\x01key\x01Coinbase\x01phrase");
+    let signature = STANDARD.encode(mac.finalize().into_bytes());
+    assert_eq!(vec![(554, "phrase".to_string()), (95, signature.len().to_string()), (96, signature)], fields);
+}
+
+#[cfg(test)]
+async fn next_message(stream: &mut TcpStream, buffer: &mut Vec<u8>) -> FixMessage {
+    let mut chunk = [0u8; 1024];
+    loop {
+        if let Some(message) = FixMessage::decode(buffer).unwrap() {
+            return message;
+        }
+        let n = stream.read(&mut chunk).await.unwrap();
+        assert!(n > 0, "client closed the connection");
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+}
+
+#[cfg(test)]
+async fn send_message(stream: &mut TcpStream, msg_type: &str, seq_num: u64, poss_dup: bool, fields: &str) {
+    let mut message = FixMessage::new(msg_type);
+    for field in fields.split('|').filter(|f| !f.is_empty()) {
+        let (tag, value) = field.split_once('=').unwrap();
+        message.push(tag.parse().unwrap(), value);
+    }
+    let header = Header {
+        begin_string: "FIX.4.4",
+        sender_comp_id: "ACCEPTOR",
+        target_comp_id: "CLIENT",
+        seq_num,
+        sending_time: "20230710-22:08:08.932",
+        poss_dup,
+    };
+    stream.write_all(&message.encode(&header)).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_session_against_acceptor() {
+    use std::sync::Arc;
+    use crate::order_book::clients::test_support::RecordingAdapter;
+    use crate::order_book::instruments::InstrumentRegistry;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let acceptor = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buffer = Vec::new();
+        let logon = next_message(&mut stream, &mut buffer).await;
+        assert_eq!("A", logon.msg_type);
+        assert_eq!((Some(1), Some("Y"), Some("user")), (logon.get_u64(34), logon.get(141), logon.get(553)));
+        send_message(&mut stream, "A", 1, false, "98=0|108=30").await;
+        let request = next_message(&mut stream, &mut buffer).await;
+        assert_eq!(("V", Some("coinbase-md-1")), (request.msg_type.as_str(), request.get(262)));
+        send_message(&mut stream, "W", 2, false, "55=BTC-USD|268=2|269=0|270=30000.00|271=1|269=1|270=30001.00|271=2").await;
+        // Skips 3, which the client has to ask for.
+        send_message(&mut stream, "X", 4, false, "268=1|279=0|269=0|55=BTC-USD|270=29999.00|271=4").await;
+        let resend = next_message(&mut stream, &mut buffer).await;
+        assert_eq!(("2", Some(3), Some(0)), (resend.msg_type.as_str(), resend.get_u64(7), resend.get_u64(16)));
+        send_message(&mut stream, "4", 3, true, "123=Y|36=4").await;
+        let resubscribe = next_message(&mut stream, &mut buffer).await;
+        assert_eq!(("V", Some("coinbase-md-2")), (resubscribe.msg_type.as_str(), resubscribe.get(262)));
+        send_message(&mut stream, "1", 5, false, "112=PING").await;
+        let heartbeat = next_message(&mut stream, &mut buffer).await;
+        assert_eq!(("0", Some("PING"), Some(5)), (heartbeat.msg_type.as_str(), heartbeat.get(112), heartbeat.get_u64(34)));
+    });
+
+    let sequence_file = std::env::temp_dir().join(format!("prism-fix-session-{}", std::process::id()));
+    let config = SessionConfig {
+        address: addr.to_string(),
+        tls: false,
+        begin_string: "FIX.4.4".to_string(),
+        sender_comp_id: "CLIENT".to_string(),
+        target_comp_id: "ACCEPTOR".to_string(),
+        heartbeat_interval: Duration::from_secs(30),
+        reset_on_logon: true,
+        auth: LogonAuth::Fields(vec![(553, "user".to_string()), (554, "password".to_string())]),
+        sequence_file: Some(sequence_file.clone()),
+    };
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let adapter = RecordingAdapter::default();
+    let mut client = FIXClient::new(config, MarketData::new("coinbase", Arc::new(registry)), adapter.clone(), ReconnectPolicy::default());
+    tokio::select! {
+        _ = client.run() => panic!("Client exited"),
+        result = acceptor => result.unwrap(),
+    }
+
+    let events = adapter.events.lock().unwrap();
+    assert_eq!(2, events.len());
+    assert!(matches!(&events[0], (venue, FeedEvent::Snapshot { pair, snapshot }) if venue == "coinbase" && pair == "BTC-USD" && snapshot.asks[0].level == 3000100));
+    assert!(matches!(&events[1].1, FeedEvent::Delta { changes, .. } if changes[0].price_level.level == 2999900));
+    assert_eq!("6 6\n", std::fs::read_to_string(&sequence_file).unwrap());
+    let _ = std::fs::remove_file(&sequence_file);
+}
@@ -1,19 +1,29 @@
 use serde::{Deserialize, Deserializer, de::{Visitor, SeqAccess}};
-use serde_json_core::de::Error;
 
-pub enum Message {
-    Snapshot { content: Result<(Snapshot, usize), Error> },
-    Update { content: Result<(Update, usize), Error> },
+use crate::order_book::data_types::to_level;
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Message<'a> {
+    #[serde(rename = "type")]
+    pub msg_type: &'a str,
+    pub symbol: Option<&'a str>,
 }
 
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct Snapshot {
-    pub changes: Box<heapless::Vec<Change, 16384>>,
+    pub changes: Vec<Change>,
 }
 
 #[derive(Deserialize, Debug, PartialEq)]
 pub struct Update {
-    pub changes: heapless::Vec<Change, 16>,
+    pub changes: Vec<Change>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Trade<'a> {
+    pub price: &'a str,
+    pub quantity: &'a str,
+    pub side: Side,
+    pub timestamp: i64,
 }
 
 #[derive(Debug, PartialEq)]
@@ -59,13 +69,13 @@ impl<'de> Visitor<'de> for ChangeVisitor {
         A: SeqAccess<'de>,
     {
         let side = seq.next_element().unwrap().unwrap();
-        let level = (seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap() * 100.) as usize;
+        let level = to_level(seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap());
         let amount = seq.next_element::<&str>().unwrap().unwrap().parse::<f64>().unwrap();
         Ok(Change {
-            side: side,
+            side,
             price_level: PriceLevel {
-                level: level,
-                amount: amount,
+                level,
+                amount,
                 sequence: 0,
             },
         })
@@ -1,107 +1,184 @@
-use std::{time::Duration, sync::Arc};
+use std::sync::Arc;
 
-use tokio::{time::Instant, sync::Mutex};
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
 
-use crate::order_book::{clients::client::WebSocketClient, multi_book::MultiBook};
+use crate::order_book::{self, data_types::PriceLevel};
+use crate::order_book::clients::feed::{DecodeError, ExchangeFeed, FeedEvent};
+use crate::order_book::instruments::InstrumentRegistry;
 
-use super::{gemini_adapter::GeminiAdapter, data_types::{Update, Snapshot, Message}};
+use super::data_types::{Message, Update, Snapshot, Side, Trade};
 
-pub struct GeminiReceiveClient {
-    adapter: GeminiAdapter,
-    client: WebSocketClient,
+struct Symbol {
+    symbol: String,
     pair: heapless::String<8>,
+    init: bool,
 }
 
-impl<'a> GeminiReceiveClient {
-    pub async fn new(multi_book: Arc<Mutex<MultiBook<3, 6>>>, pair: heapless::String<8>) -> GeminiReceiveClient {
-        return GeminiReceiveClient {
-            adapter: GeminiAdapter::new(multi_book).await,
-            client: WebSocketClient::new("wss://api.gemini.com/v2/marketdata".to_string()).await,
-            pair: pair,
+// One connection carries every configured Gemini pair; messages are routed by symbol.
+pub struct GeminiFeed {
+    symbols: Vec<Symbol>,
+    book_channel: String,
+}
+
+impl GeminiFeed {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> GeminiFeed {
+        let listings = registry.venue_listings("gemini");
+        let book_channel = listings.first()
+            .and_then(|(_, listing)| listing.channel("book"))
+            .unwrap_or("l2")
+            .to_string();
+        let symbols = listings.into_iter()
+            .map(|(pair, listing)| Symbol { symbol: listing.symbol.clone(), pair, init: false })
+            .collect();
+        GeminiFeed {
+            symbols,
+            book_channel,
         }
     }
 
-    pub async fn init(&mut self) {
-        let p = match self.pair.as_str() {
-            "ETH-USD" => "ETHUSD",
-            "BTC-USD" => "BTCUSD",
-            "ETH-USDT" => "ETHUSDT",
-            "BTC-USDT" => "BTCUSDT",
-            _ => panic!("Bad pair: {:?}", self.pair),
-        };
-        let sub_message: String = format!("{{\"type\":\"subscribe\",\"subscriptions\":[{{\"name\":\"l2\",\"symbols\":[{:?}]}}]}}", p).to_string();
-        self.client.send(tokio_tungstenite::tungstenite::protocol::Message::Text(sub_message)).await;
-        self.receive().await;
+    fn side(side: &Side) -> order_book::data_types::Side {
+        match side {
+            Side::Sell => order_book::data_types::Side::Sell,
+            Side::Buy => order_book::data_types::Side::Buy,
+        }
     }
 
-    async fn receive(&mut self) {
-        let mut count: usize = 0;
-        let mut total: usize = 0;
-        let mut init = false;
-        while let Some(msg) = self.client.receive().await {
-            let start = Instant::now();
-            match msg {
-                Ok(msg) => {
-                    let res = if init {
-                        Message::Update {content: serde_json_core::from_str::<Update>(&msg.to_text().unwrap())}
-                    } else {
-                        Message::Snapshot {content: serde_json_core::from_str::<Snapshot>(&msg.to_text().unwrap())}
-                    };
-                    match res {
-                        Message::Snapshot {content: snapshot} => {
-                            match snapshot {
-                                Ok((m, _)) => {
-                                    println!("{:?}", msg);
-                                    self.handle_snapshot(m).await;
-                                    init = true;
-                                },
-                                Err(_) => {},
-                            }
-                        },
-                        Message::Update {content: update} => {
-                            match update {
-                                Ok((msg, _)) => {
-                                    let parse_dur = start.elapsed();
-                                    self.handle_update(msg).await;
-                                    let duration = start.elapsed();
-                                    count = count + 1;
-                                    total = total + duration.as_nanos() as usize;
-                                    let avg: f64 = (total as f64) / (count as f64);
-                                    //println!("Gemini: message parsed in {:?}", parse_dur);
-                                    //println!("Gemini: message handled in {:?}", duration);
-                                    //println!("Gemini: average message handle time for {:?} messages: {:?}", count, Duration::new(0, avg as u32));
-                                },
-                                Err(_) => (),
-                            }
-                        },
-
-                    }
-                },
-                Err(err) => {
-                    println!("Gemini: {:?}\nAttempting reset.", err);
-                    return
-                }
-            }
+    fn snapshot(pair: &heapless::String<8>, snapshot: Snapshot, events: &mut Vec<FeedEvent>) {
+        let mut initial_book = order_book::data_types::Snapshot::new();
+        for change in snapshot.changes.iter() {
+            let p = &change.price_level;
+            let level = PriceLevel {level: p.level, amount: p.amount, sequence: 0};
+            let _ = match change.side {
+                Side::Buy => initial_book.bids.push(level),
+                Side::Sell => initial_book.asks.push(level),
+            };
         }
+        events.push(FeedEvent::Snapshot { pair: pair.clone(), snapshot: initial_book });
     }
 
-    async fn handle_snapshot(&mut self, snapshot: Snapshot) {
-        self.adapter.init_order_book(snapshot).await;
+    // An update with more changes than a delta holds goes out as several deltas.
+    fn update(pair: &heapless::String<8>, update: Update, events: &mut Vec<FeedEvent>) {
+        let mut changes = heapless::Vec::new();
+        for change in update.changes.iter() {
+            let change = order_book::data_types::Change {
+                side: GeminiFeed::side(&change.side),
+                price_level: PriceLevel {level: change.price_level.level, amount: change.price_level.amount, sequence: 0}
+            };
+            if let Err(change) = changes.push(change) {
+                events.push(FeedEvent::Delta { pair: pair.clone(), changes: std::mem::take(&mut changes) });
+                let _ = changes.push(change);
+            }
+        }
+        events.push(FeedEvent::Delta { pair: pair.clone(), changes });
     }
 
-    async fn handle_update(&mut self, update: Update) {
-        self.adapter.update(update).await;
+    fn trade(pair: &heapless::String<8>, trade: Trade, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let price = trade.price.parse::<f64>().map_err(|e| DecodeError::Unexpected(e.to_string()))?;
+        let size = trade.quantity.parse::<f64>().map_err(|e| DecodeError::Unexpected(e.to_string()))?;
+        events.push(FeedEvent::Trade {
+            pair: pair.clone(),
+            trade: order_book::data_types::Match { side: GeminiFeed::side(&trade.side), size, price: order_book::data_types::to_level(price) },
+            time: Some(trade.timestamp * 1_000_000),
+        });
+        Ok(())
     }
 }
 
-pub struct GeminiSendClient {
-    client: WebSocketClient,
-}
+impl ExchangeFeed for GeminiFeed {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
 
-impl GeminiSendClient {
-    pub async fn new() -> Self {
-        return GeminiSendClient {
-            client: WebSocketClient::new("wss://api.gemini.com/v2/marketdata/".to_string()).await
+    fn url(&self) -> String {
+        "wss://api.gemini.com/v2/marketdata".to_string()
+    }
+
+    fn subscriptions(&self) -> Vec<WsMessage> {
+        if self.symbols.is_empty() {
+            println!("Gemini: no listings configured");
+            return Vec::new();
         }
+        let symbols: Vec<String> = self.symbols.iter().map(|s| format!("{:?}", s.symbol)).collect();
+        let sub_message: String = format!(
+            "{{\"type\":\"subscribe\",\"subscriptions\":[{{\"name\":{:?},\"symbols\":[{}]}}]}}",
+            self.book_channel,
+            symbols.join(","),
+        ).to_string();
+        vec![WsMessage::Text(sub_message)]
     }
-}
\ No newline at end of file
+
+    // The first `l2_updates` message for a symbol is its full book; later ones are deltas.
+    fn decode(&mut self, frame: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let (message, _) = serde_json_core::from_str::<Message>(frame)?;
+        let symbol = match message.symbol.and_then(|s| self.symbols.iter_mut().find(|x| x.symbol == s)) {
+            Some(symbol) => symbol,
+            None => return Ok(()),
+        };
+        match message.msg_type {
+            "l2_updates" => {
+                if symbol.init {
+                    let (update, _) = serde_json_core::from_str::<Update>(frame)?;
+                    GeminiFeed::update(&symbol.pair, update, events);
+                } else {
+                    let (snapshot, _) = serde_json_core::from_str::<Snapshot>(frame)?;
+                    GeminiFeed::snapshot(&symbol.pair, snapshot, events);
+                    symbol.init = true;
+                }
+            },
+            "trade" => {
+                let (trade, _) = serde_json_core::from_str::<Trade>(frame)?;
+                GeminiFeed::trade(&symbol.pair, trade, events)?;
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        for symbol in self.symbols.iter_mut() {
+            symbol.init = false;
+        }
+    }
+}
+
+#[test]
+fn test_decode_routes_symbols() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = GeminiFeed::new(Arc::new(registry));
+    let subscription = match &feed.subscriptions()[0] {
+        WsMessage::Text(text) => text.clone(),
+        other => panic!("Expected text subscription, got {:?}", other),
+    };
+    assert!(subscription.contains("\"ETHUSD\"") && subscription.contains("\"BTCUSD\""));
+    let mut events = Vec::new();
+    feed.decode(r#"{"type":"l2_updates","symbol":"BTCUSD","changes":[["buy","30000.00","1.5"],["sell","30001.00","2.0"]],"trades":[]}"#, &mut events).unwrap();
+    feed.decode(r#"{"type":"l2_updates","symbol":"ETHUSD","changes":[["buy","1897.10","3.0"],["sell","1897.20","4.0"]],"trades":[]}"#, &mut events).unwrap();
+    feed.decode(r#"{"type":"l2_updates","symbol":"BTCUSD","changes":[["buy","30000.00","0"]]}"#, &mut events).unwrap();
+    feed.decode(r#"{"type":"trade","symbol":"ETHUSD","event_id":1,"timestamp":1689025543609,"price":"1897.20","quantity":"0.5","side":"buy"}"#, &mut events).unwrap();
+    feed.decode(r#"{"type":"heartbeat","timestamp":1689025543609}"#, &mut events).unwrap();
+    assert_eq!(4, events.len());
+    assert!(matches!(&events[0], FeedEvent::Snapshot { pair, snapshot } if pair == "BTC-USD" && snapshot.bids[0].level == 3000000));
+    assert!(matches!(&events[1], FeedEvent::Snapshot { pair, .. } if pair == "ETH-USD"));
+    assert!(matches!(&events[2], FeedEvent::Delta { pair, changes } if pair == "BTC-USD" && changes[0].price_level.amount == 0.0));
+    assert!(matches!(&events[3], FeedEvent::Trade { pair, time: Some(1689025543609000000), .. } if pair == "ETH-USD"));
+    // 1.13 is 112.99999999999999 hundredths in floating point.
+    feed.decode(r#"{"type":"trade","symbol":"ETHUSD","event_id":2,"timestamp":1689025543610,"price":"1.13","quantity":"0.5","side":"sell"}"#, &mut events).unwrap();
+    assert!(matches!(&events[4], FeedEvent::Trade { trade, .. } if trade.price == 113));
+}
+
+#[test]
+fn test_large_update_split_into_deltas() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = GeminiFeed::new(Arc::new(registry));
+    let mut events = Vec::new();
+    feed.decode(r#"{"type":"l2_updates","symbol":"BTCUSD","changes":[["buy","30000.00","1.5"]]}"#, &mut events).unwrap();
+    let changes: Vec<String> = (0..600).map(|i| format!("[\"sell\",\"{}.00\",\"1.0\"]", 30001 + i)).collect();
+    let update = format!("{{\"type\":\"l2_updates\",\"symbol\":\"BTCUSD\",\"changes\":[{}]}}", changes.join(","));
+    feed.decode(&update, &mut events).unwrap();
+    let sizes: Vec<usize> = events[1..].iter().map(|event| match event {
+        FeedEvent::Delta { changes, .. } => changes.len(),
+        other => panic!("Expected delta, got {:?}", other),
+    }).collect();
+    assert_eq!(vec![512, 88], sizes);
+    assert!(matches!(&events[2], FeedEvent::Delta { changes, .. } if changes[87].price_level.level == 3060000));
+}
@@ -1,3 +1,2 @@
-pub mod gemini_adapter;
 pub mod gemini_client;
 pub mod data_types;
\ No newline at end of file
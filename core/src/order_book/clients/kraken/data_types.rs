@@ -1,6 +1,7 @@
 use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Deserializer, de::{Visitor, SeqAccess}};
 
+use crate::order_book::data_types::to_level;
 #[derive(Debug)]
 pub enum Message {
     Single { content: Content },
@@ -10,9 +11,9 @@ pub enum Message {
 #[derive(Deserialize, Debug, Default, PartialEq)]
 pub struct Content {
     #[serde(alias = "as", alias = "a")]
-    pub asks: Option<heapless::Vec<PriceLevel, 2048>>,
+    pub asks: Option<Vec<PriceLevel>>,
     #[serde(alias = "bs", alias = "b")]
-    pub bids: Option<heapless::Vec<PriceLevel, 2048>>,
+    pub bids: Option<Vec<PriceLevel>>,
     #[serde(rename = "c")]
     checksum: Option<heapless::String<32>>,
 }
@@ -108,19 +109,16 @@ impl<'de> Visitor<'de> for PriceLevelVisitor {
     where
         A: SeqAccess<'de>,
     {
-        let level = (seq.next_element::<heapless::String<32>>().unwrap().unwrap().parse::<f64>().unwrap() * 100.) as usize;
+        let level = to_level(seq.next_element::<heapless::String<32>>().unwrap().unwrap().parse::<f64>().unwrap());
         let amount = seq.next_element::<heapless::String<32>>().unwrap().unwrap().parse::<f64>().unwrap();
-        let timestamp_float = seq.next_element::<heapless::String<32>>().unwrap().unwrap().parse::<f64>().unwrap() * 1000000 as f64;
-        let timestamp = Utc.timestamp_nanos((timestamp_float * (1000 as f64)) as i64);
+        let timestamp_float = seq.next_element::<heapless::String<32>>().unwrap().unwrap().parse::<f64>().unwrap() * 1000000_f64;
+        let timestamp = Utc.timestamp_nanos((timestamp_float * 1000_f64) as i64);
         let rep_opt = seq.next_element::<heapless::String<32>>().unwrap();
-        let republish = match rep_opt {
-            Some(_) => true,
-            None => false,
-        };
+        let republish = rep_opt.is_some();
         Ok(PriceLevel {
-            level: level,
-            amount: amount,
-            timestamp: timestamp,
+            level,
+            amount,
+            timestamp,
             sequence: 0,
             republished: republish,
         })
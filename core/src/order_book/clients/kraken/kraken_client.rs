@@ -1,110 +1,181 @@
-use std::{time::Duration, sync::Arc};
+use std::sync::Arc;
 
 use serde_json::Value;
-use tokio::{time::Instant, sync::Mutex};
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
 
-use crate::order_book::{clients::{client::WebSocketClient}, multi_book::MultiBook};
+use crate::order_book::clients::feed::{DecodeError, ExchangeFeed, FeedEvent};
+use crate::order_book::instruments::InstrumentRegistry;
+use crate::order_book::data_types::{PriceLevel, Snapshot, Change, Side};
 
-use super::{kraken_adapter::KrakenAdapter, data_types::{Message}};
+use super::data_types::{Content, Message};
 
-pub struct KrakenReceiveClient {
-    adapter: KrakenAdapter,
-    client: WebSocketClient,
+// One Kraken pair on the shared connection; the first book message for it is the snapshot.
+struct Symbol {
+    symbol: String,
     pair: heapless::String<8>,
+    init: bool,
 }
 
-impl<'a> KrakenReceiveClient {
-    pub async fn new(multi_book: Arc<Mutex<MultiBook<3, 6>>>, pair: heapless::String<8>) -> KrakenReceiveClient {
-        return KrakenReceiveClient {
-            adapter: KrakenAdapter::new(multi_book).await,
-            client: WebSocketClient::new("wss://ws.kraken.com".to_string()).await,
-            pair: pair,
+pub struct KrakenFeed {
+    symbols: Vec<Symbol>,
+    book_channel: String,
+}
+
+impl KrakenFeed {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> KrakenFeed {
+        let listings = registry.venue_listings("kraken");
+        let book_channel = listings.first()
+            .and_then(|(_, listing)| listing.channel("book"))
+            .unwrap_or("book")
+            .to_string();
+        KrakenFeed {
+            symbols: listings.iter().map(|(pair, listing)| Symbol {
+                symbol: listing.symbol.clone(),
+                pair: pair.clone(),
+                init: false,
+            }).collect(),
+            book_channel,
         }
     }
 
-    pub async fn init(&mut self) {
-        let p = match self.pair.as_str() {
-            "ETH-USD" => "ETH/USD",
-            "BTC-USD" => "XBT/USD",
-            "BTC-USDT" => "XBT/USDT",
-            "ETH-USDT" => "ETH/USDT",
-            _ => panic!("Bad pair: {:?}", self.pair),
-        };
-        let sub_message: String = format!("{{\"event\": \"subscribe\",\"pair\": [{:?}],\"subscription\": {{\"name\": \"book\", \"depth\": 1000}}}}", p).to_string();
-        self.client.send(tokio_tungstenite::tungstenite::protocol::Message::Text(sub_message)).await;
-        self.receive().await;
+    fn contents(message: Message) -> [Option<Content>; 2] {
+        match message {
+            Message::Single{content} => [Some(content), None],
+            Message::Double{content_1, content_2} => [Some(content_1), Some(content_2)],
+        }
     }
 
-    async fn receive(&mut self) {
-        let mut count: usize = 0;
-        let mut total: usize = 0;
-        let mut init = false;
-        while let Some(msg) = self.client.receive().await {
-            let start = Instant::now();
-            match msg {
-                Ok(msg) => {
-                    match serde_json::from_str::<Value>(&msg.to_text().unwrap()) {
-                        Ok(res) => {
-                            match res {
-                                Value::Array(arr) => {
-                                    let message: Message;
-                                    if arr.len() == 4 {
-                                        message = Message::Single {
-                                            content: serde_json::from_value(arr.get(1).unwrap().clone()).unwrap(),
-                                        }
-                                    } else if arr.len() == 5 {
-                                        message = Message::Double {
-                                            content_1: serde_json::from_value(arr.get(1).unwrap().clone()).unwrap(),
-                                            content_2: serde_json::from_value(arr.get(2).unwrap().clone()).unwrap(),
-                                        }
-                                    } else {
-                                        panic!("{:?}, {:?}", arr.len(), arr);
-                                    }
-                                    if !init {
-                                        self.handle_snapshot(message).await;
-                                        init = true;
-                                    } else {
-                                        self.handle_update(message).await;
-                                        let duration = start.elapsed();
-                                        count = count + 1;
-                                        total = total + duration.as_nanos() as usize;
-                                        let avg: f64 = (total as f64) / (count as f64);
-                                        if count % 1000 == 1 {
-                                            println!("Kraken avg. sent to handled time: {:?}", Duration::new(0, avg as u32));
-                                        }
-                                    }
-                                },
-                                _ => (),
-                            }
-                        },
-                        Err(e) => println!("Kraken parsing error for {:?}: {:?}", msg, e)
-                    }
-                },
-                Err(err) => {
-                    println!("Kraken: {:?}\nAttempting reset.", err);
-                    return
+    fn snapshot(pair: heapless::String<8>, snapshot: Message, events: &mut Vec<FeedEvent>) {
+        let mut initial_book = Snapshot::new();
+        for c in KrakenFeed::contents(snapshot).into_iter().flatten() {
+            for bid in c.bids.iter().flatten() {
+                if !bid.republished {
+                    let _ = initial_book.bids.push(PriceLevel {level: bid.level, amount: bid.amount, sequence: 0});
+                }
+            }
+            for ask in c.asks.iter().flatten() {
+                if !ask.republished {
+                    let _ = initial_book.asks.push(PriceLevel {level: ask.level, amount: ask.amount, sequence: 0});
                 }
             }
         }
+        events.push(FeedEvent::Snapshot { pair, snapshot: initial_book });
     }
 
-    async fn handle_snapshot(&mut self, snapshot: Message) {
-        self.adapter.init_order_book(snapshot).await;
+    fn update(pair: heapless::String<8>, update: Message, events: &mut Vec<FeedEvent>) {
+        let mut changes = heapless::Vec::<Change, 512>::new();
+        for u in KrakenFeed::contents(update).into_iter().flatten() {
+            for bid in u.bids.iter().flatten() {
+                let _ = changes.push(Change{
+                    side: Side::Buy,
+                    price_level: PriceLevel {level: bid.level, amount: bid.amount, sequence: 0}});
+            }
+            for ask in u.asks.iter().flatten() {
+                let _ = changes.push(Change{
+                    side: Side::Sell,
+                    price_level: PriceLevel {level: ask.level, amount: ask.amount, sequence: 0}});
+            }
+        }
+        events.push(FeedEvent::Delta { pair, changes });
     }
+}
 
-    async fn handle_update(&mut self, update: Message) {
-        self.adapter.update(update).await;
+impl ExchangeFeed for KrakenFeed {
+    fn name(&self) -> &'static str {
+        "kraken"
     }
-}
 
-pub struct KrakenSendClient {
-    client: WebSocketClient,
-}
+    fn url(&self) -> String {
+        "wss://ws.kraken.com".to_string()
+    }
 
-impl KrakenSendClient {
-    pub async fn new() -> Self {
-        return KrakenSendClient {
-            client: WebSocketClient::new("wss://ws.kraken.com".to_string()).await
+    fn subscriptions(&self) -> Vec<WsMessage> {
+        if self.symbols.is_empty() {
+            println!("Kraken: no listings configured");
+            return Vec::new();
         }
+        let pairs: Vec<String> = self.symbols.iter().map(|s| format!("{:?}", s.symbol)).collect();
+        let sub_message: String = format!(
+            "{{\"event\": \"subscribe\",\"pair\": [{}],\"subscription\": {{\"name\": {:?}, \"depth\": 1000}}}}",
+            pairs.join(","),
+            self.book_channel,
+        ).to_string();
+        vec![WsMessage::Text(sub_message)]
     }
-}
\ No newline at end of file
+
+    fn decode(&mut self, frame: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let arr = match serde_json::from_str::<Value>(frame)? {
+            Value::Array(arr) => arr,
+            _ => return Ok(()),
+        };
+        let message = if arr.len() == 4 {
+            Message::Single {
+                content: serde_json::from_value(arr[1].clone())?,
+            }
+        } else if arr.len() == 5 {
+            Message::Double {
+                content_1: serde_json::from_value(arr[1].clone())?,
+                content_2: serde_json::from_value(arr[2].clone())?,
+            }
+        } else {
+            return Err(DecodeError::Unexpected(format!("{:?} element book message", arr.len())));
+        };
+        // The pair name is always the last element of a book message.
+        let name = arr.last().and_then(|v| v.as_str()).unwrap_or("");
+        let symbol = match self.symbols.iter_mut().find(|s| s.symbol == name) {
+            Some(symbol) => symbol,
+            None => return Ok(()),
+        };
+        if !symbol.init {
+            KrakenFeed::snapshot(symbol.pair.clone(), message, events);
+            symbol.init = true;
+        } else {
+            KrakenFeed::update(symbol.pair.clone(), message, events);
+        }
+        Ok(())
+    }
+
+    fn keepalive(&self) -> Option<WsMessage> {
+        Some(WsMessage::Text("{\"event\": \"ping\"}".to_string()))
+    }
+
+    fn reset(&mut self) {
+        for symbol in self.symbols.iter_mut() {
+            symbol.init = false;
+        }
+    }
+}
+
+#[test]
+fn test_decode_snapshot_then_delta() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = KrakenFeed::new(Arc::new(registry));
+    let mut events = Vec::new();
+    let snapshot = "[560,{\"as\":[[\"1897.20000\",\"1.00000000\",\"1689025543.609620\"]],\"bs\":[[\"1897.10000\",\"2.00000000\",\"1689025543.609620\"]]},\"book-1000\",\"ETH/USD\"]";
+    feed.decode(snapshot, &mut events).unwrap();
+    match &events[0] {
+        FeedEvent::Snapshot { pair, snapshot } => {
+            assert_eq!("ETH-USD", pair.as_str());
+            assert_eq!(189710, snapshot.bids[0].level);
+            assert_eq!(189720, snapshot.asks[0].level);
+        },
+        other => panic!("Expected snapshot, got {:?}", other),
+    }
+    events.clear();
+    let update = "[560,{\"a\":[[\"1897.20000\",\"0.00000000\",\"1689025543.609620\"]]},{\"b\":[[\"1897.00000\",\"0.29882788\",\"1689026888.932974\"]],\"c\":\"1364434776\"},\"book-1000\",\"ETH/USD\"]";
+    feed.decode(update, &mut events).unwrap();
+    match &events[0] {
+        FeedEvent::Delta { changes, .. } => {
+            assert_eq!(2, changes.len());
+            assert_eq!(Side::Sell, changes[0].side);
+            assert_eq!(0.0, changes[0].price_level.amount);
+            assert_eq!(Side::Buy, changes[1].side);
+        },
+        other => panic!("Expected delta, got {:?}", other),
+    }
+    assert!(feed.decode("{\"event\":\"heartbeat\"}", &mut events).is_ok());
+    events.clear();
+    let other = "[561,{\"as\":[[\"30001.00000\",\"1.00000000\",\"1689025543.609620\"]],\"bs\":[[\"30000.00000\",\"2.00000000\",\"1689025543.609620\"]]},\"book-1000\",\"XBT/USD\"]";
+    feed.decode(other, &mut events).unwrap();
+    assert!(matches!(&events[0], FeedEvent::Snapshot { pair, .. } if pair == "BTC-USD"));
+}
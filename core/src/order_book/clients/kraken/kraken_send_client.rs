@@ -0,0 +1,370 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256, Sha512};
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
+
+use crate::order_book::clients::client::{Heartbeat, WebSocketClient};
+use crate::order_book::clients::clock;
+use crate::order_book::clients::orders::{OrderClient, OrderError, OrderEvent, OrderRequest, TimeInForce};
+use crate::order_book::data_types::{Side, to_level};
+use crate::order_book::instruments::InstrumentRegistry;
+
+use super::private_data_types::{ChannelMessage, OpenOrder, OwnTrade, RestResponse, Status, WebSocketsToken};
+
+const TOKEN_PATH: &str = "/0/private/GetWebSocketsToken";
+
+pub struct KrakenCredentials {
+    pub api_key: String,
+    // Base64, as Kraken issues it.
+    pub secret: String,
+}
+
+// Order entry over Kraken's authenticated WebSocket. The connection is opened on first use with
+// a token from the signed REST API, and subscribes to ownTrades and openOrders so fills and
+// cancels show up as events. Replies to requests are matched by reqid; anything else that
+// arrives meanwhile is kept for `next_event`.
+pub struct KrakenSendClient {
+    http: reqwest::Client,
+    rest_url: String,
+    ws_url: String,
+    credentials: KrakenCredentials,
+    registry: Arc<InstrumentRegistry>,
+    session: Option<(WebSocketClient, String)>,
+    next_reqid: u64,
+    events: VecDeque<OrderEvent>,
+}
+
+impl KrakenSendClient {
+    pub fn new(credentials: KrakenCredentials, registry: Arc<InstrumentRegistry>) -> KrakenSendClient {
+        KrakenSendClient {
+            http: reqwest::Client::new(),
+            rest_url: "https://api.kraken.com".to_string(),
+            ws_url: "wss://ws-auth.kraken.com".to_string(),
+            credentials,
+            registry,
+            session: None,
+            next_reqid: 1,
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn set_endpoints(&mut self, ws_url: String, rest_url: String) {
+        self.ws_url = ws_url;
+        self.rest_url = rest_url;
+    }
+
+    // HMAC-SHA512 of the path and SHA256(nonce + post data) under the decoded secret.
+    fn sign(&self, path: &str, nonce: &str, post_data: &str) -> String {
+        let key = STANDARD.decode(&self.credentials.secret).unwrap_or_else(|_| self.credentials.secret.as_bytes().to_vec());
+        let mut mac = Hmac::<Sha512>::new_from_slice(&key).expect("HMAC accepts any key length");
+        mac.update(path.as_bytes());
+        mac.update(&Sha256::digest(format!("{}{}", nonce, post_data).as_bytes()));
+        STANDARD.encode(mac.finalize().into_bytes())
+    }
+
+    pub async fn websocket_token(&self) -> Result<String, OrderError> {
+        let nonce = (clock::now() / 1000).to_string();
+        let post_data = format!("nonce={}", nonce);
+        let body = self.http.post(format!("{}{}", self.rest_url, TOKEN_PATH))
+            .header("API-Key", &self.credentials.api_key)
+            .header("API-Sign", self.sign(TOKEN_PATH, &nonce, &post_data))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(post_data)
+            .send()
+            .await?
+            .text()
+            .await?;
+        match serde_json::from_str::<RestResponse<WebSocketsToken>>(&body) {
+            Ok(RestResponse { result: Some(result), .. }) => Ok(result.token),
+            _ => Err(OrderError::Unexpected(body)),
+        }
+    }
+
+    async fn connect(&mut self) -> Result<(), OrderError> {
+        let token = self.websocket_token().await?;
+        let mut client = WebSocketClient::new(self.ws_url.clone()).await?;
+        client.set_heartbeat(Heartbeat::default(), None);
+        for subscription in [
+            serde_json::json!({"name": "ownTrades", "token": token, "snapshot": false}),
+            serde_json::json!({"name": "openOrders", "token": token}),
+        ] {
+            client.send(WsMessage::Text(serde_json::json!({"event": "subscribe", "subscription": subscription}).to_string())).await;
+        }
+        self.session = Some((client, token));
+        Ok(())
+    }
+
+    // The next fill or cancel reported by the venue.
+    pub async fn next_event(&mut self) -> Result<OrderEvent, OrderError> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(event);
+            }
+            if let Some(status) = self.receive().await? {
+                println!("Kraken: unsolicited {:?}", status);
+            }
+        }
+    }
+
+    // Sends `request` with a fresh reqid and the token, and waits for its status.
+    async fn request(&mut self, mut request: serde_json::Value) -> Result<Status, OrderError> {
+        if self.session.is_none() {
+            self.connect().await?;
+        }
+        let reqid = self.next_reqid;
+        self.next_reqid += 1;
+        if let Some((client, token)) = self.session.as_mut() {
+            request["reqid"] = reqid.into();
+            request["token"] = token.clone().into();
+            client.send(WsMessage::Text(request.to_string())).await;
+        }
+        loop {
+            match self.receive().await? {
+                Some(status) if status.reqid == Some(reqid) => return Ok(status),
+                Some(status) if status.status.as_deref() == Some("error") => println!("Kraken: {:?}", status),
+                _ => (),
+            }
+        }
+    }
+
+    // Reads one frame, queueing the events it carries and returning it if it is a status. The
+    // session is dropped on any connection error so the next call reconnects.
+    async fn receive(&mut self) -> Result<Option<Status>, OrderError> {
+        if self.session.is_none() {
+            self.connect().await?;
+        }
+        let msg = match self.session.as_mut() {
+            Some((client, _)) => client.receive().await,
+            None => None,
+        };
+        let text = match msg {
+            Some(Ok(WsMessage::Text(text))) => text,
+            Some(Ok(_)) => return Ok(None),
+            Some(Err(err)) => {
+                self.session = None;
+                return Err(err.into());
+            },
+            None => {
+                self.session = None;
+                return Err(OrderError::Unexpected("order connection closed".to_string()));
+            },
+        };
+        if text.starts_with('{') {
+            return serde_json::from_str::<Status>(&text).map(Some).map_err(|_| OrderError::Unexpected(text));
+        }
+        let channel = serde_json::from_str::<(serde::de::IgnoredAny, String, serde::de::IgnoredAny)>(&text)
+            .map_err(|_| OrderError::Unexpected(text.clone()))?.1;
+        match channel.as_str() {
+            "ownTrades" => {
+                let ChannelMessage(trades, _, _) = serde_json::from_str::<ChannelMessage<OwnTrade>>(&text).map_err(|_| OrderError::Unexpected(text.clone()))?;
+                for trade in trades.into_iter().flat_map(|trades| trades.into_values()) {
+                    let event = self.fill(trade).ok_or_else(|| OrderError::Unexpected(text.clone()))?;
+                    self.events.push_back(event);
+                }
+            },
+            "openOrders" => {
+                let ChannelMessage(orders, _, _) = serde_json::from_str::<ChannelMessage<OpenOrder>>(&text).map_err(|_| OrderError::Unexpected(text.clone()))?;
+                for (order_id, order) in orders.into_iter().flat_map(|orders| orders.into_iter()) {
+                    if matches!(order.status.as_deref(), Some("canceled") | Some("expired")) {
+                        self.events.push_back(OrderEvent::Canceled { order_id });
+                    }
+                }
+            },
+            _ => (),
+        }
+        Ok(None)
+    }
+
+    fn fill(&self, trade: OwnTrade) -> Option<OrderEvent> {
+        let pair = self.registry.venue_listings("kraken").into_iter().find(|(_, listing)| listing.symbol == trade.pair)?.0;
+        Some(OrderEvent::Fill {
+            order_id: trade.ordertxid,
+            pair,
+            side: match trade.side.as_str() {
+                "buy" => Side::Buy,
+                "sell" => Side::Sell,
+                _ => return None,
+            },
+            price: to_level(trade.price.parse::<f64>().ok()?),
+            size: trade.vol.parse::<f64>().ok()?,
+            fee: trade.fee.parse::<f64>().ok()?,
+        })
+    }
+}
+
+impl OrderClient for KrakenSendClient {
+    async fn place(&mut self, order: OrderRequest) -> Result<Vec<OrderEvent>, OrderError> {
+        let listing = self.registry.listing("kraken", &order.pair).ok_or_else(|| OrderError::Pair(order.pair.to_string()))?;
+        let mut request = serde_json::json!({
+            "event": "addOrder",
+            "ordertype": "limit",
+            "type": order.side,
+            "pair": listing.symbol,
+            // Kraken refuses prices and volumes with more decimals than the pair's tick and lot.
+            "price": listing.format_price(order.price),
+            "volume": listing.format_qty(order.size),
+        });
+        if order.time_in_force == TimeInForce::Ioc {
+            request["timeinforce"] = "IOC".into();
+        }
+        let status = self.request(request).await?;
+        match (status.status.as_deref(), status.txid) {
+            (Some("ok"), Some(order_id)) => Ok(vec![OrderEvent::Accepted { client_id: order.client_id, order_id }]),
+            _ => Ok(vec![OrderEvent::Rejected { client_id: order.client_id, reason: status.error_message.unwrap_or_default() }]),
+        }
+    }
+
+    async fn cancel(&mut self, order_id: &str) -> Result<Vec<OrderEvent>, OrderError> {
+        let status = self.request(serde_json::json!({"event": "cancelOrder", "txid": [order_id]})).await?;
+        match status.status.as_deref() {
+            Some("ok") => Ok(vec![OrderEvent::Canceled { order_id: order_id.to_string() }]),
+            _ => Ok(vec![OrderEvent::CancelRejected { order_id: order_id.to_string(), reason: status.error_message.unwrap_or_default() }]),
+        }
+    }
+
+    // editOrder replaces the order with a new one under a new txid.
+    async fn replace(&mut self, order_id: &str, order: OrderRequest) -> Result<Vec<OrderEvent>, OrderError> {
+        let listing = self.registry.listing("kraken", &order.pair).ok_or_else(|| OrderError::Pair(order.pair.to_string()))?;
+        let request = serde_json::json!({
+            "event": "editOrder",
+            "orderid": order_id,
+            "pair": listing.symbol,
+            "price": listing.format_price(order.price),
+            "volume": listing.format_qty(order.size),
+        });
+        let status = self.request(request).await?;
+        match (status.status.as_deref(), status.txid) {
+            (Some("ok"), Some(new_id)) => Ok(vec![
+                OrderEvent::Canceled { order_id: status.originaltxid.unwrap_or(order_id.to_string()) },
+                OrderEvent::Accepted { client_id: order.client_id, order_id: new_id },
+            ]),
+            _ => Ok(vec![OrderEvent::CancelRejected { order_id: order_id.to_string(), reason: status.error_message.unwrap_or_default() }]),
+        }
+    }
+}
+
+#[cfg(test)]
+const TEST_SECRET: &str = "a3Jha2VuLXNlY3JldA==";
+
+// Kraken's REST token endpoint, answering only correctly signed requests.
+#[cfg(test)]
+fn kraken_token_mock(request: &crate::order_book::clients::test_support::HttpRequest) -> (u16, String) {
+    let nonce = request.body.strip_prefix("nonce=").unwrap_or("");
+    let mut mac = Hmac::<Sha512>::new_from_slice(&STANDARD.decode(TEST_SECRET).unwrap()).unwrap();
+    mac.update(request.path.as_bytes());
+    mac.update(&Sha256::digest(format!("{}{}", nonce, request.body).as_bytes()));
+    let signature = request.header("api-sign").and_then(|s| STANDARD.decode(s).ok()).unwrap_or_default();
+    if request.path != TOKEN_PATH || request.header("api-key") != Some("key") || mac.verify_slice(&signature).is_err() {
+        return (200, r#"{"error":["EAPI:Invalid signature"]}"#.to_string());
+    }
+    (200, r#"{"error":[],"result":{"token":"ws-token","expires":900}}"#.to_string())
+}
+
+// Kraken's authenticated socket: acknowledges requests carrying the token, and reports an IOC's
+// fill and the cancel of its remainder.
+#[cfg(test)]
+async fn kraken_ws_mock() -> (std::net::SocketAddr, std::sync::Arc<std::sync::Mutex<Vec<serde_json::Value>>>) {
+    use futures_util::{SinkExt, StreamExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        while let Some(Ok(WsMessage::Text(text))) = ws.next().await {
+            let request: serde_json::Value = serde_json::from_str(&text).unwrap();
+            seen.lock().unwrap().push(request.clone());
+            let token_ok = request["token"] == "ws-token" || request["subscription"]["token"] == "ws-token";
+            let reqid = request["reqid"].clone();
+            let mut replies = Vec::new();
+            match request["event"].as_str().unwrap() {
+                "subscribe" => replies.push(serde_json::json!({"event": "subscriptionStatus", "status": "ok", "subscription": {"name": request["subscription"]["name"]}})),
+                _ if !token_ok => replies.push(serde_json::json!({"event": "error", "reqid": reqid, "status": "error", "errorMessage": "EGeneral:Invalid arguments"})),
+                "addOrder" if request["volume"] == "100.00000000" => replies.push(serde_json::json!({"event": "addOrderStatus", "reqid": reqid, "status": "error", "errorMessage": "EOrder:Insufficient funds"})),
+                "addOrder" => {
+                    // Fills can be reported before the acknowledgement.
+                    if request["timeinforce"] == "IOC" {
+                        replies.push(serde_json::json!([[{"T-1": {"ordertxid": "O-1", "pair": "XBT/USD", "type": "buy", "price": "30000.10000", "vol": "0.25000000", "fee": "1.95000", "cost": "7500.025"}}], "ownTrades", {"sequence": 1}]));
+                        replies.push(serde_json::json!([[{"O-1": {"status": "canceled", "cancel_reason": "IOC"}}], "openOrders", {"sequence": 2}]));
+                    }
+                    replies.push(serde_json::json!({"event": "addOrderStatus", "reqid": reqid, "status": "ok", "txid": "O-1", "descr": "buy"}));
+                },
+                "cancelOrder" if request["txid"][0] == "O-1" => replies.push(serde_json::json!({"event": "cancelOrderStatus", "reqid": reqid, "status": "ok"})),
+                "cancelOrder" => replies.push(serde_json::json!({"event": "cancelOrderStatus", "reqid": reqid, "status": "error", "errorMessage": "EOrder:Unknown order"})),
+                "editOrder" => replies.push(serde_json::json!({"event": "editOrderStatus", "reqid": reqid, "status": "ok", "txid": "O-2", "originaltxid": request["orderid"]})),
+                _ => (),
+            }
+            replies.push(serde_json::json!({"event": "heartbeat"}));
+            for reply in replies {
+                ws.send(WsMessage::Text(reply.to_string())).await.unwrap();
+            }
+        }
+    });
+    (addr, requests)
+}
+
+#[cfg(test)]
+fn kraken_order(size: f64, time_in_force: TimeInForce) -> OrderRequest {
+    OrderRequest {
+        client_id: "c-1".to_string(),
+        pair: heapless::String::from("BTC-USD"),
+        side: Side::Buy,
+        price: 3000010,
+        size,
+        time_in_force,
+    }
+}
+
+#[tokio::test]
+async fn test_kraken_order_entry() {
+    let (rest_addr, rest_requests) = crate::order_book::clients::test_support::serve_rest(kraken_token_mock).await;
+    let (ws_addr, ws_requests) = kraken_ws_mock().await;
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let credentials = KrakenCredentials { api_key: "key".to_string(), secret: TEST_SECRET.to_string() };
+    let mut client = KrakenSendClient::new(credentials, Arc::new(registry));
+    client.set_endpoints(format!("ws://{}", ws_addr), format!("http://{}", rest_addr));
+
+    let events = client.place(kraken_order(1.0, TimeInForce::Gtc)).await.unwrap();
+    assert_eq!(vec![OrderEvent::Accepted { client_id: "c-1".to_string(), order_id: "O-1".to_string() }], events);
+    let events = client.place(kraken_order(100.0, TimeInForce::Gtc)).await.unwrap();
+    assert_eq!(vec![OrderEvent::Rejected { client_id: "c-1".to_string(), reason: "EOrder:Insufficient funds".to_string() }], events);
+    client.place(kraken_order(1.0, TimeInForce::Ioc)).await.unwrap();
+    assert_eq!(OrderEvent::Fill { order_id: "O-1".to_string(), pair: heapless::String::from("BTC-USD"), side: Side::Buy, price: 3000010, size: 0.25, fee: 1.95 }, client.next_event().await.unwrap());
+    assert_eq!(OrderEvent::Canceled { order_id: "O-1".to_string() }, client.next_event().await.unwrap());
+
+    assert_eq!(vec![OrderEvent::Canceled { order_id: "O-1".to_string() }], client.cancel("O-1").await.unwrap());
+    assert_eq!(vec![OrderEvent::CancelRejected { order_id: "O-9".to_string(), reason: "EOrder:Unknown order".to_string() }], client.cancel("O-9").await.unwrap());
+    let events = client.replace("O-1", kraken_order(0.5, TimeInForce::Gtc)).await.unwrap();
+    assert_eq!(vec![
+        OrderEvent::Canceled { order_id: "O-1".to_string() },
+        OrderEvent::Accepted { client_id: "c-1".to_string(), order_id: "O-2".to_string() },
+    ], events);
+
+    // One token for the whole session, and requests shaped as Kraken expects.
+    assert_eq!(1, rest_requests.lock().unwrap().len());
+    let requests = ws_requests.lock().unwrap();
+    assert_eq!(serde_json::json!({"name": "ownTrades", "token": "ws-token", "snapshot": false}), requests[0]["subscription"]);
+    assert_eq!(serde_json::json!({
+        "event": "addOrder", "ordertype": "limit", "type": "buy", "pair": "XBT/USD", "price": "30000.1", "volume": "1.00000000",
+        "reqid": 1, "token": "ws-token",
+    }), requests[2]);
+    assert_eq!("IOC", requests[4]["timeinforce"]);
+    assert_eq!(serde_json::json!(["O-1"]), requests[5]["txid"]);
+    assert_eq!(("O-1", "0.50000000"), (requests[7]["orderid"].as_str().unwrap(), requests[7]["volume"].as_str().unwrap()));
+}
+
+#[tokio::test]
+async fn test_kraken_token_needs_valid_signature() {
+    let (rest_addr, _) = crate::order_book::clients::test_support::serve_rest(kraken_token_mock).await;
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let credentials = KrakenCredentials { api_key: "key".to_string(), secret: "b3RoZXI=".to_string() };
+    let mut client = KrakenSendClient::new(credentials, Arc::new(registry));
+    client.set_endpoints("ws://127.0.0.1:1".to_string(), format!("http://{}", rest_addr));
+    assert!(matches!(client.websocket_token().await, Err(OrderError::Unexpected(body)) if body.contains("Invalid signature")));
+}
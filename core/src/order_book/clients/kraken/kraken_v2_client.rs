@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::Utc;
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
+
+use crate::order_book::clients::feed::{DecodeError, ExchangeFeed, FeedEvent};
+use crate::order_book::data_types::{Change, Match, PriceLevel, Side, Snapshot, to_level};
+use crate::order_book::instruments::{InstrumentRegistry, Listing};
+
+use super::v2_data_types::{Book, ChannelMessage, Frame, MessageType, Trade};
+
+const BOOK_DEPTH: usize = 1000;
+const CHECKSUM_DEPTH: usize = 10;
+
+// Kraken's v2 checksum covers the top of our copy of the book, so we keep one at the venue's own
+// price and quantity precision alongside the shared MultiBook. Prices are keyed in ticks.
+#[derive(Default)]
+struct Mirror {
+    bids: BTreeMap<u64, f64>,
+    asks: BTreeMap<u64, f64>,
+}
+
+impl Mirror {
+    fn side(&mut self, side: Side) -> &mut BTreeMap<u64, f64> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    // Drops levels beyond the subscribed depth, returning their keys.
+    fn truncate(&mut self, side: Side, depth: usize) -> Vec<u64> {
+        let levels = self.side(side);
+        let mut removed = Vec::new();
+        while levels.len() > depth {
+            let worst = match side {
+                Side::Buy => *levels.keys().next().unwrap(),
+                Side::Sell => *levels.keys().next_back().unwrap(),
+            };
+            levels.remove(&worst);
+            removed.push(worst);
+        }
+        removed
+    }
+
+    fn checksum(&self, qty_precision: usize) -> u32 {
+        let mut input = String::new();
+        let asks = self.asks.iter().take(CHECKSUM_DEPTH);
+        let bids = self.bids.iter().rev().take(CHECKSUM_DEPTH);
+        for (ticks, qty) in asks.chain(bids) {
+            input.push_str(&ticks.to_string());
+            let qty = format!("{:.*}", qty_precision, qty).replace('.', "");
+            input.push_str(qty.trim_start_matches('0'));
+        }
+        crc32fast::hash(input.as_bytes())
+    }
+}
+
+// Mirror and sync state for one Kraken symbol on the shared connection.
+struct Symbol {
+    pair: heapless::String<8>,
+    // The v2 API's name for the listing's symbol.
+    symbol: String,
+    listing: Listing,
+    mirror: Mirror,
+    init: bool,
+}
+
+pub struct KrakenV2Feed {
+    symbols: Vec<Symbol>,
+}
+
+impl KrakenV2Feed {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> KrakenV2Feed {
+        KrakenV2Feed {
+            symbols: registry.venue_listings("kraken").into_iter().map(|(pair, listing)| Symbol {
+                pair,
+                symbol: KrakenV2Feed::v2_symbol(&listing.symbol),
+                listing: listing.clone(),
+                mirror: Mirror::default(),
+                init: false,
+            }).collect(),
+        }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> Option<&mut Symbol> {
+        self.symbols.iter_mut().find(|s| s.symbol == symbol)
+    }
+
+    // The listings use v1's asset codes; v2 only knows the common ones.
+    fn v2_symbol(symbol: &str) -> String {
+        symbol.split('/').map(|asset| match asset {
+            "XBT" => "BTC",
+            "XDG" => "DOGE",
+            other => other,
+        }).collect::<Vec<&str>>().join("/")
+    }
+
+    fn ticks(price: f64, price_precision: usize) -> u64 {
+        (price * 10f64.powi(price_precision as i32)).round() as u64
+    }
+
+    fn level(ticks: u64, price_precision: usize) -> usize {
+        to_level(ticks as f64 / 10f64.powi(price_precision as i32))
+    }
+}
+
+impl Symbol {
+    fn book(&mut self, kind: MessageType, book: Book, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let listing = &self.listing;
+        let (price_precision, qty_precision) = (listing.price_precision(), listing.qty_precision());
+        match kind {
+            MessageType::Snapshot => {
+                self.mirror = Mirror::default();
+                let mut initial_book = Snapshot::new();
+                for (side, levels) in [(Side::Buy, &book.bids), (Side::Sell, &book.asks)] {
+                    for level in levels.iter() {
+                        let ticks = KrakenV2Feed::ticks(level.price, price_precision);
+                        self.mirror.side(side).insert(ticks, level.qty);
+                        let price_level = PriceLevel { level: KrakenV2Feed::level(ticks, price_precision), amount: level.qty, sequence: 0 };
+                        let _ = match side {
+                            Side::Buy => initial_book.bids.push(price_level),
+                            Side::Sell => initial_book.asks.push(price_level),
+                        };
+                    }
+                }
+                self.verify(book.checksum, qty_precision)?;
+                self.init = true;
+                events.push(FeedEvent::Snapshot { pair: self.pair.clone(), snapshot: initial_book });
+            },
+            MessageType::Update => {
+                if !self.init {
+                    return Ok(());
+                }
+                let mut changes = heapless::Vec::<Change, 512>::new();
+                for (side, levels) in [(Side::Buy, &book.bids), (Side::Sell, &book.asks)] {
+                    for level in levels.iter() {
+                        let ticks = KrakenV2Feed::ticks(level.price, price_precision);
+                        if level.qty == 0.0 {
+                            self.mirror.side(side).remove(&ticks);
+                        } else {
+                            self.mirror.side(side).insert(ticks, level.qty);
+                        }
+                        let _ = changes.push(Change {
+                            side,
+                            price_level: PriceLevel { level: KrakenV2Feed::level(ticks, price_precision), amount: level.qty, sequence: 0 },
+                        });
+                    }
+                    for ticks in self.mirror.truncate(side, BOOK_DEPTH) {
+                        let _ = changes.push(Change {
+                            side,
+                            price_level: PriceLevel { level: KrakenV2Feed::level(ticks, price_precision), amount: 0.0, sequence: 0 },
+                        });
+                    }
+                }
+                self.verify(book.checksum, qty_precision)?;
+                events.push(FeedEvent::Delta { pair: self.pair.clone(), changes });
+            },
+        }
+        Ok(())
+    }
+
+    fn verify(&mut self, expected: u32, qty_precision: usize) -> Result<(), DecodeError> {
+        let checksum = self.mirror.checksum(qty_precision);
+        if checksum != expected {
+            self.init = false;
+            return Err(DecodeError::OutOfSync(format!("checksum {:?} != {:?}", checksum, expected)));
+        }
+        Ok(())
+    }
+
+    fn trade(&self, trade: Trade, events: &mut Vec<FeedEvent>) {
+        let time = chrono::DateTime::<Utc>::from_str(&trade.timestamp).ok()
+            .map(|t| t.timestamp() * 1_000_000_000 + t.timestamp_subsec_nanos() as i64);
+        events.push(FeedEvent::Trade {
+            pair: self.pair.clone(),
+            trade: Match { side: trade.side, size: trade.qty, price: to_level(trade.price) },
+            time,
+        });
+    }
+}
+
+impl ExchangeFeed for KrakenV2Feed {
+    fn name(&self) -> &'static str {
+        "kraken"
+    }
+
+    fn url(&self) -> String {
+        "wss://ws.kraken.com/v2".to_string()
+    }
+
+    fn subscriptions(&self) -> Vec<WsMessage> {
+        let listing = match self.symbols.first() {
+            Some(symbol) => &symbol.listing,
+            None => {
+                println!("Kraken: no listings configured");
+                return Vec::new();
+            },
+        };
+        let symbols: Vec<String> = self.symbols.iter().map(|s| format!("{:?}", s.symbol)).collect();
+        let book = format!(
+            "{{\"method\": \"subscribe\", \"params\": {{\"channel\": {:?}, \"symbol\": [{}], \"depth\": {}, \"snapshot\": true}}}}",
+            listing.channel("book").unwrap_or("book"),
+            symbols.join(","),
+            BOOK_DEPTH,
+        );
+        let trades = format!(
+            "{{\"method\": \"subscribe\", \"params\": {{\"channel\": {:?}, \"symbol\": [{}], \"snapshot\": false}}}}",
+            listing.channel("trades").unwrap_or("trade"),
+            symbols.join(","),
+        );
+        vec![WsMessage::Text(book), WsMessage::Text(trades)]
+    }
+
+    fn decode(&mut self, frame: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        match serde_json::from_str::<Frame>(frame)? {
+            Frame::Channel(ChannelMessage::Book { kind, data }) => {
+                for book in data {
+                    if let Some(symbol) = self.symbol(&book.symbol) {
+                        symbol.book(kind, book, events)?;
+                    }
+                }
+            },
+            Frame::Channel(ChannelMessage::Trade { data, .. }) => {
+                for trade in data {
+                    if let Some(symbol) = self.symbol(&trade.symbol) {
+                        symbol.trade(trade, events);
+                    }
+                }
+            },
+            Frame::Channel(ChannelMessage::Other) => (),
+            // A symbol we could not subscribe to would never get a book, so start over.
+            Frame::Response(response) => {
+                if response.success == Some(false) {
+                    return Err(DecodeError::OutOfSync(format!("{} failed: {:?}", response.method, response.error)));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn keepalive(&self) -> Option<WsMessage> {
+        Some(WsMessage::Text("{\"method\": \"ping\"}".to_string()))
+    }
+
+    fn reset(&mut self) {
+        for symbol in self.symbols.iter_mut() {
+            symbol.mirror = Mirror::default();
+            symbol.init = false;
+        }
+    }
+}
+
+#[test]
+fn test_decode_checksummed_book() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = KrakenV2Feed::new(Arc::new(registry));
+    let mut events = Vec::new();
+    let snapshot = r#"{"channel":"book","type":"snapshot","data":[{"symbol":"ETH/USD","bids":[{"price":1897.1,"qty":2.0}],"asks":[{"price":1897.2,"qty":1.0},{"price":1897.5,"qty":0.5}],"checksum":3926711299}]}"#;
+    feed.decode(snapshot, &mut events).unwrap();
+    match &events[0] {
+        FeedEvent::Snapshot { pair, snapshot } => {
+            assert_eq!("ETH-USD", pair.as_str());
+            assert_eq!(189710, snapshot.bids[0].level);
+            assert_eq!(189720, snapshot.asks[0].level);
+        },
+        other => panic!("Expected snapshot, got {:?}", other),
+    }
+    events.clear();
+    let update = r#"{"channel":"book","type":"update","data":[{"symbol":"ETH/USD","bids":[{"price":1897.0,"qty":0.29882788}],"asks":[{"price":1897.2,"qty":0.0}],"checksum":198215468,"timestamp":"2023-07-10T22:08:08.932974Z"}]}"#;
+    feed.decode(update, &mut events).unwrap();
+    match &events[0] {
+        FeedEvent::Delta { changes, .. } => {
+            assert_eq!(2, changes.len());
+            assert_eq!(Side::Buy, changes[0].side);
+            assert_eq!(189700, changes[0].price_level.level);
+            assert_eq!(Side::Sell, changes[1].side);
+            assert_eq!(0.0, changes[1].price_level.amount);
+        },
+        other => panic!("Expected delta, got {:?}", other),
+    }
+    events.clear();
+    let trade = r#"{"channel":"trade","type":"update","data":[{"symbol":"ETH/USD","side":"sell","price":1897.1,"qty":0.5,"ord_type":"market","trade_id":4665846,"timestamp":"2023-07-10T22:08:09.100000Z"}]}"#;
+    feed.decode(trade, &mut events).unwrap();
+    assert!(matches!(&events[0], FeedEvent::Trade { trade: Match { side: Side::Sell, price: 189710, .. }, time: Some(_), .. }));
+    events.clear();
+    let corrupt = r#"{"channel":"book","type":"update","data":[{"symbol":"ETH/USD","bids":[{"price":1896.9,"qty":1.0}],"asks":[],"checksum":1}]}"#;
+    assert!(matches!(feed.decode(corrupt, &mut events), Err(DecodeError::OutOfSync(_))));
+    assert!(events.is_empty());
+}
+
+#[test]
+fn test_mirror_truncates_to_depth() {
+    let mut mirror = Mirror::default();
+    for ticks in 1..=5 {
+        mirror.side(Side::Buy).insert(ticks, 1.0);
+        mirror.side(Side::Sell).insert(ticks + 10, 1.0);
+    }
+    assert_eq!(vec![1, 2], mirror.truncate(Side::Buy, 3));
+    assert_eq!(vec![15, 14], mirror.truncate(Side::Sell, 3));
+}
+
+#[test]
+fn test_subscribes_with_v2_symbols() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let mut feed = KrakenV2Feed::new(Arc::new(registry));
+    let subscriptions = feed.subscriptions();
+    assert_eq!(2, subscriptions.len());
+    for subscription in subscriptions.iter() {
+        let text = subscription.to_text().unwrap();
+        assert!(text.contains("\"BTC/USD\""), "{}", text);
+        assert!(!text.contains("XBT"), "{}", text);
+    }
+    let mut events = Vec::new();
+    let failed = r#"{"method":"subscribe","success":false,"error":"Currency pair not supported XBT/USD","time_in":"2023-07-10T22:08:08.000000Z","time_out":"2023-07-10T22:08:08.000100Z"}"#;
+    assert!(matches!(feed.decode(failed, &mut events), Err(DecodeError::OutOfSync(_))));
+}
@@ -1,3 +1,6 @@
-pub mod kraken_adapter;
 pub mod kraken_client;
-pub mod data_types;
\ No newline at end of file
+pub mod kraken_send_client;
+pub mod kraken_v2_client;
+pub mod data_types;
+pub mod private_data_types;
+pub mod v2_data_types;
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct RestResponse<T> {
+    pub error: Vec<String>,
+    pub result: Option<T>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct WebSocketsToken {
+    pub token: String,
+}
+
+// Any object frame on the authenticated socket: heartbeats, subscription and request statuses.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Status {
+    pub event: String,
+    pub reqid: Option<u64>,
+    pub status: Option<String>,
+    pub txid: Option<String>,
+    pub originaltxid: Option<String>,
+    #[serde(rename = "errorMessage")]
+    pub error_message: Option<String>,
+}
+
+// `[[{id: item}, ...], channel, {"sequence": n}]`
+#[derive(Debug, Deserialize)]
+pub struct ChannelMessage<T>(pub Vec<HashMap<String, T>>, pub String, pub serde::de::IgnoredAny);
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct OwnTrade {
+    pub ordertxid: String,
+    pub pair: String,
+    #[serde(rename = "type")]
+    pub side: String,
+    pub price: String,
+    pub vol: String,
+    pub fee: String,
+}
+
+// Only the fields that changed are sent after the first update for an order.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct OpenOrder {
+    pub status: Option<String>,
+}
+
+#[test]
+fn test_own_trades() {
+    let input = r#"[[{"TDLH43-DVQXD-2KHVYY":{"cost":"1000000.00000","fee":"1600.00000","margin":"0.00000","ordertxid":"TDLH43-DVQXD-2KHVYY","ordertype":"limit","pair":"XBT/EUR","postxid":"OGTT3Y-C6I3P-XRI6HX","price":"100000.00000","time":"1560516023.070651","type":"sell","vol":"1000000000.00000000"}}],"ownTrades",{"sequence":2948}]"#;
+    let ChannelMessage(trades, channel, _) = serde_json::from_str::<ChannelMessage<OwnTrade>>(input).unwrap();
+    assert_eq!("ownTrades", channel);
+    assert_eq!("sell", trades[0]["TDLH43-DVQXD-2KHVYY"].side);
+}
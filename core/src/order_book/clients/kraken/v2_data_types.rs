@@ -0,0 +1,89 @@
+use serde::Deserialize;
+
+use crate::order_book::data_types::Side;
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Frame {
+    Channel(ChannelMessage),
+    Response(Response),
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "channel", rename_all = "lowercase")]
+pub enum ChannelMessage {
+    Book {
+        #[serde(rename = "type")]
+        kind: MessageType,
+        data: Vec<Book>,
+    },
+    Trade {
+        #[serde(rename = "type")]
+        kind: MessageType,
+        data: Vec<Trade>,
+    },
+    // heartbeat, status
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageType {
+    Snapshot,
+    Update,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Response {
+    pub method: String,
+    pub success: Option<bool>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Book {
+    pub symbol: String,
+    #[serde(default)]
+    pub bids: Vec<Level>,
+    #[serde(default)]
+    pub asks: Vec<Level>,
+    pub checksum: u32,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct Level {
+    pub price: f64,
+    pub qty: f64,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct Trade {
+    pub symbol: String,
+    pub side: Side,
+    pub price: f64,
+    pub qty: f64,
+    pub timestamp: String,
+}
+
+#[test]
+fn test_frame() {
+    let book = r#"{"channel":"book","type":"update","data":[{"symbol":"ETH/USD","bids":[{"price":1897.0,"qty":0.29882788}],"asks":[],"checksum":198215468,"timestamp":"2023-07-10T22:08:08.932974Z"}]}"#;
+    match serde_json::from_str::<Frame>(book).unwrap() {
+        Frame::Channel(ChannelMessage::Book { kind, data }) => {
+            assert_eq!(MessageType::Update, kind);
+            assert_eq!(Level { price: 1897.0, qty: 0.29882788 }, data[0].bids[0]);
+            assert_eq!(198215468, data[0].checksum);
+        },
+        other => panic!("Expected book, got {:?}", other),
+    }
+    let trade = r#"{"channel":"trade","type":"update","data":[{"symbol":"ETH/USD","side":"sell","price":1897.1,"qty":0.5,"ord_type":"market","trade_id":4665846,"timestamp":"2023-07-10T22:08:09.100000Z"}]}"#;
+    assert!(matches!(serde_json::from_str::<Frame>(trade).unwrap(), Frame::Channel(ChannelMessage::Trade { .. })));
+    let heartbeat = r#"{"channel":"heartbeat"}"#;
+    assert_eq!(Frame::Channel(ChannelMessage::Other), serde_json::from_str::<Frame>(heartbeat).unwrap());
+    let response = r#"{"method":"subscribe","error":"Currency pair not supported","success":false,"symbol":"ETH/USX"}"#;
+    match serde_json::from_str::<Frame>(response).unwrap() {
+        Frame::Response(response) => assert_eq!(Some(false), response.success),
+        other => panic!("Expected response, got {:?}", other),
+    }
+}
@@ -1,6 +1,16 @@
 pub mod client;
+pub mod clock;
+pub mod feed;
+pub mod fix;
+pub mod orders;
+pub mod relay;
 pub mod binance;
 pub mod bitstamp;
+pub mod bybit;
 pub mod coinbase;
 pub mod gemini;
-pub mod kraken;
\ No newline at end of file
+pub mod kraken;
+pub mod okx;
+pub mod udp;
+#[cfg(test)]
+pub mod test_support;
\ No newline at end of file
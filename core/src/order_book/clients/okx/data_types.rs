@@ -0,0 +1,128 @@
+use serde::{de::{self, Visitor, SeqAccess}, Deserializer, Deserialize};
+
+use crate::order_book::data_types::{Side, to_level};
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Header<'a> {
+    pub event: Option<&'a str>,
+    #[serde(borrow)]
+    pub arg: Option<Arg<'a>>,
+    pub action: Option<&'a str>,
+    pub msg: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Arg<'a> {
+    pub channel: &'a str,
+    #[serde(rename = "instId")]
+    pub inst_id: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct BookMessage {
+    pub data: Vec<Book>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Book {
+    pub asks: Vec<Level>,
+    pub bids: Vec<Level>,
+    pub checksum: i32,
+    pub prev_seq_id: i64,
+    pub seq_id: i64,
+}
+
+// The checksum is computed over the price and size strings exactly as OKX sent them, so they are
+// kept alongside the parsed values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Level {
+    pub price: String,
+    pub size: String,
+    pub level: usize,
+    pub amount: f64,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct TradeMessage {
+    pub data: Vec<Trade>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Trade {
+    pub px: String,
+    pub sz: String,
+    pub side: Side,
+    pub ts: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct TimeMessage {
+    pub data: Vec<ServerTime>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct ServerTime {
+    pub ts: String,
+}
+
+impl<'de> Deserialize<'de> for Level {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_seq(LevelVisitor)
+    }
+}
+
+struct LevelVisitor;
+
+impl<'de> Visitor<'de> for LevelVisitor {
+    type Value = Level;
+
+    fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+        formatter.write_str("An OKX [price, size, liquidated orders, orders] level")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let price = seq.next_element::<String>()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let size = seq.next_element::<String>()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        while seq.next_element::<de::IgnoredAny>()?.is_some() {}
+        let level = to_level(price.parse::<f64>().map_err(de::Error::custom)?);
+        let amount = size.parse::<f64>().map_err(de::Error::custom)?;
+        Ok(Level { price, size, level, amount })
+    }
+}
+
+#[test]
+fn test_header() {
+    let input = r#"{"arg":{"channel":"books","instId":"ETH-USDT"},"action":"update","data":[{"asks":[],"bids":[],"ts":"1","checksum":0,"prevSeqId":1,"seqId":2}]}"#;
+    let (header, _) = serde_json_core::from_str::<Header>(input).unwrap();
+    assert_eq!(Some("books"), header.arg.map(|a| a.channel));
+    assert_eq!(Some("update"), header.action);
+    let input = r#"{"event":"error","code":"60012","msg":"Invalid request","connId":"a4d3ae55"}"#;
+    let (header, _) = serde_json_core::from_str::<Header>(input).unwrap();
+    assert_eq!(Some("error"), header.event);
+    assert_eq!(Some("Invalid request"), header.msg);
+}
+
+#[test]
+fn test_book() {
+    let input = r#"{"arg":{"channel":"books","instId":"ETH-USDT"},"action":"snapshot","data":[{"asks":[["1897.2","1.1","0","2"]],"bids":[["1897.1","2.5","0","3"]],"ts":"1689025543609","checksum":-855196043,"prevSeqId":-1,"seqId":1000}]}"#;
+    let message = serde_json::from_str::<BookMessage>(input).unwrap();
+    let book = &message.data[0];
+    assert_eq!(-855196043, book.checksum);
+    assert_eq!((-1, 1000), (book.prev_seq_id, book.seq_id));
+    assert_eq!(Level { price: "1897.2".to_string(), size: "1.1".to_string(), level: 189720, amount: 1.1 }, book.asks[0]);
+}
+
+#[test]
+fn test_trade() {
+    let input = r#"{"arg":{"channel":"trades","instId":"ETH-USDT"},"data":[{"instId":"ETH-USDT","tradeId":"130639474","px":"1897.1","sz":"0.5","side":"sell","ts":"1689025543700"}]}"#;
+    let message = serde_json::from_str::<TradeMessage>(input).unwrap();
+    assert_eq!(Side::Sell, message.data[0].side);
+    assert_eq!("1897.1", message.data[0].px);
+}
@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
+
+use crate::order_book::clients::feed::{DecodeError, ExchangeFeed, FeedEvent};
+use crate::order_book::data_types::{Change, Match, PriceLevel, Side, Snapshot, to_level};
+use crate::order_book::instruments::{InstrumentRegistry, Listing};
+
+use super::data_types::{Book, BookMessage, Header, Level, TimeMessage, TradeMessage};
+
+const CHECKSUM_DEPTH: usize = 25;
+
+// Our copy of the top of the OKX book, keyed in ticks, holding the strings the checksum is
+// computed over.
+#[derive(Default)]
+struct Mirror {
+    bids: BTreeMap<u64, Level>,
+    asks: BTreeMap<u64, Level>,
+}
+
+impl Mirror {
+    fn side(&mut self, side: Side) -> &mut BTreeMap<u64, Level> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    // CRC32 of the best 25 bids and asks interleaved as "bid:size:ask:size:...", read as an i32.
+    fn checksum(&self) -> i32 {
+        let mut bids = self.bids.values().rev().take(CHECKSUM_DEPTH);
+        let mut asks = self.asks.values().take(CHECKSUM_DEPTH);
+        let mut parts = Vec::new();
+        for _ in 0..CHECKSUM_DEPTH {
+            if let Some(bid) = bids.next() {
+                parts.push(format!("{}:{}", bid.price, bid.size));
+            }
+            if let Some(ask) = asks.next() {
+                parts.push(format!("{}:{}", ask.price, ask.size));
+            }
+        }
+        crc32fast::hash(parts.join(":").as_bytes()) as i32
+    }
+}
+
+// Mirror and sequence state for one OKX instrument on the shared connection.
+struct Symbol {
+    pair: heapless::String<8>,
+    listing: Listing,
+    mirror: Mirror,
+    seq_id: Option<i64>,
+}
+
+pub struct OkxFeed {
+    symbols: Vec<Symbol>,
+}
+
+impl OkxFeed {
+    pub fn new(registry: Arc<InstrumentRegistry>) -> OkxFeed {
+        OkxFeed {
+            symbols: registry.venue_listings("okx").into_iter().map(|(pair, listing)| Symbol {
+                pair,
+                listing: listing.clone(),
+                mirror: Mirror::default(),
+                seq_id: None,
+            }).collect(),
+        }
+    }
+
+    fn symbol(&mut self, inst_id: Option<&str>) -> Option<&mut Symbol> {
+        let inst_id = inst_id?;
+        self.symbols.iter_mut().find(|s| s.listing.symbol == inst_id)
+    }
+}
+
+impl Symbol {
+    fn ticks(&self, level: &Level) -> Result<u64, DecodeError> {
+        let precision = self.listing.price_precision();
+        let price = level.price.parse::<f64>().map_err(|e| DecodeError::Unexpected(e.to_string()))?;
+        Ok((price * 10f64.powi(precision as i32)).round() as u64)
+    }
+
+    fn snapshot(&mut self, book: Book, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        self.mirror = Mirror::default();
+        let mut initial_book = Snapshot::new();
+        for (side, levels) in [(Side::Buy, book.bids), (Side::Sell, book.asks)] {
+            for level in levels {
+                let price_level = PriceLevel { level: level.level, amount: level.amount, sequence: 0 };
+                let _ = match side {
+                    Side::Buy => initial_book.bids.push(price_level),
+                    Side::Sell => initial_book.asks.push(price_level),
+                };
+                let ticks = self.ticks(&level)?;
+                self.mirror.side(side).insert(ticks, level);
+            }
+        }
+        self.verify(book.checksum)?;
+        self.seq_id = Some(book.seq_id);
+        events.push(FeedEvent::Snapshot { pair: self.pair.clone(), snapshot: initial_book });
+        Ok(())
+    }
+
+    fn update(&mut self, book: Book, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        let last = match self.seq_id {
+            Some(last) => last,
+            None => return Ok(()),
+        };
+        if book.prev_seq_id != last {
+            self.seq_id = None;
+            return Err(DecodeError::OutOfSync(format!("prevSeqId {:?} != {:?}", book.prev_seq_id, last)));
+        }
+        let mut changes = heapless::Vec::<Change, 512>::new();
+        for (side, levels) in [(Side::Buy, book.bids), (Side::Sell, book.asks)] {
+            for level in levels {
+                let _ = changes.push(Change {
+                    side,
+                    price_level: PriceLevel { level: level.level, amount: level.amount, sequence: 0 },
+                });
+                let ticks = self.ticks(&level)?;
+                if level.amount == 0.0 {
+                    self.mirror.side(side).remove(&ticks);
+                } else {
+                    self.mirror.side(side).insert(ticks, level);
+                }
+            }
+        }
+        self.verify(book.checksum)?;
+        self.seq_id = Some(book.seq_id);
+        // Unchanged books are still pushed periodically with seqId == prevSeqId.
+        if !changes.is_empty() {
+            events.push(FeedEvent::Delta { pair: self.pair.clone(), changes });
+        }
+        Ok(())
+    }
+
+    fn verify(&mut self, expected: i32) -> Result<(), DecodeError> {
+        let checksum = self.mirror.checksum();
+        if checksum != expected {
+            self.seq_id = None;
+            return Err(DecodeError::OutOfSync(format!("checksum {:?} != {:?}", checksum, expected)));
+        }
+        Ok(())
+    }
+}
+
+impl ExchangeFeed for OkxFeed {
+    fn name(&self) -> &'static str {
+        "okx"
+    }
+
+    fn url(&self) -> String {
+        "wss://ws.okx.com:8443/ws/v5/public".to_string()
+    }
+
+    fn time_url(&self) -> Option<String> {
+        Some("https://www.okx.com/api/v5/public/time".to_string())
+    }
+
+    fn decode_time(&self, body: &str) -> Result<i64, DecodeError> {
+        let time = serde_json::from_str::<TimeMessage>(body)?.data.pop()
+            .and_then(|time| time.ts.parse::<i64>().ok())
+            .ok_or_else(|| DecodeError::Unexpected(body.to_string()))?;
+        Ok(time * 1_000_000)
+    }
+
+    fn subscriptions(&self) -> Vec<WsMessage> {
+        if self.symbols.is_empty() {
+            println!("OKX: no listings configured");
+            return Vec::new();
+        }
+        let mut args = Vec::new();
+        for symbol in self.symbols.iter() {
+            for (name, default) in [("book", "books"), ("trades", "trades")] {
+                args.push(format!(
+                    "{{\"channel\": {:?}, \"instId\": {:?}}}",
+                    symbol.listing.channel(name).unwrap_or(default),
+                    symbol.listing.symbol,
+                ));
+            }
+        }
+        let sub_message = format!("{{\"op\": \"subscribe\", \"args\": [{}]}}", args.join(", "));
+        vec![WsMessage::Text(sub_message)]
+    }
+
+    fn decode(&mut self, frame: &str, events: &mut Vec<FeedEvent>) -> Result<(), DecodeError> {
+        if frame == "pong" {
+            return Ok(());
+        }
+        let (header, _) = serde_json_core::from_str::<Header>(frame)?;
+        if header.event == Some("error") {
+            return Err(DecodeError::Unexpected(format!("error: {:?}", header.msg)));
+        }
+        let (channel, symbol) = match (header.event, header.arg) {
+            (None, Some(arg)) => match self.symbol(arg.inst_id) {
+                Some(symbol) => (arg.channel, symbol),
+                None => return Ok(()),
+            },
+            _ => return Ok(()),
+        };
+        match (channel, header.action) {
+            ("books", Some("snapshot")) => {
+                for book in serde_json::from_str::<BookMessage>(frame)?.data {
+                    symbol.snapshot(book, events)?;
+                }
+            },
+            ("books", Some("update")) => {
+                for book in serde_json::from_str::<BookMessage>(frame)?.data {
+                    symbol.update(book, events)?;
+                }
+            },
+            ("trades", _) => {
+                for trade in serde_json::from_str::<TradeMessage>(frame)?.data {
+                    let price = trade.px.parse::<f64>().map_err(|e| DecodeError::Unexpected(e.to_string()))?;
+                    let size = trade.sz.parse::<f64>().map_err(|e| DecodeError::Unexpected(e.to_string()))?;
+                    events.push(FeedEvent::Trade {
+                        pair: symbol.pair.clone(),
+                        trade: Match { side: trade.side, size, price: to_level(price) },
+                        time: trade.ts.parse::<i64>().ok().map(|t| t * 1_000_000),
+                    });
+                }
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+
+    // OKX drops connections that send nothing for 30 seconds and answers a bare "ping" with "pong".
+    fn keepalive(&self) -> Option<WsMessage> {
+        Some(WsMessage::Text("ping".to_string()))
+    }
+
+    fn reset(&mut self) {
+        for symbol in self.symbols.iter_mut() {
+            symbol.mirror = Mirror::default();
+            symbol.seq_id = None;
+        }
+    }
+}
+
+#[cfg(test)]
+fn fixture_feed() -> OkxFeed {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    OkxFeed::new(Arc::new(registry))
+}
+
+#[test]
+fn test_fixture_session() {
+    let mut feed = fixture_feed();
+    let mut events = Vec::new();
+    for frame in include_str!("../../../../fixtures/okx/eth_usdt_session.jsonl").lines() {
+        feed.decode(frame, &mut events).unwrap();
+    }
+    assert_eq!(4, events.len());
+    match &events[0] {
+        FeedEvent::Snapshot { pair, snapshot } => {
+            assert_eq!("ETH-USD", pair.as_str());
+            assert_eq!(400, snapshot.bids.len());
+            assert_eq!(189720, snapshot.asks[0].level);
+        },
+        other => panic!("Expected snapshot, got {:?}", other),
+    }
+    assert!(matches!(&events[1], FeedEvent::Delta { changes, .. } if changes.len() == 2));
+    assert!(matches!(&events[2], FeedEvent::Trade { trade: Match { side: Side::Sell, price: 189710, .. }, time: Some(1689025543700000000), .. }));
+    assert!(matches!(&events[3], FeedEvent::Delta { changes, .. } if changes[1].price_level.level == 189730));
+    assert_eq!(Some(1010), feed.symbols[0].seq_id);
+    assert_eq!(None, feed.symbols[1].seq_id);
+}
+
+#[test]
+fn test_sequence_gap_and_bad_checksum() {
+    let frames: Vec<&str> = include_str!("../../../../fixtures/okx/eth_usdt_session.jsonl").lines().collect();
+    let mut feed = fixture_feed();
+    let mut events = Vec::new();
+    feed.decode(frames[2], &mut events).unwrap();
+    let gap = frames[3].replace("\"prevSeqId\":1000", "\"prevSeqId\":999");
+    assert!(matches!(feed.decode(&gap, &mut events), Err(DecodeError::OutOfSync(_))));
+
+    let mut feed = fixture_feed();
+    feed.decode(frames[2], &mut events).unwrap();
+    let corrupt = frames[3].replace("\"1897.05\",\"0.4\"", "\"1897.05\",\"0.5\"");
+    assert!(matches!(feed.decode(&corrupt, &mut events), Err(DecodeError::OutOfSync(_))));
+}
+
+#[test]
+fn test_decode_server_time() {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let feed = OkxFeed::new(Arc::new(registry));
+    assert_eq!(1597026383085000000, feed.decode_time(r#"{"code":"0","msg":"","data":[{"ts":"1597026383085"}]}"#).unwrap());
+    assert!(feed.decode_time(r#"{"code":"50001","msg":"unavailable","data":[]}"#).is_err());
+}
@@ -0,0 +1,109 @@
+use std::future::Future;
+
+use crate::order_book::data_types::{Side, to_level};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeInForce {
+    // Rests on the book until filled or canceled.
+    Gtc,
+    // Fills what it can on arrival; the rest is canceled.
+    Ioc,
+}
+
+// A limit order in the same units as the books: prices in hundredths, sizes in the base asset.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrderRequest {
+    // Our id for the order, echoed in its acknowledgement.
+    pub client_id: String,
+    pub pair: heapless::String<8>,
+    pub side: Side,
+    pub price: usize,
+    pub size: f64,
+    pub time_in_force: TimeInForce,
+}
+
+// What a venue reported about our orders. Order ids are the venue's.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OrderEvent {
+    Accepted {
+        client_id: String,
+        order_id: String,
+    },
+    Rejected {
+        client_id: String,
+        reason: String,
+    },
+    Fill {
+        order_id: String,
+        pair: heapless::String<8>,
+        side: Side,
+        price: usize,
+        size: f64,
+        // In the quote asset.
+        fee: f64,
+    },
+    Canceled {
+        order_id: String,
+    },
+    // A cancel or replace the venue refused, typically because the order is already done.
+    CancelRejected {
+        order_id: String,
+        reason: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum OrderError {
+    Http(reqwest::Error),
+    WebSocket(Box<tokio_tungstenite::tungstenite::Error>),
+    // Something we couldn't interpret, with the venue's text.
+    Unexpected(String),
+    // The pair has no listing on the venue.
+    Pair(String),
+}
+
+impl std::fmt::Display for OrderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OrderError::Http(err) => write!(f, "request failed: {}", err),
+            OrderError::WebSocket(err) => write!(f, "order connection failed: {}", err),
+            OrderError::Unexpected(text) => write!(f, "unexpected response: {}", text),
+            OrderError::Pair(pair) => write!(f, "{} is not listed", pair),
+        }
+    }
+}
+
+impl From<reqwest::Error> for OrderError {
+    fn from(err: reqwest::Error) -> Self {
+        OrderError::Http(err)
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for OrderError {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        OrderError::WebSocket(Box::new(err))
+    }
+}
+
+// Order entry for one venue. Each call returns what the venue said in reply; a refusal is an
+// event, and only failing to get an answer at all is an error.
+pub trait OrderClient {
+    fn place(&mut self, order: OrderRequest) -> impl Future<Output = Result<Vec<OrderEvent>, OrderError>> + Send;
+
+    fn cancel(&mut self, order_id: &str) -> impl Future<Output = Result<Vec<OrderEvent>, OrderError>> + Send;
+
+    // Swaps a resting order for `order`. Venues without an amend cancel first and only place the
+    // new order once the cancel is confirmed.
+    fn replace(&mut self, order_id: &str, order: OrderRequest) -> impl Future<Output = Result<Vec<OrderEvent>, OrderError>> + Send;
+}
+
+// 3000005 for "30000.05".
+pub fn parse_price(price: &str) -> Option<usize> {
+    price.parse::<f64>().ok().map(to_level)
+}
+
+#[test]
+fn test_price_parse() {
+    assert_eq!(Some(3000005), parse_price("30000.05000000"));
+    assert_eq!(None, parse_price("x"));
+}
@@ -0,0 +1,675 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use futures_util::{SinkExt, StreamExt};
+use prism_wire::{Message as WireMessage, RejectReason, Side as WireSide};
+use tokio::net::{TcpListener, TcpStream, TcpSocket};
+use tokio::sync::mpsc;
+use tokio_native_tls::native_tls;
+use tokio_native_tls::TlsAcceptor;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, accept_async};
+
+use tokio_tungstenite::tungstenite::protocol::Message as WsMessage;
+
+use crate::order_book::clients::clock::{self, ClockEstimate, ClockSample};
+use crate::order_book::clients::feed::{BookAdapter, FeedEvent, LatencyStats};
+use crate::order_book::data_types::{Match, PriceLevel, Side, to_level};
+use crate::order_book::instruments::{ConfigError, InstrumentRegistry};
+
+type RelayStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// How long a relay has to answer the token challenge.
+const AUTH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+// How often relays that understand pings are asked for their clock.
+const RELAY_PING_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+// TLS identity and shared token for relay connections. Either can be left out, but without both
+// anyone who can reach the port can write to the books.
+#[derive(Clone, Default)]
+pub struct RelaySecurity {
+    pub tls: Option<TlsAcceptor>,
+    pub token: Option<String>,
+}
+
+impl RelaySecurity {
+    // Loads a PEM certificate chain and PKCS#8 key.
+    pub fn tls(cert_path: &str, key_path: &str) -> Result<TlsAcceptor, ConfigError> {
+        let cert = std::fs::read(cert_path).map_err(ConfigError::Io)?;
+        let key = std::fs::read(key_path).map_err(ConfigError::Io)?;
+        let identity = native_tls::Identity::from_pkcs8(&cert, &key).map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        let acceptor = native_tls::TlsAcceptor::new(identity).map_err(|e| ConfigError::Invalid(e.to_string()))?;
+        Ok(TlsAcceptor::from(acceptor))
+    }
+
+    async fn wrap(&self, connection: TcpStream) -> Result<MaybeTlsStream<TcpStream>, native_tls::Error> {
+        match &self.tls {
+            Some(acceptor) => Ok(MaybeTlsStream::NativeTls(acceptor.accept(connection).await?)),
+            None => Ok(MaybeTlsStream::Plain(connection)),
+        }
+    }
+}
+
+// Connection state for one connected relay.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RelayState {
+    pub id: u64,
+    pub peer: SocketAddr,
+    pub venue: String,
+    pub version: u16,
+    // Whether this relay's messages are the ones applied to its venue's books.
+    pub active: bool,
+    pub messages: u64,
+    // Messages repeating a sequence this relay already sent.
+    pub duplicates: u64,
+    // Messages passed over while another relay for the venue is active.
+    pub standby: u64,
+    pub gaps: u64,
+    pub last_seq: u64,
+    // The relay's clock against ours, from the fastest recent ping.
+    pub clock: Option<ClockSample>,
+}
+
+// Shared view of every connected relay that has completed a handshake.
+#[derive(Clone, Default)]
+pub struct RelayStates(Arc<std::sync::Mutex<BTreeMap<u64, RelayState>>>);
+
+impl RelayStates {
+    pub fn snapshot(&self) -> Vec<RelayState> {
+        self.0.lock().unwrap().values().cloned().collect()
+    }
+}
+
+enum RelayEvent {
+    Connected(RelayState),
+    Message { id: u64, message: WireMessage },
+    Clock { id: u64, sample: ClockSample },
+    Closed { id: u64 },
+}
+
+// Listens for relays for as long as the process runs. Any number may connect, for any venue the
+// core doesn't already feed directly; each names its venue in its hello. Relays for one venue run hot/hot: the first to connect is
+// applied and the rest stand by, taking over in turn when it disconnects. Their tops carry no
+// upstream id to match copies on, so nothing is merged across relays.
+pub struct RelayListener<A: BookAdapter> {
+    adapter: A,
+    addr: SocketAddr,
+    security: RelaySecurity,
+    pairs: Vec<heapless::String<8>>,
+    // Venues the core connects to itself, whose relays are turned away.
+    direct: Arc<Vec<String>>,
+    states: RelayStates,
+    // The relay applied for each venue.
+    active: HashMap<String, u64>,
+    clocks: HashMap<u64, ClockEstimate>,
+    // Relay to core, corrected for each relay's clock; the exchange to relay hop is measured by
+    // the relay itself.
+    latency: LatencyStats,
+}
+
+impl<A: BookAdapter> RelayListener<A> {
+    pub fn new(adapter: A, registry: Arc<InstrumentRegistry>, security: RelaySecurity, addr: SocketAddr) -> RelayListener<A> {
+        RelayListener {
+            adapter,
+            addr,
+            security,
+            pairs: registry.pairs(),
+            direct: Arc::new(Vec::new()),
+            states: RelayStates::default(),
+            active: HashMap::new(),
+            clocks: HashMap::new(),
+            latency: LatencyStats::new("relay hop"),
+        }
+    }
+
+    pub fn set_direct(&mut self, venues: Vec<String>) {
+        self.direct = Arc::new(venues);
+    }
+
+    pub fn states(&self) -> RelayStates {
+        self.states.clone()
+    }
+
+    pub async fn bind(&self) -> std::io::Result<TcpListener> {
+        let socket = TcpSocket::new_v4()?;
+        socket.set_reuseaddr(true)?;
+        socket.bind(self.addr)?;
+        socket.listen(1024)
+    }
+
+    pub async fn run(&mut self) {
+        match self.bind().await {
+            Ok(listener) => self.serve(listener).await,
+            Err(err) => println!("Relay listener failed to bind {:?}: {:?}", self.addr, err),
+        }
+    }
+
+    async fn serve(&mut self, listener: TcpListener) {
+        let (sender, mut events) = mpsc::unbounded_channel();
+        let digest = prism_wire::instruments_digest(&self.pairs);
+        let mut next_id = 0;
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok((connection, peer)) => {
+                        let _ = connection.set_nodelay(true);
+                        next_id += 1;
+                        let relay = RelayConnection::new(next_id, peer, self.security.token.clone(), digest, self.direct.clone());
+                        tokio::spawn(relay.run(connection, self.security.clone(), sender.clone()));
+                    },
+                    Err(err) => println!("Error accepting relay: {:?}", err),
+                },
+                Some(event) = events.recv() => self.handle(event).await,
+            }
+        }
+    }
+
+    async fn handle(&mut self, event: RelayEvent) {
+        let (id, message) = match event {
+            RelayEvent::Connected(mut state) => {
+                println!("{} relay {:?} connected from {:?} with protocol version {:?}", state.venue, state.id, state.peer, state.version);
+                if !self.active.contains_key(&state.venue) {
+                    self.active.insert(state.venue.clone(), state.id);
+                    state.active = true;
+                }
+                self.states.0.lock().unwrap().insert(state.id, state);
+                return;
+            },
+            RelayEvent::Clock { id, sample } => {
+                let clock = self.clocks.entry(id).or_default();
+                clock.add(sample);
+                if let Some(state) = self.states.0.lock().unwrap().get_mut(&id) {
+                    state.clock = clock.best();
+                }
+                return;
+            },
+            RelayEvent::Closed { id } => {
+                self.clocks.remove(&id);
+                self.close(id).await;
+                return;
+            },
+            RelayEvent::Message { id, message } => (id, message),
+        };
+        let header = match &message {
+            WireMessage::TopOfBook { header, .. } | WireMessage::Trade { header, .. } => *header,
+            _ => return,
+        };
+        let venue = {
+            let mut states = self.states.0.lock().unwrap();
+            let state = match states.get_mut(&id) {
+                Some(state) => state,
+                None => return,
+            };
+            state.messages += 1;
+            if header.seq <= state.last_seq {
+                state.duplicates += 1;
+                return;
+            }
+            if header.seq != state.last_seq + 1 {
+                println!("{} relay {:?}: expected sequence {:?}, got {:?}", state.venue, id, state.last_seq + 1, header.seq);
+                state.gaps += 1;
+            }
+            state.last_seq = header.seq;
+            if !state.active {
+                state.standby += 1;
+                return;
+            }
+            state.venue.clone()
+        };
+        let pair = match self.pairs.get(header.instrument as usize) {
+            Some(pair) => pair.clone(),
+            None => return,
+        };
+        self.adapter.apply(&venue, RelayListener::<A>::event(pair, message)).await;
+        if header.relay_time != 0 {
+            let offset = self.clocks.get(&id).map_or(0, |clock| clock.offset());
+            self.latency.record_from(header.relay_time, offset);
+        }
+    }
+
+    // Hands the venue to its longest-connected standby, or marks it stale if none is left.
+    async fn close(&mut self, id: u64) {
+        let (venue, standby) = {
+            let mut states = self.states.0.lock().unwrap();
+            let state = match states.remove(&id) {
+                Some(state) => state,
+                None => return,
+            };
+            println!("{} relay {:?} disconnected: {:?}", state.venue, id, state);
+            if !state.active {
+                return;
+            }
+            let standby = states.values_mut().find(|s| s.venue == state.venue).map(|s| {
+                s.active = true;
+                s.id
+            });
+            (state.venue, standby)
+        };
+        match standby {
+            Some(standby) => {
+                println!("{} relay {:?} is now active", venue, standby);
+                self.active.insert(venue, standby);
+            },
+            None => {
+                self.active.remove(&venue);
+                self.adapter.set_stale(&venue, true).await;
+            },
+        }
+    }
+
+    fn level(price: u64) -> usize {
+        to_level(prism_wire::from_fixed(price))
+    }
+
+    fn event(pair: heapless::String<8>, message: WireMessage) -> FeedEvent {
+        match message {
+            WireMessage::Trade { header, side, price, size } => FeedEvent::Trade {
+                pair,
+                trade: Match {
+                    side: match side {
+                        WireSide::Buy => Side::Buy,
+                        WireSide::Sell => Side::Sell,
+                    },
+                    price: RelayListener::<A>::level(price),
+                    size: prism_wire::from_fixed(size),
+                },
+                time: if header.exchange_time == 0 { None } else { Some(header.exchange_time) },
+            },
+            WireMessage::TopOfBook { bid, ask, .. } => FeedEvent::TopOfBook {
+                pair,
+                bid: PriceLevel { level: RelayListener::<A>::level(bid.price), amount: prism_wire::from_fixed(bid.size), sequence: 0 },
+                ask: PriceLevel { level: RelayListener::<A>::level(ask.price), amount: prism_wire::from_fixed(ask.size), sequence: 0 },
+            },
+            other => unreachable!("{:?} is not market data", other),
+        }
+    }
+}
+
+// One accepted relay: the handshake, then its messages passed to the listener.
+struct RelayConnection {
+    id: u64,
+    peer: SocketAddr,
+    token: Option<String>,
+    instruments: u32,
+    direct: Arc<Vec<String>>,
+}
+
+impl RelayConnection {
+    fn new(id: u64, peer: SocketAddr, token: Option<String>, instruments: u32, direct: Arc<Vec<String>>) -> RelayConnection {
+        RelayConnection { id, peer, token, instruments, direct }
+    }
+
+    async fn run(self, connection: TcpStream, security: RelaySecurity, events: mpsc::UnboundedSender<RelayEvent>) {
+        let connection = match security.wrap(connection).await {
+            Ok(connection) => connection,
+            Err(err) => {
+                println!("TLS handshake with relay {:?} failed: {}", self.peer, err);
+                return;
+            },
+        };
+        let mut stream = match accept_async(connection).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("WebSocket handshake with relay {:?} failed: {:?}", self.peer, err);
+                return;
+            },
+        };
+        let (venue, version) = match self.handshake(&mut stream).await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                println!("Relay handshake with {:?} failed: {}", self.peer, err);
+                return;
+            },
+        };
+        let _ = events.send(RelayEvent::Connected(RelayState {
+            id: self.id,
+            peer: self.peer,
+            venue: venue.clone(),
+            version,
+            active: false,
+            messages: 0,
+            duplicates: 0,
+            standby: 0,
+            gaps: 0,
+            last_seq: 0,
+            clock: None,
+        }));
+        let mut ping = tokio::time::interval(RELAY_PING_INTERVAL);
+        loop {
+            let msg = tokio::select! {
+                msg = stream.next() => msg,
+                _ = ping.tick(), if version >= 3 => {
+                    let ping = WireMessage::Ping { origin: clock::now() };
+                    if stream.send(WsMessage::Binary(ping.encode())).await.is_err() {
+                        break;
+                    }
+                    continue;
+                },
+            };
+            let bytes = match msg {
+                Some(Ok(WsMessage::Binary(bytes))) => bytes,
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            };
+            let event = match WireMessage::decode(&bytes) {
+                Ok(WireMessage::Pong { origin, received, sent }) => {
+                    RelayEvent::Clock { id: self.id, sample: ClockSample::new(origin, received, sent, clock::now()) }
+                },
+                Ok(message) => RelayEvent::Message { id: self.id, message },
+                Err(err) => {
+                    println!("{} relay {:?}: dropping message: {}", venue, self.id, err);
+                    continue;
+                },
+            };
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+        let _ = events.send(RelayEvent::Closed { id: self.id });
+    }
+
+    // Waits for the relay's hello and accepts it if both ends speak a common version, index the
+    // same instruments and the venue isn't fed directly, and the relay proves it holds the token
+    // when one is configured.
+    async fn handshake(&self, stream: &mut RelayStream) -> Result<(String, u16), String> {
+        let (version, instruments, venue) = match RelayConnection::next_message(stream).await? {
+            WireMessage::Hello { version, instruments, venue } => (version, instruments, venue),
+            other => return Err(format!("expected hello, got {:?}", other)),
+        };
+        let mut reply = match prism_wire::negotiate(version) {
+            None => WireMessage::Reject { version: prism_wire::VERSION, reason: RejectReason::Version },
+            Some(_) if instruments != self.instruments => {
+                WireMessage::Reject { version: prism_wire::VERSION, reason: RejectReason::Instruments }
+            },
+            Some(_) if self.direct.contains(&venue) => {
+                WireMessage::Reject { version: prism_wire::VERSION, reason: RejectReason::Venue }
+            },
+            Some(version) => WireMessage::Accept { version },
+        };
+        if let (WireMessage::Accept { version }, Some(token)) = (&reply, &self.token) {
+            // Relays from before the challenge can't answer it.
+            if *version < 2 || !RelayConnection::authenticate(stream, token, &venue).await? {
+                reply = WireMessage::Reject { version: prism_wire::VERSION, reason: RejectReason::Auth };
+            }
+        }
+        stream.send(WsMessage::Binary(reply.encode())).await.map_err(|e| e.to_string())?;
+        match reply {
+            WireMessage::Accept { version } => Ok((venue, version)),
+            _ => Err(format!("rejected {} relay: {:?}", venue, reply)),
+        }
+    }
+
+    async fn authenticate(stream: &mut RelayStream, token: &str, venue: &str) -> Result<bool, String> {
+        let nonce: [u8; 32] = rand::random();
+        stream.send(WsMessage::Binary(WireMessage::Challenge { nonce }.encode())).await.map_err(|e| e.to_string())?;
+        let answer = tokio::time::timeout(AUTH_TIMEOUT, RelayConnection::next_message(stream)).await
+            .map_err(|_| "timed out waiting for auth".to_string())??;
+        match answer {
+            WireMessage::Auth { proof } => Ok(prism_wire::verify_proof(token.as_bytes(), &nonce, venue, &proof)),
+            _ => Ok(false),
+        }
+    }
+
+    async fn next_message(stream: &mut RelayStream) -> Result<WireMessage, String> {
+        match stream.next().await {
+            Some(Ok(WsMessage::Binary(bytes))) => WireMessage::decode(&bytes).map_err(|e| e.to_string()),
+            other => Err(format!("expected a binary message, got {:?}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+type TestRelay = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Starts a listener on a free local port.
+#[cfg(test)]
+async fn relay_listener(security: RelaySecurity) -> (SocketAddr, crate::order_book::clients::test_support::RecordingAdapter, RelayStates) {
+    let registry = Arc::new(InstrumentRegistry::parse(include_str!("../../../config/instruments.json")).unwrap());
+    let adapter = crate::order_book::clients::test_support::RecordingAdapter::default();
+    let mut client = RelayListener::new(adapter.clone(), registry, security, "127.0.0.1:0".parse().unwrap());
+    client.set_direct(vec!["coinbase".to_string()]);
+    let listener = client.bind().await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let states = client.states();
+    tokio::spawn(async move { client.serve(listener).await });
+    (addr, adapter, states)
+}
+
+#[cfg(test)]
+async fn relay_connect(addr: SocketAddr, connector: Option<native_tls::TlsConnector>) -> TestRelay {
+    let tcp = TcpStream::connect(addr).await.unwrap();
+    let url = match connector {
+        Some(_) => format!("wss://localhost:{}", addr.port()),
+        None => format!("ws://{}", addr),
+    };
+    let connector = connector.map(tokio_tungstenite::Connector::NativeTls);
+    tokio_tungstenite::client_async_tls_with_config(url, tcp, None, connector).await.unwrap().0
+}
+
+#[cfg(test)]
+async fn relay_reply(relay: &mut TestRelay) -> WireMessage {
+    match relay.next().await {
+        Some(Ok(WsMessage::Binary(bytes))) => WireMessage::decode(&bytes).unwrap(),
+        other => panic!("Expected a reply, got {:?}", other),
+    }
+}
+
+#[cfg(test)]
+fn relay_hello(pairs: &[&str]) -> WsMessage {
+    venue_hello("okx", pairs)
+}
+
+#[cfg(test)]
+fn venue_hello(venue: &str, pairs: &[&str]) -> WsMessage {
+    let hello = WireMessage::Hello { version: prism_wire::VERSION, instruments: prism_wire::instruments_digest(pairs), venue: venue.to_string() };
+    WsMessage::Binary(hello.encode())
+}
+
+#[cfg(test)]
+fn relay_messages() -> [WireMessage; 2] {
+    use prism_wire::{Header as WireHeader, Level};
+
+    let top = WireMessage::TopOfBook {
+        header: WireHeader { instrument: 1, seq: 1, exchange_time: 0, relay_time: 0 },
+        bid: Level { price: prism_wire::to_fixed(30000.0), size: prism_wire::to_fixed(1.5) },
+        ask: Level { price: prism_wire::to_fixed(30000.5), size: prism_wire::to_fixed(2.0) },
+    };
+    let trade = WireMessage::Trade {
+        header: WireHeader { instrument: 0, seq: 2, exchange_time: 1689025543609000000, relay_time: 0 },
+        side: prism_wire::Side::Sell,
+        price: prism_wire::to_fixed(1897.1),
+        size: prism_wire::to_fixed(0.25),
+    };
+    [top, trade]
+}
+
+// Polls the listener's relay states until `done` holds for them.
+#[cfg(test)]
+async fn relay_states_until(states: &RelayStates, done: impl Fn(&[RelayState]) -> bool) -> Vec<RelayState> {
+    let wait = async {
+        loop {
+            let snapshot = states.snapshot();
+            if done(&snapshot) {
+                return snapshot;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(5), wait).await.expect("Timed out waiting for relay states")
+}
+
+#[tokio::test]
+async fn test_relay_handshake_and_messages() {
+    let (addr, adapter, states) = relay_listener(RelaySecurity::default()).await;
+    let mut relay = relay_connect(addr, None).await;
+    relay.send(relay_hello(&["ETH-USD", "BTC-USD"])).await.unwrap();
+    assert_eq!(WireMessage::Accept { version: prism_wire::VERSION }, relay_reply(&mut relay).await);
+    for message in relay_messages() {
+        relay.send(WsMessage::Binary(message.encode())).await.unwrap();
+    }
+    adapter.wait_for(2).await;
+
+    let events = adapter.events.lock().unwrap();
+    assert_eq!("okx", events[0].0);
+    match &events[0].1 {
+        FeedEvent::TopOfBook { pair, bid, ask } => {
+            assert_eq!("BTC-USD", pair.as_str());
+            assert_eq!((3000000, 1.5, 3000050), (bid.level, bid.amount, ask.level));
+        },
+        other => panic!("Expected top of book, got {:?}", other),
+    }
+    assert!(matches!(&events[1].1, FeedEvent::Trade { pair, trade: Match { side: Side::Sell, price: 189710, .. }, time: Some(1689025543609000000) } if pair == "ETH-USD"));
+    let state = &states.snapshot()[0];
+    assert_eq!(("okx", prism_wire::VERSION, true, 2, 0, 2), (state.venue.as_str(), state.version, state.active, state.messages, state.gaps, state.last_seq));
+}
+
+#[tokio::test]
+async fn test_relay_with_other_instruments_rejected() {
+    let (addr, adapter, states) = relay_listener(RelaySecurity::default()).await;
+    let mut relay = relay_connect(addr, None).await;
+    relay.send(relay_hello(&["BTC-USD"])).await.unwrap();
+    let reply = relay_reply(&mut relay).await;
+    assert_eq!(WireMessage::Reject { version: prism_wire::VERSION, reason: RejectReason::Instruments }, reply);
+    assert!(adapter.events.lock().unwrap().is_empty());
+    assert!(states.snapshot().is_empty());
+}
+
+#[tokio::test]
+async fn test_relay_for_direct_venue_rejected() {
+    let (addr, _, states) = relay_listener(RelaySecurity::default()).await;
+    let mut relay = relay_connect(addr, None).await;
+    relay.send(venue_hello("coinbase", &["ETH-USD", "BTC-USD"])).await.unwrap();
+    let reply = relay_reply(&mut relay).await;
+    assert_eq!(WireMessage::Reject { version: prism_wire::VERSION, reason: RejectReason::Venue }, reply);
+    assert!(states.snapshot().is_empty());
+}
+
+#[tokio::test]
+async fn test_relay_token_challenge() {
+    let security = RelaySecurity { tls: None, token: Some("secret".to_string()) };
+    let (addr, _, _) = relay_listener(security).await;
+    for (token, accepted) in [("secret", true), ("guess", false)] {
+        let mut relay = relay_connect(addr, None).await;
+        relay.send(relay_hello(&["ETH-USD", "BTC-USD"])).await.unwrap();
+        let nonce = match relay_reply(&mut relay).await {
+            WireMessage::Challenge { nonce } => nonce,
+            other => panic!("Expected a challenge, got {:?}", other),
+        };
+        let proof = prism_wire::auth_proof(token.as_bytes(), &nonce, "okx");
+        relay.send(WsMessage::Binary(WireMessage::Auth { proof }.encode())).await.unwrap();
+        let expected = match accepted {
+            true => WireMessage::Accept { version: prism_wire::VERSION },
+            false => WireMessage::Reject { version: prism_wire::VERSION, reason: RejectReason::Auth },
+        };
+        assert_eq!(expected, relay_reply(&mut relay).await);
+    }
+}
+
+#[tokio::test]
+async fn test_relay_over_tls() {
+    let cert = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/relay/cert.pem");
+    let key = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/relay/key.pem");
+    let security = RelaySecurity { tls: Some(RelaySecurity::tls(cert, key).unwrap()), token: None };
+    let root = native_tls::Certificate::from_pem(&std::fs::read(cert).unwrap()).unwrap();
+    let connector = native_tls::TlsConnector::builder().add_root_certificate(root).build().unwrap();
+    let (addr, _, _) = relay_listener(security).await;
+    let mut relay = relay_connect(addr, Some(connector)).await;
+    assert!(matches!(relay.get_ref(), MaybeTlsStream::NativeTls(_)));
+    relay.send(relay_hello(&["ETH-USD", "BTC-USD"])).await.unwrap();
+    assert_eq!(WireMessage::Accept { version: prism_wire::VERSION }, relay_reply(&mut relay).await);
+}
+
+#[cfg(test)]
+fn relay_top(seq: u64, bid: f64) -> WsMessage {
+    use prism_wire::{Header as WireHeader, Level};
+
+    let top = WireMessage::TopOfBook {
+        header: WireHeader { instrument: 1, seq, exchange_time: 0, relay_time: 0 },
+        bid: Level { price: prism_wire::to_fixed(bid), size: prism_wire::to_fixed(1.0) },
+        ask: Level { price: prism_wire::to_fixed(bid + 1.0), size: prism_wire::to_fixed(1.0) },
+    };
+    WsMessage::Binary(top.encode())
+}
+
+#[tokio::test]
+async fn test_top_returning_to_earlier_value_applied() {
+    let (addr, adapter, _) = relay_listener(RelaySecurity::default()).await;
+    let mut relay = relay_connect(addr, None).await;
+    relay.send(relay_hello(&["ETH-USD", "BTC-USD"])).await.unwrap();
+    relay_reply(&mut relay).await;
+    for (seq, bid) in [(1, 30000.0), (2, 30001.0), (3, 30000.0)] {
+        relay.send(relay_top(seq, bid)).await.unwrap();
+    }
+    adapter.wait_for(3).await;
+    let bids: Vec<usize> = adapter.events.lock().unwrap().iter().map(|(_, event)| match event {
+        FeedEvent::TopOfBook { bid, .. } => bid.level,
+        other => panic!("Expected top of book, got {:?}", other),
+    }).collect();
+    assert_eq!(vec![3000000, 3000100, 3000000], bids);
+}
+
+#[tokio::test]
+async fn test_hot_hot_relays_fail_over() {
+    let (addr, adapter, states) = relay_listener(RelaySecurity::default()).await;
+    let mut relays = Vec::new();
+    for _ in 0..2 {
+        let mut relay = relay_connect(addr, None).await;
+        relay.send(relay_hello(&["ETH-USD", "BTC-USD"])).await.unwrap();
+        relay_reply(&mut relay).await;
+        relays.push(relay);
+        relay_states_until(&states, |states| states.len() == relays.len()).await;
+    }
+    // Both relays carry the same updates, each with its own sequence; the first also repeats one.
+    for relay in relays.iter_mut() {
+        for message in relay_messages() {
+            relay.send(WsMessage::Binary(message.encode())).await.unwrap();
+        }
+    }
+    let repeat = relay_messages()[1].clone();
+    relays[0].send(WsMessage::Binary(repeat.encode())).await.unwrap();
+    let snapshot = relay_states_until(&states, |states| states.iter().map(|s| s.messages).sum::<u64>() == 5).await;
+    assert_eq!(2, adapter.events.lock().unwrap().len());
+    assert_eq!(vec![(true, 1, 0), (false, 0, 2)], snapshot.iter().map(|s| (s.active, s.duplicates, s.standby)).collect::<Vec<_>>());
+
+    // The standby takes over when the active relay drops, and the listener keeps accepting.
+    drop(relays.remove(0));
+    relay_states_until(&states, |states| states.len() == 1 && states[0].active).await;
+    relays[0].send(relay_top(3, 30001.0)).await.unwrap();
+    adapter.wait_for(3).await;
+    let mut relay = relay_connect(addr, None).await;
+    relay.send(relay_hello(&["ETH-USD", "BTC-USD"])).await.unwrap();
+    assert_eq!(WireMessage::Accept { version: prism_wire::VERSION }, relay_reply(&mut relay).await);
+    relay_states_until(&states, |states| states.len() == 2).await;
+    assert!(adapter.stale.lock().unwrap().is_empty());
+
+    // With no relay left the venue's books go stale.
+    drop(relays);
+    drop(relay);
+    relay_states_until(&states, |states| states.is_empty()).await;
+    let stale = async {
+        while adapter.stale.lock().unwrap().is_empty() {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(5), stale).await.unwrap();
+    assert_eq!(vec![("okx".to_string(), true)], *adapter.stale.lock().unwrap());
+}
+
+#[tokio::test]
+async fn test_relay_clock_from_pings() {
+    let (addr, _, states) = relay_listener(RelaySecurity::default()).await;
+    let mut relay = relay_connect(addr, None).await;
+    relay.send(relay_hello(&["ETH-USD", "BTC-USD"])).await.unwrap();
+    relay_reply(&mut relay).await;
+    // A relay clock 5ms ahead of ours.
+    let origin = match relay_reply(&mut relay).await {
+        WireMessage::Ping { origin } => origin,
+        other => panic!("Expected a ping, got {:?}", other),
+    };
+    let pong = WireMessage::Pong { origin, received: clock::now() + 5_000_000, sent: clock::now() + 5_000_000 };
+    relay.send(WsMessage::Binary(pong.encode())).await.unwrap();
+    let snapshot = relay_states_until(&states, |states| states.iter().any(|s| s.clock.is_some())).await;
+    let sample = snapshot[0].clock.unwrap();
+    assert!(sample.rtt >= 0 && sample.rtt < 1_000_000_000);
+    assert!((sample.offset - 5_000_000).abs() < sample.rtt + 1_000_000, "{:?}", sample);
+}
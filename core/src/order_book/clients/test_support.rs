@@ -0,0 +1,148 @@
+// Local stand-ins for venue endpoints, shared by the feed tests.
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::Notify;
+
+use super::feed::{BookAdapter, FeedEvent};
+
+#[derive(Clone, Default)]
+pub struct RecordingAdapter {
+    pub events: Arc<Mutex<Vec<(String, FeedEvent)>>>,
+    pub stale: Arc<Mutex<Vec<(String, bool)>>>,
+    notify: Arc<Notify>,
+}
+
+impl RecordingAdapter {
+    // Waits until at least `count` events have been applied.
+    pub async fn wait_for(&self, count: usize) {
+        let wait = async {
+            while self.events.lock().unwrap().len() < count {
+                self.notify.notified().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), wait).await
+            .expect("Timed out waiting for feed events");
+    }
+}
+
+impl BookAdapter for RecordingAdapter {
+    async fn apply(&mut self, venue: &str, event: FeedEvent) {
+        self.events.lock().unwrap().push((venue.to_string(), event));
+        self.notify.notify_one();
+    }
+
+    async fn set_stale(&mut self, venue: &str, stale: bool) {
+        self.stale.lock().unwrap().push((venue.to_string(), stale));
+    }
+}
+
+// Answers every request with `body`, recording each request line.
+pub async fn serve_http(body: String) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                }
+            }
+            let request = String::from_utf8_lossy(&request).to_string();
+            seen.lock().unwrap().push(request.lines().next().unwrap_or("").to_string());
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body,
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        }
+    });
+    (addr, requests)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    // Names lowercased.
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+}
+
+// Answers each request with the status and body `respond` picks for it, recording every request.
+pub async fn serve_rest<F>(respond: F) -> (SocketAddr, Arc<Mutex<Vec<HttpRequest>>>)
+    where
+    F: Fn(&HttpRequest) -> (u16, String) + Send + Sync + 'static,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = requests.clone();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut bytes = Vec::new();
+            let mut buf = [0u8; 1024];
+            let head_end = loop {
+                if let Some(end) = bytes.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break Some(end + 4);
+                }
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break None,
+                    Ok(n) => bytes.extend_from_slice(&buf[..n]),
+                }
+            };
+            let head_end = match head_end {
+                Some(end) => end,
+                None => continue,
+            };
+            let head = String::from_utf8_lossy(&bytes[..head_end]).to_string();
+            let mut lines = head.lines();
+            let mut request_line = lines.next().unwrap_or("").split(' ');
+            let mut request = HttpRequest {
+                method: request_line.next().unwrap_or("").to_string(),
+                path: request_line.next().unwrap_or("").to_string(),
+                ..Default::default()
+            };
+            for line in lines {
+                if let Some((name, value)) = line.split_once(':') {
+                    request.headers.push((name.trim().to_lowercase(), value.trim().to_string()));
+                }
+            }
+            let length = request.header("content-length").and_then(|l| l.parse::<usize>().ok()).unwrap_or(0);
+            while bytes.len() < head_end + length {
+                match stream.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => bytes.extend_from_slice(&buf[..n]),
+                }
+            }
+            request.body = String::from_utf8_lossy(&bytes[head_end..]).to_string();
+            let (status, body) = respond(&request);
+            seen.lock().unwrap().push(request);
+            let response = format!(
+                "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body,
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+            let _ = stream.shutdown().await;
+        }
+    });
+    (addr, requests)
+}
@@ -0,0 +1,250 @@
+use crate::order_book::data_types::{Change, Match, PriceLevel, Side};
+
+// Compact little-endian packets for relays publishing over UDP. Every packet carries a header
+// and one or more fixed-size frames:
+//
+//   header:      magic u16 | version u8 | frame count u8 | packet sequence u64
+//   change:      1 | pair id u16 | side u8 | level u64 | amount f64
+//   top of book: 2 | pair id u16 | bid level u64 | bid amount f64 | ask level u64 | ask amount f64
+//   trade:       3 | pair id u16 | side u8 | price u64 | size f64 | exchange time ns i64
+//
+// Pair ids index the instrument registry, so both ends must load the same instruments file.
+// Levels and prices are hundredths, as in the books.
+pub const MAGIC: u16 = 0x5052;
+pub const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = 12;
+// Keeps packets under a typical 1500 byte MTU once IP and UDP headers are added.
+pub const MAX_PACKET: usize = 1400;
+
+const CHANGE: u8 = 1;
+const TOP_OF_BOOK: u8 = 2;
+const TRADE: u8 = 3;
+
+#[derive(Debug, PartialEq)]
+pub enum FrameError {
+    Short(usize),
+    Magic(u16),
+    Version(u8),
+    Type(u8),
+    Side(u8),
+    // Encoded length of a packet too big for one datagram.
+    Oversize(usize),
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Short(len) => write!(f, "packet truncated at {} bytes", len),
+            FrameError::Magic(magic) => write!(f, "bad magic {:#06x}", magic),
+            FrameError::Version(version) => write!(f, "unsupported version {}", version),
+            FrameError::Type(frame_type) => write!(f, "unknown frame type {}", frame_type),
+            FrameError::Side(side) => write!(f, "unknown side {}", side),
+            FrameError::Oversize(len) => write!(f, "packet of {} bytes exceeds {} bytes", len, MAX_PACKET),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Frame {
+    Change { pair_id: u16, change: Change },
+    TopOfBook { pair_id: u16, bid: PriceLevel, ask: PriceLevel },
+    Trade { pair_id: u16, trade: Match, time: Option<i64> },
+}
+
+impl Frame {
+    pub fn encoded_len(&self) -> usize {
+        match self {
+            Frame::Change { .. } => 20,
+            Frame::TopOfBook { .. } => 35,
+            Frame::Trade { .. } => 28,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Frame::Change { pair_id, change } => {
+                out.push(CHANGE);
+                out.extend_from_slice(&pair_id.to_le_bytes());
+                out.push(side_byte(change.side));
+                out.extend_from_slice(&(change.price_level.level as u64).to_le_bytes());
+                out.extend_from_slice(&change.price_level.amount.to_le_bytes());
+            },
+            Frame::TopOfBook { pair_id, bid, ask } => {
+                out.push(TOP_OF_BOOK);
+                out.extend_from_slice(&pair_id.to_le_bytes());
+                for level in [bid, ask] {
+                    out.extend_from_slice(&(level.level as u64).to_le_bytes());
+                    out.extend_from_slice(&level.amount.to_le_bytes());
+                }
+            },
+            Frame::Trade { pair_id, trade, time } => {
+                out.push(TRADE);
+                out.extend_from_slice(&pair_id.to_le_bytes());
+                out.push(side_byte(trade.side));
+                out.extend_from_slice(&(trade.price as u64).to_le_bytes());
+                out.extend_from_slice(&trade.size.to_le_bytes());
+                out.extend_from_slice(&time.unwrap_or(0).to_le_bytes());
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Packet {
+    pub seq: u64,
+    pub frames: Vec<Frame>,
+}
+
+impl Packet {
+    // Refuses packets that don't fit in one datagram, which also keeps the frame count within its
+    // byte.
+    pub fn encode(&self) -> Result<Vec<u8>, FrameError> {
+        let len = HEADER_LEN + self.frames.iter().map(|f| f.encoded_len()).sum::<usize>();
+        if len > MAX_PACKET || self.frames.len() > u8::MAX as usize {
+            return Err(FrameError::Oversize(len));
+        }
+        let mut out = Vec::with_capacity(len);
+        out.extend_from_slice(&MAGIC.to_le_bytes());
+        out.push(VERSION);
+        out.push(self.frames.len() as u8);
+        out.extend_from_slice(&self.seq.to_le_bytes());
+        for frame in self.frames.iter() {
+            frame.encode(&mut out);
+        }
+        Ok(out)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Packet, FrameError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let magic = reader.u16()?;
+        if magic != MAGIC {
+            return Err(FrameError::Magic(magic));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(FrameError::Version(version));
+        }
+        let count = reader.u8()?;
+        let seq = reader.u64()?;
+        let mut frames = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let frame = match reader.u8()? {
+                CHANGE => {
+                    let pair_id = reader.u16()?;
+                    let side = reader.side()?;
+                    let level = reader.u64()? as usize;
+                    let amount = reader.f64()?;
+                    Frame::Change { pair_id, change: Change { side, price_level: PriceLevel { level, amount, sequence: 0 } } }
+                },
+                TOP_OF_BOOK => {
+                    let pair_id = reader.u16()?;
+                    let bid = PriceLevel { level: reader.u64()? as usize, amount: reader.f64()?, sequence: 0 };
+                    let ask = PriceLevel { level: reader.u64()? as usize, amount: reader.f64()?, sequence: 0 };
+                    Frame::TopOfBook { pair_id, bid, ask }
+                },
+                TRADE => {
+                    let pair_id = reader.u16()?;
+                    let side = reader.side()?;
+                    let price = reader.u64()? as usize;
+                    let size = reader.f64()?;
+                    let time = reader.i64()?;
+                    Frame::Trade { pair_id, trade: Match { side, size, price }, time: if time == 0 { None } else { Some(time) } }
+                },
+                other => return Err(FrameError::Type(other)),
+            };
+            frames.push(frame);
+        }
+        Ok(Packet { seq, frames })
+    }
+}
+
+fn side_byte(side: Side) -> u8 {
+    match side {
+        Side::Buy => 0,
+        Side::Sell => 1,
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], FrameError> {
+        let end = self.pos + N;
+        let bytes = self.bytes.get(self.pos..end).ok_or(FrameError::Short(self.bytes.len()))?;
+        self.pos = end;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, FrameError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, FrameError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, FrameError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, FrameError> {
+        Ok(i64::from_le_bytes(self.take()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, FrameError> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    fn side(&mut self) -> Result<Side, FrameError> {
+        match self.u8()? {
+            0 => Ok(Side::Buy),
+            1 => Ok(Side::Sell),
+            other => Err(FrameError::Side(other)),
+        }
+    }
+}
+
+#[test]
+fn test_packet_round_trip() {
+    let packet = Packet {
+        seq: 42,
+        frames: vec![
+            Frame::Change { pair_id: 1, change: Change { side: Side::Sell, price_level: PriceLevel { level: 3000100, amount: 0.5, sequence: 0 } } },
+            Frame::TopOfBook {
+                pair_id: 0,
+                bid: PriceLevel { level: 189710, amount: 2.0, sequence: 0 },
+                ask: PriceLevel { level: 189720, amount: 1.0, sequence: 0 },
+            },
+            Frame::Trade { pair_id: 1, trade: Match { side: Side::Buy, size: 0.1, price: 3000000 }, time: Some(1689025543609000000) },
+        ],
+    };
+    let bytes = packet.encode().unwrap();
+    assert_eq!(HEADER_LEN + 20 + 35 + 28, bytes.len());
+    assert_eq!(packet, Packet::decode(&bytes).unwrap());
+}
+
+#[test]
+fn test_decode_rejects_bad_packets() {
+    let bytes = Packet { seq: 1, frames: vec![Frame::Trade { pair_id: 0, trade: Match { side: Side::Buy, size: 1.0, price: 1 }, time: None }] }.encode().unwrap();
+    assert_eq!(Err(FrameError::Short(bytes.len() - 1)), Packet::decode(&bytes[..bytes.len() - 1]));
+    let mut bad = bytes.clone();
+    bad[2] = 9;
+    assert_eq!(Err(FrameError::Version(9)), Packet::decode(&bad));
+    let mut bad = bytes.clone();
+    bad[HEADER_LEN] = 7;
+    assert_eq!(Err(FrameError::Type(7)), Packet::decode(&bad));
+}
+
+#[test]
+fn test_encode_rejects_oversize_packets() {
+    let change = |level| Frame::Change { pair_id: 0, change: Change { side: Side::Buy, price_level: PriceLevel { level, amount: 1.0, sequence: 0 } } };
+    let full = Packet { seq: 1, frames: (0..69).map(change).collect() };
+    assert_eq!(MAX_PACKET - 8, full.encode().unwrap().len());
+    let over = Packet { seq: 1, frames: (0..70).map(change).collect() };
+    assert_eq!(Err(FrameError::Oversize(HEADER_LEN + 70 * 20)), over.encode());
+    let many = Packet { seq: 1, frames: (0..300).map(change).collect() };
+    assert!(matches!(many.encode(), Err(FrameError::Oversize(_))));
+}
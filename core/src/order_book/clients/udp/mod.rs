@@ -0,0 +1,4 @@
+pub mod frame;
+pub mod receiver;
+
+pub use receiver::UDPClient;
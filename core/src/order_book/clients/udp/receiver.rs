@@ -0,0 +1,255 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::order_book::clients::feed::{BookAdapter, FeedEvent, LatencyStats};
+use crate::order_book::instruments::InstrumentRegistry;
+
+use super::frame::{Frame, Packet};
+
+// How long a retransmit request may take before the gap is given up on.
+const RETRANSMIT_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct UdpConfig {
+    // Venue the packets are applied to in the books.
+    pub venue: &'static str,
+    pub bind: SocketAddr,
+    // Multicast group and the local interface to join it on.
+    pub multicast: Option<(Ipv4Addr, Ipv4Addr)>,
+    // Publisher's TCP endpoint for missed packets. The request is the first missing sequence
+    // and the one after the last, as little-endian u64s; the reply is each packet prefixed by
+    // its u16 length, then the connection is closed.
+    pub retransmit: Option<SocketAddr>,
+}
+
+pub struct UDPClient<A: BookAdapter> {
+    config: UdpConfig,
+    pairs: Vec<heapless::String<8>>,
+    adapter: A,
+    next_seq: Option<u64>,
+    latency: LatencyStats,
+}
+
+impl<A: BookAdapter> UDPClient<A> {
+    pub fn new(config: UdpConfig, registry: Arc<InstrumentRegistry>, adapter: A) -> Self {
+        let venue = config.venue;
+        UDPClient {
+            config,
+            pairs: registry.pairs(),
+            adapter,
+            next_seq: None,
+            latency: LatencyStats::new(venue),
+        }
+    }
+
+    pub async fn bind(&self) -> std::io::Result<UdpSocket> {
+        let socket = UdpSocket::bind(self.config.bind).await?;
+        if let Some((group, interface)) = self.config.multicast {
+            socket.join_multicast_v4(group, interface)?;
+        }
+        Ok(socket)
+    }
+
+    pub async fn run(&mut self) {
+        let socket = match self.bind().await {
+            Ok(socket) => socket,
+            Err(err) => {
+                println!("{} UDP: failed to bind {:?}: {:?}", self.config.venue, self.config.bind, err);
+                return;
+            },
+        };
+        self.receive(&socket).await;
+    }
+
+    async fn receive(&mut self, socket: &UdpSocket) {
+        let mut buf = [0u8; 2048];
+        loop {
+            let len = match socket.recv(&mut buf).await {
+                Ok(len) => len,
+                Err(err) => {
+                    println!("{} UDP: receive error: {:?}", self.config.venue, err);
+                    continue;
+                },
+            };
+            match Packet::decode(&buf[..len]) {
+                Ok(packet) => self.handle(packet).await,
+                Err(err) => println!("{} UDP: dropping packet: {}", self.config.venue, err),
+            }
+        }
+    }
+
+    async fn handle(&mut self, packet: Packet) {
+        if let Some(expected) = self.next_seq {
+            if packet.seq < expected {
+                return;
+            }
+            if packet.seq > expected {
+                self.recover(expected, packet.seq).await;
+                if self.next_seq != Some(packet.seq) {
+                    println!("{} UDP: lost packets {:?} to {:?}", self.config.venue, self.next_seq.unwrap_or(expected), packet.seq - 1);
+                    // Deltas after a gap can't be trusted until the venue's top of book is resent.
+                    self.adapter.set_stale(self.config.venue, true).await;
+                }
+            }
+        }
+        self.apply(packet).await;
+    }
+
+    async fn recover(&mut self, from: u64, to: u64) {
+        let addr = match self.config.retransmit {
+            Some(addr) => addr,
+            None => return,
+        };
+        let packets = match tokio::time::timeout(RETRANSMIT_TIMEOUT, retransmit(addr, from, to)).await {
+            Ok(Ok(packets)) => packets,
+            Ok(Err(err)) => {
+                println!("{} UDP: retransmit request failed: {:?}", self.config.venue, err);
+                return;
+            },
+            Err(_) => {
+                println!("{} UDP: retransmit request timed out", self.config.venue);
+                return;
+            },
+        };
+        for packet in packets {
+            if Some(packet.seq) == self.next_seq && packet.seq < to {
+                self.apply(packet).await;
+            }
+        }
+    }
+
+    async fn apply(&mut self, packet: Packet) {
+        self.next_seq = Some(packet.seq + 1);
+        let mut events = Vec::new();
+        for frame in packet.frames {
+            let pair_id = match &frame {
+                Frame::Change { pair_id, .. } | Frame::TopOfBook { pair_id, .. } | Frame::Trade { pair_id, .. } => *pair_id,
+            };
+            let pair = match self.pairs.get(pair_id as usize) {
+                Some(pair) => pair.clone(),
+                None => {
+                    println!("{} UDP: unknown pair id {:?}", self.config.venue, pair_id);
+                    continue;
+                },
+            };
+            match frame {
+                Frame::Change { change, .. } => {
+                    // Consecutive changes for a pair are applied as one delta.
+                    if let Some(FeedEvent::Delta { pair: last, changes }) = events.last_mut() {
+                        if *last == pair && !changes.is_full() {
+                            let _ = changes.push(change);
+                            continue;
+                        }
+                    }
+                    let mut changes = heapless::Vec::new();
+                    let _ = changes.push(change);
+                    events.push(FeedEvent::Delta { pair, changes });
+                },
+                Frame::TopOfBook { bid, ask, .. } => events.push(FeedEvent::TopOfBook { pair, bid, ask }),
+                Frame::Trade { trade, time, .. } => events.push(FeedEvent::Trade { pair, trade, time }),
+            }
+        }
+        for event in events {
+            if let FeedEvent::Trade { time: Some(time), .. } = event {
+                self.latency.record(time);
+            }
+            self.adapter.apply(self.config.venue, event).await;
+        }
+    }
+}
+
+async fn retransmit(addr: SocketAddr, from: u64, to: u64) -> std::io::Result<Vec<Packet>> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let mut request = from.to_le_bytes().to_vec();
+    request.extend_from_slice(&to.to_le_bytes());
+    stream.write_all(&request).await?;
+    let mut reply = Vec::new();
+    stream.read_to_end(&mut reply).await?;
+    let mut packets = Vec::new();
+    let mut pos = 0;
+    while pos + 2 <= reply.len() {
+        let len = u16::from_le_bytes([reply[pos], reply[pos + 1]]) as usize;
+        pos += 2;
+        let bytes = reply.get(pos..pos + len)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "truncated retransmit reply"))?;
+        packets.push(Packet::decode(bytes).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e.to_string()))?);
+        pos += len;
+    }
+    Ok(packets)
+}
+
+#[cfg(test)]
+fn top_of_book(seq: u64, pair_id: u16, bid: usize) -> Packet {
+    use crate::order_book::data_types::PriceLevel;
+    Packet {
+        seq,
+        frames: vec![Frame::TopOfBook {
+            pair_id,
+            bid: PriceLevel { level: bid, amount: 1.0, sequence: 0 },
+            ask: PriceLevel { level: bid + 1, amount: 1.0, sequence: 0 },
+        }],
+    }
+}
+
+#[cfg(test)]
+fn test_client(retransmit: Option<SocketAddr>) -> (UDPClient<crate::order_book::clients::test_support::RecordingAdapter>, crate::order_book::clients::test_support::RecordingAdapter) {
+    let registry = InstrumentRegistry::parse(include_str!("../../../../config/instruments.json")).unwrap();
+    let adapter = crate::order_book::clients::test_support::RecordingAdapter::default();
+    let config = UdpConfig {
+        venue: "binance",
+        bind: "127.0.0.1:0".parse().unwrap(),
+        multicast: None,
+        retransmit,
+    };
+    (UDPClient::new(config, Arc::new(registry), adapter.clone()), adapter)
+}
+
+#[tokio::test]
+async fn test_gap_filled_by_retransmit() {
+    let retransmit_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let retransmit_addr = retransmit_listener.local_addr().unwrap();
+    let retransmit_server = tokio::spawn(async move {
+        let (mut stream, _) = retransmit_listener.accept().await.unwrap();
+        let mut request = [0u8; 16];
+        stream.read_exact(&mut request).await.unwrap();
+        let bytes = top_of_book(3, 1, 3000000).encode().unwrap();
+        stream.write_all(&(bytes.len() as u16).to_le_bytes()).await.unwrap();
+        stream.write_all(&bytes).await.unwrap();
+        request
+    });
+
+    let (mut client, adapter) = test_client(Some(retransmit_addr));
+    let socket = client.bind().await.unwrap();
+    let publisher = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    publisher.connect(socket.local_addr().unwrap()).await.unwrap();
+    for packet in [top_of_book(1, 0, 189710), top_of_book(2, 0, 189720), top_of_book(4, 1, 3000100), top_of_book(2, 0, 189720)] {
+        publisher.send(&packet.encode().unwrap()).await.unwrap();
+    }
+    tokio::select! {
+        _ = client.receive(&socket) => panic!("Receiver exited"),
+        _ = adapter.wait_for(4) => (),
+    }
+
+    let request = retransmit_server.await.unwrap();
+    assert_eq!((3, 4), (u64::from_le_bytes(request[..8].try_into().unwrap()), u64::from_le_bytes(request[8..].try_into().unwrap())));
+    let events = adapter.events.lock().unwrap();
+    let bids: Vec<(&str, usize)> = events.iter().map(|(_, event)| match event {
+        FeedEvent::TopOfBook { pair, bid, .. } => (pair.as_str(), bid.level),
+        other => panic!("Expected top of book, got {:?}", other),
+    }).collect();
+    assert_eq!(vec![("ETH-USD", 189710), ("ETH-USD", 189720), ("BTC-USD", 3000000), ("BTC-USD", 3000100)], bids);
+    assert!(adapter.stale.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_unrecovered_gap_marks_stale() {
+    let (mut client, adapter) = test_client(None);
+    client.handle(top_of_book(7, 0, 189710)).await;
+    client.handle(top_of_book(9, 0, 189720)).await;
+    assert_eq!(vec![("binance".to_string(), true)], *adapter.stale.lock().unwrap());
+    assert_eq!(2, adapter.events.lock().unwrap().len());
+    assert_eq!(Some(10), client.next_seq);
+}
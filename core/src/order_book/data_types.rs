@@ -1,9 +1,38 @@
+use serde::{Deserialize, Serialize};
+
 #[derive(Debug, PartialEq)]
 pub struct Snapshot {
     pub bids: Box<heapless::Vec<PriceLevel, 65536>>,
     pub asks: Box<heapless::Vec<PriceLevel, 65536>>,
 }
 
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot {
+    pub fn new() -> Self {
+        Snapshot {
+            bids: Box::new(heapless::Vec::new()),
+            asks: Box::new(heapless::Vec::new()),
+        }
+    }
+}
+
+// Book prices are whole hundredths of the quote asset on every venue, so levels compare across
+// venues. The registry refuses listings whose tick is finer than that.
+pub const PRICE_SCALE: f64 = 100.0;
+
+pub fn to_level(price: f64) -> usize {
+    (price * PRICE_SCALE).round() as usize
+}
+
+pub fn to_price(level: usize) -> f64 {
+    level as f64 / PRICE_SCALE
+}
+
 #[derive(Copy, Clone, Default, Debug, PartialEq)]
 pub struct PriceLevel {
     pub level: usize,
@@ -31,7 +60,8 @@ pub struct Match {
     pub price: usize,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Side {
     Buy,
     Sell,
@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+pub const DEFAULT_HORIZONS: [Duration; 3] = [
+    Duration::from_millis(100),
+    Duration::from_secs(1),
+    Duration::from_secs(10),
+];
+
+#[derive(Clone, Copy, Debug)]
+struct Prediction {
+    time: i64,
+    mid: f64,
+    theoretical: f64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HorizonStats {
+    pub count: usize,
+    pub directional: usize,
+    pub hits: usize,
+    sum_error: f64,
+    sum_abs_error: f64,
+    sum_x: f64,
+    sum_y: f64,
+    sum_xx: f64,
+    sum_yy: f64,
+    sum_xy: f64,
+}
+
+impl HorizonStats {
+    fn add(&mut self, prediction: &Prediction, realised: f64) {
+        let predicted_return = (prediction.theoretical - prediction.mid) / prediction.mid;
+        let realised_return = (realised - prediction.mid) / prediction.mid;
+        let error = (prediction.theoretical - realised) / realised * 10000.0;
+        self.count += 1;
+        if predicted_return != 0.0 && realised_return != 0.0 {
+            self.directional += 1;
+            if predicted_return.signum() == realised_return.signum() {
+                self.hits += 1;
+            }
+        }
+        self.sum_error += error;
+        self.sum_abs_error += error.abs();
+        self.sum_x += predicted_return;
+        self.sum_y += realised_return;
+        self.sum_xx += predicted_return * predicted_return;
+        self.sum_yy += realised_return * realised_return;
+        self.sum_xy += predicted_return * realised_return;
+    }
+
+    pub fn hit_rate(&self) -> Option<f64> {
+        if self.directional == 0 {
+            return None;
+        }
+        Some(self.hits as f64 / self.directional as f64)
+    }
+
+    // Mean signed error of the theoretical price against the realised mid, in basis points.
+    pub fn mean_error(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.sum_error / self.count as f64)
+    }
+
+    pub fn mean_abs_error(&self) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(self.sum_abs_error / self.count as f64)
+    }
+
+    // Pearson correlation between the predicted and realised mid returns.
+    pub fn information_coefficient(&self) -> Option<f64> {
+        let n = self.count as f64;
+        let cov = n * self.sum_xy - self.sum_x * self.sum_y;
+        let var_x = n * self.sum_xx - self.sum_x * self.sum_x;
+        let var_y = n * self.sum_yy - self.sum_y * self.sum_y;
+        if self.count < 2 || var_x <= 0.0 || var_y <= 0.0 {
+            return None;
+        }
+        Some(cov / (var_x.sqrt() * var_y.sqrt()))
+    }
+}
+
+pub struct Evaluator {
+    horizons: Vec<Duration>,
+    pending: Vec<VecDeque<Prediction>>,
+    pub stats: Vec<HorizonStats>,
+    last_mid: Option<f64>,
+}
+
+impl Default for Evaluator {
+    fn default() -> Self {
+        Evaluator::new(&DEFAULT_HORIZONS)
+    }
+}
+
+impl Evaluator {
+    pub fn new(horizons: &[Duration]) -> Self {
+        Evaluator {
+            horizons: horizons.to_vec(),
+            pending: horizons.iter().map(|_| VecDeque::new()).collect(),
+            stats: horizons.iter().map(|_| HorizonStats::default()).collect(),
+            last_mid: None,
+        }
+    }
+
+    pub fn horizons(&self) -> &[Duration] {
+        &self.horizons
+    }
+
+    pub fn record(&mut self, time: i64, theoretical: usize, mid: f64) {
+        if theoretical == 0 || mid <= 0.0 {
+            return;
+        }
+        let prediction = Prediction { time, mid, theoretical: theoretical as f64 };
+        for pending in self.pending.iter_mut() {
+            pending.push_back(prediction);
+        }
+    }
+
+    // Called after every book change. Predictions whose horizon has passed are scored against the
+    // mid that was in force at the deadline, which is the mid from before this change.
+    pub fn observe(&mut self, time: i64, mid: f64) {
+        if let Some(realised) = self.last_mid {
+            for (i, horizon) in self.horizons.iter().enumerate() {
+                let horizon = horizon.as_nanos() as i64;
+                while let Some(prediction) = self.pending[i].front() {
+                    if prediction.time + horizon > time {
+                        break;
+                    }
+                    let prediction = self.pending[i].pop_front().unwrap();
+                    self.stats[i].add(&prediction, realised);
+                }
+            }
+        }
+        self.last_mid = Some(mid);
+    }
+
+    pub fn print(&self, name: &str) {
+        for (horizon, stats) in self.horizons.iter().zip(self.stats.iter()) {
+            println!(
+                "{:?} {:?} horizon: {} predictions, hit rate {}, mean error {} bps, mean abs. error {} bps, IC {}",
+                name,
+                horizon,
+                stats.count,
+                format_stat(stats.hit_rate().map(|h| h * 100.0), "%"),
+                format_stat(stats.mean_error(), ""),
+                format_stat(stats.mean_abs_error(), ""),
+                format_stat(stats.information_coefficient(), ""),
+            );
+        }
+    }
+}
+
+fn format_stat(value: Option<f64>, suffix: &str) -> String {
+    match value {
+        Some(v) => format!("{:.4}{}", v, suffix),
+        None => "n/a".to_string(),
+    }
+}
+
+#[test]
+fn test_evaluator_scores_at_horizon() {
+    let mut evaluator = Evaluator::new(&[Duration::from_millis(100), Duration::from_secs(1)]);
+    evaluator.observe(0, 10000.0);
+    evaluator.record(0, 10010, 10000.0);
+    evaluator.observe(50_000_000, 10020.0);
+    assert_eq!(0, evaluator.stats[0].count);
+    evaluator.observe(150_000_000, 9990.0);
+    assert_eq!(1, evaluator.stats[0].count);
+    assert_eq!(1, evaluator.stats[0].hits);
+    assert_eq!(0, evaluator.stats[1].count);
+    evaluator.observe(2_000_000_000, 9990.0);
+    assert_eq!(1, evaluator.stats[1].count);
+    assert_eq!(0, evaluator.stats[1].hits);
+}
+
+#[test]
+fn test_evaluator_information_coefficient() {
+    let mut evaluator = Evaluator::new(&[Duration::from_millis(100)]);
+    let mut time = 0;
+    let mut mid = 10000.0;
+    evaluator.observe(time, mid);
+    for step in [5.0, -3.0, 8.0, -1.0, 2.0] {
+        evaluator.record(time, (mid + step) as usize, mid);
+        mid += step * 2.0;
+        evaluator.observe(time + 50_000_000, mid);
+        time += 200_000_000;
+        evaluator.observe(time, mid);
+    }
+    let stats = evaluator.stats[0];
+    assert_eq!(5, stats.count);
+    assert_eq!(Some(1.0), stats.hit_rate());
+    assert!((stats.information_coefficient().unwrap() - 1.0).abs() < 1e-6);
+    assert!(stats.mean_error().unwrap() < 0.0);
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::fs;
+
+use serde::Deserialize;
+
+use super::data_types::{to_price, PRICE_SCALE};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum InstrumentKind {
+    #[default]
+    Spot,
+    Perpetual,
+    Future,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Hash)]
+pub struct Instrument {
+    pub base: String,
+    pub quote: String,
+    #[serde(default)]
+    pub kind: InstrumentKind,
+}
+
+impl Instrument {
+    // Canonical pair name, which is also the `MultiBook` pair.
+    pub fn pair(&self) -> heapless::String<8> {
+        heapless::String::from(format!("{}-{}", self.base, self.quote).as_str())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Listing {
+    pub symbol: String,
+    pub tick_size: f64,
+    pub lot_size: f64,
+    #[serde(default)]
+    pub channels: HashMap<String, String>,
+}
+
+impl Listing {
+    pub fn channel(&self, name: &str) -> Option<&str> {
+        self.channels.get(name).map(|c| c.as_str())
+    }
+
+    // Decimal places implied by the tick and lot sizes, e.g. 0.01 -> 2.
+    pub fn price_precision(&self) -> usize {
+        precision(self.tick_size)
+    }
+
+    pub fn qty_precision(&self) -> usize {
+        precision(self.lot_size)
+    }
+
+    // Order prices are hundredths; venues take them at the listing's precision.
+    pub fn format_price(&self, level: usize) -> String {
+        format!("{:.*}", self.price_precision(), to_price(level))
+    }
+
+    pub fn format_qty(&self, size: f64) -> String {
+        format!("{:.*}", self.qty_precision(), size)
+    }
+}
+
+fn precision(step: f64) -> usize {
+    (-step.log10()).round().max(0.0) as usize
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct InstrumentConfig {
+    #[serde(flatten)]
+    instrument: Instrument,
+    venues: HashMap<String, Listing>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+struct RegistryConfig {
+    instruments: Vec<InstrumentConfig>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "{}", err),
+            ConfigError::Parse(err) => write!(f, "{}", err),
+            ConfigError::Invalid(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InstrumentRegistry {
+    instruments: Vec<InstrumentConfig>,
+}
+
+impl InstrumentRegistry {
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        InstrumentRegistry::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let config: RegistryConfig = serde_json::from_str(contents).map_err(ConfigError::Parse)?;
+        let mut seen = Vec::new();
+        for instrument in config.instruments.iter() {
+            let pair = format!("{}-{}", instrument.instrument.base, instrument.instrument.quote);
+            if pair.len() > 8 {
+                return Err(ConfigError::Invalid(format!("Pair name {:?} is longer than 8 characters", pair)));
+            }
+            if seen.contains(&pair) {
+                return Err(ConfigError::Invalid(format!("Duplicate instrument {:?}", pair)));
+            }
+            for (venue, listing) in instrument.venues.iter() {
+                if listing.tick_size <= 0.0 || listing.lot_size <= 0.0 {
+                    return Err(ConfigError::Invalid(format!("{:?} on {:?} needs a positive tick and lot size", pair, venue)));
+                }
+                // Every tick has to land on a book level.
+                let ticks = listing.tick_size * PRICE_SCALE;
+                if ticks < 1.0 - 1e-9 || (ticks - ticks.round()).abs() > 1e-9 {
+                    return Err(ConfigError::Invalid(format!("{:?} on {:?} has a tick size finer than the books' {:?}", pair, venue, 1.0 / PRICE_SCALE)));
+                }
+            }
+            seen.push(pair);
+        }
+        Ok(InstrumentRegistry { instruments: config.instruments })
+    }
+
+    pub fn instruments(&self) -> impl Iterator<Item = &Instrument> {
+        self.instruments.iter().map(|i| &i.instrument)
+    }
+
+    pub fn pairs(&self) -> Vec<heapless::String<8>> {
+        self.instruments().map(|i| i.pair()).collect()
+    }
+
+    pub fn listing(&self, venue: &str, pair: &str) -> Option<&Listing> {
+        self.instruments.iter()
+            .find(|i| i.instrument.pair().as_str() == pair)
+            .and_then(|i| i.venues.get(venue))
+    }
+
+    // Every instrument listed on `venue`, as (canonical pair, listing).
+    pub fn venue_listings(&self, venue: &str) -> Vec<(heapless::String<8>, &Listing)> {
+        self.instruments.iter()
+            .filter_map(|i| i.venues.get(venue).map(|l| (i.instrument.pair(), l)))
+            .collect()
+    }
+
+    // Drops every instrument not in `pairs`, for processes that only carry some of the file.
+    pub fn retain_pairs(&mut self, pairs: &[String]) {
+        self.instruments.retain(|i| pairs.iter().any(|p| p.as_str() == i.instrument.pair().as_str()));
+    }
+
+    pub fn pair_for_symbol(&self, venue: &str, symbol: &str) -> Option<heapless::String<8>> {
+        self.instruments.iter()
+            .find(|i| i.venues.get(venue).is_some_and(|l| l.symbol == symbol))
+            .map(|i| i.instrument.pair())
+    }
+}
+
+#[test]
+fn test_registry() {
+    let input = r#"
+    {
+        "instruments": [
+            {
+                "base": "SOL",
+                "quote": "USD",
+                "venues": {
+                    "coinbase": { "symbol": "SOL-USD", "tick_size": 0.01, "lot_size": 0.001 },
+                    "kraken": { "symbol": "SOL/USD", "tick_size": 0.01, "lot_size": 0.001, "channels": { "book": "book" } }
+                }
+            }
+        ]
+    }"#;
+    let registry = InstrumentRegistry::parse(input).unwrap();
+    assert_eq!(vec![heapless::String::<8>::from("SOL-USD")], registry.pairs());
+    assert_eq!(InstrumentKind::Spot, registry.instruments().next().unwrap().kind);
+    assert_eq!("SOL/USD", registry.listing("kraken", "SOL-USD").unwrap().symbol);
+    assert_eq!(Some("book"), registry.listing("kraken", "SOL-USD").unwrap().channel("book"));
+    assert_eq!(None, registry.listing("gemini", "SOL-USD"));
+    assert_eq!(Some(heapless::String::<8>::from("SOL-USD")), registry.pair_for_symbol("coinbase", "SOL-USD"));
+    assert_eq!(1, registry.venue_listings("coinbase").len());
+    assert_eq!(2, registry.listing("kraken", "SOL-USD").unwrap().price_precision());
+    assert_eq!(3, registry.listing("kraken", "SOL-USD").unwrap().qty_precision());
+}
+
+#[test]
+fn test_registry_rejects_bad_config() {
+    let long = r#"{"instruments": [{"base": "MATIC", "quote": "USDT", "venues": {}}]}"#;
+    assert!(matches!(InstrumentRegistry::parse(long), Err(ConfigError::Invalid(_))));
+    let tick = r#"{"instruments": [{"base": "SOL", "quote": "USD", "venues": {"coinbase": {"symbol": "SOL-USD", "tick_size": 0, "lot_size": 1}}}]}"#;
+    assert!(matches!(InstrumentRegistry::parse(tick), Err(ConfigError::Invalid(_))));
+    let fine = r#"{"instruments": [{"base": "SOL", "quote": "USD", "venues": {"coinbase": {"symbol": "SOL-USD", "tick_size": 0.001, "lot_size": 1}}}]}"#;
+    assert!(matches!(InstrumentRegistry::parse(fine), Err(ConfigError::Invalid(_))));
+}
+
+#[test]
+fn test_registry_default_config() {
+    let registry = InstrumentRegistry::parse(include_str!("../../config/instruments.json")).unwrap();
+    assert_eq!(vec![heapless::String::<8>::from("ETH-USD"), heapless::String::<8>::from("BTC-USD")], registry.pairs());
+    assert_eq!(Some(heapless::String::<8>::from("BTC-USD")), registry.pair_for_symbol("binance", "BTCUSDT"));
+}
+
+#[test]
+fn test_registry_retain_pairs() {
+    let mut registry = InstrumentRegistry::parse(include_str!("../../config/instruments.json")).unwrap();
+    registry.retain_pairs(&["BTC-USD".to_string()]);
+    assert_eq!(vec![heapless::String::<8>::from("BTC-USD")], registry.pairs());
+    assert_eq!(None, registry.pair_for_symbol("binance", "ETHUSDT"));
+}
+
+#[test]
+fn test_listing_formats_orders() {
+    let listing = |tick_size, lot_size| Listing { symbol: "BTC-USD".to_string(), tick_size, lot_size, channels: HashMap::new() };
+    assert_eq!(("30000.05", "0.25000000"), (listing(0.01, 1e-8).format_price(3000005).as_str(), listing(0.01, 1e-8).format_qty(0.25).as_str()));
+    assert_eq!("30000.2", listing(0.1, 1e-8).format_price(3000020));
+    assert_eq!(("30000", "2"), (listing(1.0, 1.0).format_price(3000000).as_str(), listing(1.0, 1.0).format_qty(2.0).as_str()));
+    assert_eq!("0.50", listing(0.01, 1e-8).format_price(50));
+}
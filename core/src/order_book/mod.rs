@@ -1,4 +1,11 @@
+#[allow(clippy::module_inception)]
 pub mod order_book;
 pub mod multi_book;
 pub mod clients;
-pub mod data_types;
\ No newline at end of file
+pub mod data_types;
+pub mod evaluator;
+pub mod replay;
+pub mod volatility;
+pub mod instruments;
+pub mod paper;
+pub mod portfolio;
@@ -1,12 +1,30 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
 use chrono::Local;
 
-use super::{data_types::Side, order_book::OrderBook};
+use super::{data_types::Side, order_book::OrderBook, evaluator::Evaluator, replay::Recorder};
+use super::clients::orders::{OrderRequest, TimeInForce};
+use super::paper::PaperExchange;
+use super::portfolio::Portfolio;
+use super::volatility::{VolatilityConfig, VolatilityEstimator};
+
+const ARB_THRESHOLD: f64 = 0.002;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThresholdScaling {
+    pub reference_volatility: f64,
+    pub max_scale: f64,
+}
 
 #[derive(Clone, Copy, Debug, Default)]
 pub struct Spread {
     pub raw: isize,
     pub percentage: f64,
-    pub seqs: [i64; 2],
+    // The ask we buy at and the bid we sell at, as (level, amount).
+    pub tops: [(usize, f64); 2],
+    // What both tops can take and our balances allow, in the base asset.
+    pub size: f64,
 }
 
 pub struct MultiBook<const S: usize, const T: usize> {
@@ -21,6 +39,9 @@ pub struct MultiBook<const S: usize, const T: usize> {
     o10: usize,
     o05: usize,
     max: f64,
+    threshold_scaling: Option<ThresholdScaling>,
+    paper: Option<Arc<Mutex<PaperExchange>>>,
+    portfolio: Option<Arc<Mutex<Portfolio>>>,
 }
 
 impl<const S: usize, const T: usize> MultiBook<S, T> {
@@ -28,18 +49,18 @@ impl<const S: usize, const T: usize> MultiBook<S, T> {
         let mut books = heapless::Vec::<OrderBook, S>::new();
         let mut spreads = heapless::Vec::<Spread, T>::new();
         let mut last_spreads = heapless::Vec::<Spread, T>::new();
-        for i in 0..S {
-            let _ = books.push(OrderBook::new(names[i].to_owned(), pair.to_owned()));
+        for name in names.iter() {
+            let _ = books.push(OrderBook::new(name.to_owned(), pair.to_owned()));
         }
         for _ in 0..T {
             let _ = spreads.push(Spread::default());
             let _ = last_spreads.push(Spread::default());
         }
-        return MultiBook {
-            pair: pair,
+        MultiBook {
+            pair,
             books: Box::new(books),
-            spreads: spreads,
-            last_spreads: last_spreads,
+            spreads,
+            last_spreads,
             arb_count: 0,
             o25: 0,
             o20: 0,
@@ -47,9 +68,90 @@ impl<const S: usize, const T: usize> MultiBook<S, T> {
             o10: 0,
             o05: 0,
             max: 0.0,
+            threshold_scaling: None,
+            paper: None,
+            portfolio: None,
+        }
+    }
+
+    pub fn book_idx(&self, name: &str) -> Option<usize> {
+        self.books.iter().position(|book| book.name.as_str() == name)
+    }
+
+    pub fn set_horizons(&mut self, horizons: &[Duration]) {
+        for book in self.books.iter_mut() {
+            book.evaluator = Evaluator::new(horizons);
+        }
+    }
+
+    pub fn set_recorder(&mut self, recorder: Arc<Mutex<Recorder>>) {
+        for book in self.books.iter_mut() {
+            book.recorder = Some(recorder.clone());
+        }
+    }
+
+    // Opportunities are taken on the paper exchange, which fills from these books.
+    pub fn set_paper(&mut self, paper: Arc<Mutex<PaperExchange>>) {
+        for book in self.books.iter_mut() {
+            book.paper = Some(paper.clone());
+        }
+        self.paper = Some(paper);
+    }
+
+    // Limits opportunity sizes to what can be bought and sold with the inventory on each venue.
+    pub fn set_portfolio(&mut self, portfolio: Arc<Mutex<Portfolio>>) {
+        self.portfolio = Some(portfolio);
+    }
+
+    // While a venue is disconnected its book is kept but takes no part in spreads until it is
+    // re-initialised.
+    pub fn set_stale(&mut self, book_idx: usize, stale: bool) {
+        self.books[book_idx].stale = stale;
+        if stale {
+            for i in 0..T {
+                let (buy, sell) = MultiBook::<S, T>::spread_books(i);
+                if buy == book_idx || sell == book_idx {
+                    self.spreads[i] = Spread::default();
+                }
+            }
+        }
+    }
+
+    pub fn set_volatility_config(&mut self, config: VolatilityConfig) {
+        for book in self.books.iter_mut() {
+            book.volatility = VolatilityEstimator::new(config);
+        }
+    }
+
+    pub fn set_threshold_scaling(&mut self, scaling: Option<ThresholdScaling>) {
+        self.threshold_scaling = scaling;
+    }
+
+    // The buy-side book of spread `idx` is `idx / (S - 1)`; the sell side skips over it.
+    fn spread_books(idx: usize) -> (usize, usize) {
+        let buy = idx / (S - 1);
+        let sell = idx % (S - 1);
+        if sell < buy {
+            (buy, sell)
+        } else {
+            (buy, sell + 1)
         }
     }
 
+    // Above the reference volatility, quotes go stale faster than they can be hit, so the threshold
+    // grows with the more volatile of the two books.
+    pub fn arb_threshold(&self, spread_idx: usize) -> f64 {
+        let scaling = match self.threshold_scaling {
+            Some(s) => s,
+            None => return ARB_THRESHOLD,
+        };
+        let (buy, sell) = MultiBook::<S, T>::spread_books(spread_idx);
+        let volatility = self.books[buy].volatility.volatility().unwrap_or(0.0)
+            .max(self.books[sell].volatility.volatility().unwrap_or(0.0));
+        let scale = (volatility / scaling.reference_volatility).clamp(1.0, scaling.max_scale);
+        ARB_THRESHOLD * scale
+    }
+
     pub fn update_spread(&mut self, book_idx: usize) {
         for i in 0..S {
             if i != book_idx {
@@ -57,11 +159,8 @@ impl<const S: usize, const T: usize> MultiBook<S, T> {
                 let forward_sell = self.get_best(Side::Buy, &self.books[i]);
                 let reverse_buy = self.get_best(Side::Sell, &self.books[i]);
                 let reverse_sell = self.get_best(Side::Buy, &self.books[book_idx]);
-                if forward_buy.is_some() && forward_sell.is_some() {
-                    let spread = self.spread_from_levels(
-                        forward_buy.unwrap().0 as isize, 
-                        forward_sell.unwrap().0 as isize,
-                        [forward_buy.unwrap().1, forward_sell.unwrap().1]);
+                if let (Some(buy), Some(sell)) = (forward_buy, forward_sell) {
+                    let spread = self.spread_from_levels([buy, sell], self.executable(book_idx, i, buy.0, buy.1.min(sell.1)));
 
                     let mut spread_idx = (book_idx * S) + i;
                     if i < book_idx {
@@ -71,11 +170,8 @@ impl<const S: usize, const T: usize> MultiBook<S, T> {
                     }
                     self.spreads[spread_idx] = spread;
                 }
-                if reverse_buy.is_some() && reverse_sell.is_some() {
-                    let spread = self.spread_from_levels(
-                        reverse_buy.unwrap().0 as isize, 
-                        reverse_sell.unwrap().0 as isize,
-                        [reverse_buy.unwrap().1, reverse_sell.unwrap().1]);
+                if let (Some(buy), Some(sell)) = (reverse_buy, reverse_sell) {
+                    let spread = self.spread_from_levels([buy, sell], self.executable(i, book_idx, buy.0, buy.1.min(sell.1)));
                     
                     let mut spread_idx = (i * S) + book_idx;
                     if book_idx < i {
@@ -107,58 +203,193 @@ impl<const S: usize, const T: usize> MultiBook<S, T> {
             if spread.percentage >= self.max {
                 self.max = spread.percentage;
             }
-            if spread.percentage >= 0.002 && (self.last_spreads[i].seqs[0] == 0 || (spread.seqs[0] != self.last_spreads[i].seqs[0] || spread.seqs[1] != self.last_spreads[i].seqs[1])) {
-                self.last_spreads[i] = spread.clone();
+            // An opportunity is only taken again once either top has moved or changed size.
+            if spread.percentage >= self.arb_threshold(i) && spread.tops != self.last_spreads[i].tops {
+                self.last_spreads[i] = *spread;
                 self.arb_count += 1;
+                self.execute_paper(i);
                 self.print();
                 return;
             }
         }
     }
-    fn spread_from_levels(&self, ask: isize, bid: isize, seqs: [i64; 2]) -> Spread {
-        return Spread {raw: bid - ask, percentage: (bid - ask) as f64 / ask as f64, seqs: seqs}
+    fn spread_from_levels(&self, tops: [(usize, f64); 2], size: f64) -> Spread {
+        let (ask, bid) = (tops[0].0 as isize, tops[1].0 as isize);
+        Spread {raw: bid - ask, percentage: (bid - ask) as f64 / ask as f64, tops, size}
+    }
+    fn executable(&self, buy: usize, sell: usize, ask: usize, size: f64) -> f64 {
+        match &self.portfolio {
+            Some(portfolio) => {
+                let limit = portfolio.lock().unwrap().max_executable(&self.pair, &self.books[buy].name, ask, &self.books[sell].name);
+                size.min(limit)
+            },
+            None => size,
+        }
+    }
+    // Buys the opportunity's size at one venue's ask and sells it at the other's bid, both IOC.
+    fn execute_paper(&mut self, spread_idx: usize) {
+        let paper = match &self.paper {
+            Some(paper) => paper.clone(),
+            None => return,
+        };
+        let (buy, sell) = MultiBook::<S, T>::spread_books(spread_idx);
+        let size = self.spreads[spread_idx].size;
+        let legs = match (self.books[buy].best_ask, self.books[sell].best_bid) {
+            (Some(ask), Some(bid)) if size > 0.0 => [(buy, Side::Buy, ask), (sell, Side::Sell, bid)],
+            _ => return,
+        };
+        let mut paper = paper.lock().unwrap();
+        for (book_idx, side, price) in legs {
+            let order = OrderRequest {
+                client_id: format!("arb-{}", self.arb_count),
+                pair: self.pair.clone(),
+                side,
+                price,
+                size,
+                time_in_force: TimeInForce::Ioc,
+            };
+            paper.place(&self.books[book_idx], order);
+        }
     }
     pub fn print(&self) {
         println!("{:?}", self.pair);
         for book in self.books.iter() {
-            self.print_book(&book);
+            self.print_book(book);
         }
-        for spread in &*self.spreads {
-            println!("{:?}", spread);
+        for (i, spread) in self.spreads.iter().enumerate() {
+            let (buy, sell) = MultiBook::<S, T>::spread_books(i);
+            println!("{:?} -> {:?}: {:?}, threshold {:.5}%", self.books[buy].name, self.books[sell].name, spread, self.arb_threshold(i) * 100.0);
         }
         let date = Local::now();
         println!("Arbitrage opportunity count: {:?}", self.arb_count);
         println!(">0.2%: {:?}\n>0.15%: {:?}\n>0.1%: {:?}\n>0.05%: {:?}", self.o20, self.o15, self.o10, self.o05);
         println!("Best seen: {:.5}%", self.max * 100.0);
+        if let Some(paper) = &self.paper {
+            paper.lock().unwrap().print(&self.pair);
+        }
+        if let Some(portfolio) = &self.portfolio {
+            portfolio.lock().unwrap().print();
+        }
         println!("{}", date.format("%Y-%m-%d %H:%M:%S"));
     }
     fn print_book(&self, book: &OrderBook) {
 
-        if book.best_bid.is_some() && book.best_ask.is_some() {
-            let bid = book.bid_lookup.get(&book.best_bid.unwrap());
-            let ask = book.ask_lookup.get(&book.best_ask.unwrap());
+        if let (Some(best_bid), Some(best_ask)) = (book.best_bid, book.best_ask) {
+            let bid = book.bid_lookup.get(&best_bid);
+            let ask = book.ask_lookup.get(&best_ask);
             let bid_hs = book.bids.len();
             let ask_hs = book.asks.len();
             println!("{:?} best bid: {:?}\n{:?} best ask: {:?}", book.name, bid, book.name, ask);
+            if book.stale {
+                println!("{:?} is stale", book.name);
+            }
             println!("Book pressure: {:?}", book.pressure);
             println!("Theoretical price: {:?}", book.theoretical_price);
             println!("Bid heap: {:?} elements\nAsk heap: {:?} elements", bid_hs, ask_hs);
+            let volatility = &book.volatility;
+            println!(
+                "Realised volatility: {:?} bps/{:?}, average spread: {:?} bps, regime: {:?}",
+                volatility.volatility(), volatility.config.sample_interval, volatility.average_spread(), volatility.regime()
+            );
+            book.evaluator.print(&book.name);
         }
     }
-    fn get_best(&self, side: Side, book: &OrderBook) -> Option<(usize, i64)> {
+    fn get_best(&self, side: Side, book: &OrderBook) -> Option<(usize, f64)> {
+        if book.stale {
+            return None;
+        }
         match side {
             Side::Buy => {
-                match book.best_bid {
-                    Some(b) => Some((b, book.bid_lookup.get(&b).unwrap().sequence)),
-                    None => None,
-                }
+                book.best_bid.map(|b| (b, book.bid_lookup.get(&b).unwrap().amount))
             },
             Side::Sell => {
-                match book.best_ask {
-                    Some(a) => Some((a, book.ask_lookup.get(&a).unwrap().sequence)),
-                    None => None,
-                }
+                book.best_ask.map(|a| (a, book.ask_lookup.get(&a).unwrap().amount))
             },
         }
     }
-}
\ No newline at end of file
+}
+#[test]
+fn test_spread_books() {
+    let pairs: std::vec::Vec<(usize, usize)> = (0..6).map(MultiBook::<3, 6>::spread_books).collect();
+    assert_eq!(vec![(0, 1), (0, 2), (1, 0), (1, 2), (2, 0), (2, 1)], pairs);
+}
+
+#[test]
+fn test_opportunity_taken_on_paper() {
+    use super::data_types::{Change, PriceLevel, Update};
+    use super::paper::PaperConfig;
+    let mut multi_book = MultiBook::<2, 2>::new(heapless::String::from("ETH-USD"), [heapless::String::from("coinbase"), heapless::String::from("kraken")]);
+    let paper = Arc::new(Mutex::new(PaperExchange::new(PaperConfig { taker_fee_bps: 5.0, ..PaperConfig::default() })));
+    multi_book.set_paper(paper.clone());
+    for (book_idx, bid, ask, amount) in [(0, 189900, 190000, 2.0), (1, 191000, 191100, 0.5)] {
+        let book = &mut multi_book.books[book_idx];
+        book.replay_time = Some(1);
+        let mut update = Update { product_id: "", time: "", changes: heapless::Vec::new() };
+        let _ = update.changes.push(Change { side: Side::Buy, price_level: PriceLevel { level: bid, amount, sequence: 0 } });
+        let _ = update.changes.push(Change { side: Side::Sell, price_level: PriceLevel { level: ask, amount, sequence: 0 } });
+        book.update(update);
+        multi_book.update_spread(book_idx);
+    }
+    // Coinbase's ask against Kraken's bid, limited by Kraken's half.
+    assert_eq!(0.5, multi_book.spreads[0].size);
+    let paper = paper.lock().unwrap();
+    let (bought, sold) = (paper.position("coinbase", "ETH-USD").unwrap(), paper.position("kraken", "ETH-USD").unwrap());
+    assert_eq!((0.5, -0.5), (bought.base, sold.base));
+    assert!((bought.quote + sold.quote - (5.0 - 0.475 - 0.4775)).abs() < 1e-9);
+}
+
+#[test]
+fn test_opportunity_sized_by_portfolio() {
+    use super::data_types::{Change, PriceLevel, Update};
+    use super::paper::PaperConfig;
+    let mut multi_book = MultiBook::<2, 2>::new(heapless::String::from("ETH-USD"), [heapless::String::from("coinbase"), heapless::String::from("kraken")]);
+    let paper = Arc::new(Mutex::new(PaperExchange::new(PaperConfig::default())));
+    let mut portfolio = Portfolio::new(0.0);
+    portfolio.set_balance("coinbase", "USD", 10000.0);
+    portfolio.set_balance("kraken", "ETH", 0.2);
+    let portfolio = Arc::new(Mutex::new(portfolio));
+    paper.lock().unwrap().set_portfolio(portfolio.clone());
+    multi_book.set_paper(paper.clone());
+    multi_book.set_portfolio(portfolio.clone());
+    for (book_idx, bid, ask) in [(0, 189900, 190000), (1, 191000, 191100)] {
+        let book = &mut multi_book.books[book_idx];
+        let mut update = Update { product_id: "", time: "", changes: heapless::Vec::new() };
+        let _ = update.changes.push(Change { side: Side::Buy, price_level: PriceLevel { level: bid, amount: 3.0, sequence: 0 } });
+        let _ = update.changes.push(Change { side: Side::Sell, price_level: PriceLevel { level: ask, amount: 3.0, sequence: 0 } });
+        book.update(update);
+        multi_book.update_spread(book_idx);
+    }
+    // Only Kraken's 0.2 ETH can be sold, and buying it on Coinbase leaves USD to spare.
+    assert!((multi_book.spreads[0].size - 0.2).abs() < 1e-9);
+    assert_eq!(0.0, multi_book.spreads[1].size);
+    let portfolio = portfolio.lock().unwrap();
+    assert!((portfolio.balance("coinbase", "ETH") - 0.2).abs() < 1e-9);
+    assert!(portfolio.balance("kraken", "ETH").abs() < 1e-9);
+    assert!((portfolio.balance("kraken", "USD") - 382.0).abs() < 1e-9);
+
+    // Having sold it, there is nothing left to size another opportunity with.
+    drop(portfolio);
+    multi_book.update_spread(1);
+    assert!(multi_book.spreads[0].size.abs() < 1e-9);
+}
+
+#[test]
+fn test_unchanged_opportunity_taken_once() {
+    use super::data_types::{Change, PriceLevel, Update};
+    use super::paper::PaperConfig;
+    let mut multi_book = MultiBook::<2, 2>::new(heapless::String::from("ETH-USD"), [heapless::String::from("coinbase"), heapless::String::from("kraken")]);
+    let paper = Arc::new(Mutex::new(PaperExchange::new(PaperConfig::default())));
+    multi_book.set_paper(paper.clone());
+    for (book_idx, bid, ask) in [(0, 189900, 190000), (1, 191000, 191100), (1, 188000, 191100)] {
+        let book = &mut multi_book.books[book_idx];
+        let mut update = Update { product_id: "", time: "", changes: heapless::Vec::new() };
+        let _ = update.changes.push(Change { side: Side::Buy, price_level: PriceLevel { level: bid, amount: 0.5, sequence: 0 } });
+        let _ = update.changes.push(Change { side: Side::Sell, price_level: PriceLevel { level: ask, amount: 0.5, sequence: 0 } });
+        book.update(update);
+        multi_book.update_spread(book_idx);
+    }
+    // The last update only adds a level below Kraken's best bid, so the same tops are not taken twice.
+    assert_eq!(1, multi_book.arb_count);
+    let paper = paper.lock().unwrap();
+    assert_eq!((1, 1), (paper.position("coinbase", "ETH-USD").unwrap().fills, paper.position("kraken", "ETH-USD").unwrap().fills));
+}
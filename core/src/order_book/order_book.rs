@@ -1,8 +1,13 @@
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use heapless::{binary_heap::{Max, Min}, Vec};
 use tokio::time::Instant;
 
 use super::data_types::{Update, Side, PriceLevel, Snapshot, Match};
+use super::evaluator::Evaluator;
+use super::paper::PaperExchange;
+use super::replay::{Event, Recorder};
+use super::volatility::VolatilityEstimator;
 
 #[derive(Default)]
 pub struct OrderBook {
@@ -25,17 +30,19 @@ pub struct OrderBook {
     average_update: f64,
     num_updates: usize,
     count: i64,
+    pub evaluator: Evaluator,
+    pub volatility: VolatilityEstimator,
+    pub replay_time: Option<i64>,
+    pub stale: bool,
+    pub recorder: Option<Arc<Mutex<Recorder>>>,
+    pub paper: Option<Arc<Mutex<PaperExchange>>>,
 }
 
 impl Eq for PriceLevel {}
 
 impl PartialOrd for PriceLevel {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        if self.level != other.level {
-            self.level.partial_cmp(&other.level)
-        } else {
-            self.sequence.partial_cmp(&other.sequence)
-        }
+        Some(self.cmp(other))
     }
 }
 
@@ -51,9 +58,9 @@ impl Ord for PriceLevel {
 
 impl OrderBook {
     pub fn new(name: heapless::String<8>, pair: heapless::String<8>) -> Self {
-        return OrderBook {
-            name: name,
-            pair: pair,
+        OrderBook {
+            name,
+            pair,
             bids: Box::new(heapless::BinaryHeap::new()),
             asks: Box::new(heapless::BinaryHeap::new()),
             bid_lookup: Box::new(heapless::FnvIndexMap::new()),
@@ -71,13 +78,48 @@ impl OrderBook {
             average_update: 0.0,
             num_updates: 0,
             count: 0,
+            evaluator: Evaluator::default(),
+            volatility: VolatilityEstimator::default(),
+            replay_time: None,
+            stale: false,
+            recorder: None,
+            paper: None,
+        }
+    }
+    pub fn now(&self) -> i64 {
+        match self.replay_time {
+            Some(t) => t,
+            None => SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64,
+        }
+    }
+    pub fn mid(&self) -> Option<f64> {
+        match (self.best_bid, self.best_ask) {
+            (Some(bid), Some(ask)) => Some((bid as f64 + ask as f64) / 2.0),
+            _ => None,
+        }
+    }
+    pub fn spread_bps(&self) -> Option<f64> {
+        match (self.best_bid, self.best_ask, self.mid()) {
+            (Some(bid), Some(ask), Some(mid)) => Some((ask as f64 - bid as f64) / mid * 10000.0),
+            _ => None,
+        }
+    }
+    fn record(&self, event: Event) {
+        if let Some(recorder) = &self.recorder {
+            recorder.lock().unwrap().record(&event);
         }
     }
     pub fn init(&mut self, snapshot: Snapshot) {
+        if self.recorder.is_some() {
+            self.record(Event::snapshot(self.now(), &self.name, &self.pair, &snapshot));
+        }
         OrderBook::init_side(&mut self.bid_lookup, &mut self.bids, &mut self.best_bid, &snapshot.bids);
         OrderBook::init_side(&mut self.ask_lookup, &mut self.asks, &mut self.best_ask, &snapshot.asks);
         OrderBook::update_best::<Max>(&self.bids, &mut self.best_bid);
         OrderBook::update_best::<Min>(&self.asks, &mut self.best_ask);
+        if let Some(paper) = &self.paper {
+            paper.lock().unwrap().on_book(self);
+        }
     }
     fn init_side<K>(
         lookup: &mut Box<heapless::FnvIndexMap<usize, PriceLevel, 65536>>,
@@ -85,18 +127,20 @@ impl OrderBook {
         best: &mut Option<usize>,
         snapshot: &Vec<PriceLevel, 65536>)
     where K: heapless::binary_heap::Kind {
+            lookup.clear();
+            heap.clear();
+            *best = None;
             for price_level in snapshot {
-                lookup.clear();
-                heap.clear();
-                *best = None;
                 let level: usize = price_level.level;
-                let _ = lookup.insert(level, price_level.clone()).unwrap();
-                let _ = heap.push(level).unwrap();
+                let _ = lookup.insert(level, *price_level).unwrap();
+                heap.push(level).unwrap();
             }
         }
     pub fn update(&mut self, update: Update) {
-        let start = Instant::now();
-        self.count = self.count + 1;
+        if self.recorder.is_some() {
+            self.record(Event::update(self.now(), &self.name, &self.pair, &update));
+        }
+        self.count += 1;
         for change in update.changes {
             let (level, amount) = (change.price_level.level, change.price_level.amount);
             match change.side {
@@ -106,7 +150,7 @@ impl OrderBook {
                     OrderBook::update_best::<Max>(&self.bids, &mut self.best_bid);
                 },
                 Side::Sell => {
-                    OrderBook::update_lookup(&mut self.ask_lookup, level, amount, -1 * self.count);
+                    OrderBook::update_lookup(&mut self.ask_lookup, level, amount, -self.count);
                     OrderBook::update_heap::<Min>(&self.ask_lookup, &mut self.asks, level, amount);
                     OrderBook::update_best::<Min>(&self.asks, &mut self.best_ask);
                 },
@@ -116,22 +160,28 @@ impl OrderBook {
         if self.best_bid.is_some() && self.best_ask.is_some() {
             self.update_pressure();
             self.validate();
+            let (now, mid) = (self.now(), self.mid().unwrap());
+            self.evaluator.observe(now, mid);
+            self.volatility.observe(now, mid, self.spread_bps().unwrap());
         }
         self.theoretical_price = 0;
+        if let Some(paper) = &self.paper {
+            paper.lock().unwrap().on_book(self);
+        }
     }
     fn update_lookup(
         lookup: &mut Box<heapless::FnvIndexMap<usize, PriceLevel, 65536>>,
         level: usize,
         amount: f64,
         seq: i64) {
-        if amount.to_bits() == (0.0 as f64).to_bits() && lookup.contains_key(&level){
+        if amount.to_bits() == 0.0_f64.to_bits() && lookup.contains_key(&level){
             lookup.remove(&level).unwrap();                                                                                                                                                     
-        } else if amount.to_bits() != (0.0 as f64).to_bits() {
-            let _ = lookup.insert(level, PriceLevel{ level: level, amount: amount, sequence: seq }).unwrap();
+        } else if amount.to_bits() != 0.0_f64.to_bits() {
+            let _ = lookup.insert(level, PriceLevel{ level, amount, sequence: seq }).unwrap();
         }
     }
     fn update_heap<K>(
-        lookup: &Box<heapless::FnvIndexMap<usize, PriceLevel, 65536>>,
+        lookup: &heapless::FnvIndexMap<usize, PriceLevel, 65536>,
         heap: &mut Box<heapless::BinaryHeap<usize, K, 65536>>,
         level: usize,
         amount: f64)
@@ -139,15 +189,15 @@ impl OrderBook {
         if heap.len() >= 65536 {
             OrderBook::heap_from_lookup(lookup, heap);
         }
-        while heap.len() > 0 && !lookup.contains_key(&heap.peek().unwrap()) {
+        while !heap.is_empty() && !lookup.contains_key(heap.peek().unwrap()) {
             let _ = heap.pop().unwrap();
         }
-        if !(amount.to_bits() == (0.0 as f64).to_bits()) {
-            let _ = heap.push(level).unwrap();
+        if amount.to_bits() != 0.0_f64.to_bits()  {
+            heap.push(level).unwrap();
         }
     }
     fn update_best<K>(
-        heap: &Box<heapless::BinaryHeap<usize, K, 65536>>,
+        heap: &heapless::BinaryHeap<usize, K, 65536>,
         best: &mut Option<usize>)
     where K: heapless::binary_heap::Kind {
         match heap.peek() {
@@ -158,7 +208,7 @@ impl OrderBook {
         }
     }
     fn heap_from_lookup<K>(
-        lookup: &Box<heapless::FnvIndexMap<usize, PriceLevel, 65536>>,
+        lookup: &heapless::FnvIndexMap<usize, PriceLevel, 65536>,
         heap: &mut Box<heapless::BinaryHeap<usize, K, 65536>>)
     where K: heapless::binary_heap::Kind {
         heap.clear();
@@ -180,6 +230,9 @@ impl OrderBook {
         self.pressure = ((bid_amount * ask_level as f64) + (ask_amount * bid_level as f64)) / (bid_amount + ask_amount);
     }
     pub fn update_impulse(&mut self, match_: Match) {
+        if self.recorder.is_some() {
+            self.record(Event::match_(self.now(), &self.name, &self.pair, &match_));
+        }
         match match_.side {
             Side::Buy => {
                 let delta = self.best_ask.unwrap() as f64 - self.best_bid.unwrap() as f64;
@@ -190,6 +243,11 @@ impl OrderBook {
                 self.theoretical_price = (self.pressure + ((delta * match_.size) / (self.avg_bid + self.avg_ask))) as usize;
             },
         }
+        let (now, mid) = (self.now(), self.mid().unwrap());
+        self.evaluator.record(now, self.theoretical_price, mid);
+        if let Some(paper) = &self.paper {
+            paper.lock().unwrap().on_trade(self, &match_);
+        }
         /*if self.theoretical_price > self.best_ask.unwrap() || self.theoretical_price < self.best_bid.unwrap() {
             println!("Best bid: {:?}\nBest ask: {:?}", self.bid_lookup.get(self.best_bid.as_ref().unwrap()), self.ask_lookup.get(self.best_ask.as_ref().unwrap()));
             println!("Book pressure: {:?}", self.pressure);
@@ -212,8 +270,8 @@ impl OrderBook {
         let best_ask = &self.best_ask.unwrap();
         let heap_bid = self.bids.peek().unwrap();
         let heap_ask = self.asks.peek().unwrap();
-        let bid = self.bid_lookup.get(&best_bid).unwrap();
-        let ask = self.ask_lookup.get(&best_ask).unwrap();
+        let bid = self.bid_lookup.get(best_bid).unwrap();
+        let ask = self.ask_lookup.get(best_ask).unwrap();
 
         if best_bid >= best_ask || best_bid != heap_bid || best_ask != heap_ask || heap_bid != &bid.level || heap_ask != &ask.level {
             self.print(&Instant::now());
@@ -222,3 +280,18 @@ impl OrderBook {
     }
 }
 
+
+#[test]
+fn test_init_keeps_every_level() {
+    let mut book = OrderBook::new(heapless::String::from("bitstamp"), heapless::String::from("BTC-USD"));
+    let mut snapshot = Snapshot::new();
+    for level in [2999900, 3000000] {
+        let _ = snapshot.bids.push(PriceLevel { level, amount: 1.0, sequence: 0 });
+        let _ = snapshot.asks.push(PriceLevel { level: level + 200, amount: 1.0, sequence: 0 });
+    }
+    book.init(snapshot);
+    assert_eq!(2, book.bid_lookup.len());
+    assert_eq!(2, book.asks.len());
+    assert_eq!(Some(3000000), book.best_bid);
+    assert_eq!(Some(3000100), book.best_ask);
+}
@@ -0,0 +1,662 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::clients::orders::{OrderClient, OrderError, OrderEvent, OrderRequest, TimeInForce};
+use super::data_types::{to_price, Match, Side, PRICE_SCALE};
+use super::instruments::ConfigError;
+use super::multi_book::MultiBook;
+use super::order_book::OrderBook;
+use super::portfolio::Portfolio;
+
+// Remaining sizes below this count as filled.
+const DUST: f64 = 1e-9;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaperConfig {
+    // From placing or canceling an order to the venue acting on it, on the book's clock.
+    pub latency: Duration,
+    pub taker_fee_bps: f64,
+    pub maker_fee_bps: f64,
+    // How much of the size already at a resting order's price is ahead of it: 1.0 joins the back
+    // of the queue, 0.0 the front.
+    pub queue_ahead: f64,
+}
+
+impl Default for PaperConfig {
+    fn default() -> Self {
+        PaperConfig {
+            latency: Duration::ZERO,
+            taker_fee_bps: 0.0,
+            maker_fee_bps: 0.0,
+            queue_ahead: 1.0,
+        }
+    }
+}
+
+// One entry of the paper config file. Whatever a venue leaves out comes from the default entry,
+// and whatever that leaves out from `PaperConfig::default()`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PaperSettings {
+    latency_ms: Option<u64>,
+    taker_fee_bps: Option<f64>,
+    maker_fee_bps: Option<f64>,
+    queue_ahead: Option<f64>,
+}
+
+impl PaperSettings {
+    fn over(&self, base: PaperConfig) -> Result<PaperConfig, ConfigError> {
+        let config = PaperConfig {
+            latency: self.latency_ms.map_or(base.latency, Duration::from_millis),
+            taker_fee_bps: self.taker_fee_bps.unwrap_or(base.taker_fee_bps),
+            maker_fee_bps: self.maker_fee_bps.unwrap_or(base.maker_fee_bps),
+            queue_ahead: self.queue_ahead.unwrap_or(base.queue_ahead),
+        };
+        if !(0.0..=1.0).contains(&config.queue_ahead) {
+            return Err(ConfigError::Invalid(format!("queue_ahead {:?} is not between 0 and 1", config.queue_ahead)));
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PaperFile {
+    #[serde(default)]
+    default: PaperSettings,
+    #[serde(default)]
+    venues: HashMap<String, PaperSettings>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Position {
+    pub base: f64,
+    // Net of fees.
+    pub quote: f64,
+    pub fees: f64,
+    // Traded notional in the quote asset.
+    pub volume: f64,
+    pub fills: usize,
+    // The venue's last mid, in hundredths.
+    pub mark: Option<f64>,
+}
+
+impl Position {
+    // Marked to the venue's mid.
+    pub fn pnl(&self) -> Option<f64> {
+        self.mark.map(|mark| self.quote + self.base * mark / PRICE_SCALE)
+    }
+}
+
+enum Action {
+    Place(OrderRequest),
+    Cancel,
+    // The new order's id and the order.
+    Replace(String, OrderRequest),
+}
+
+struct Pending {
+    arrival: i64,
+    venue: String,
+    pair: String,
+    order_id: String,
+    action: Action,
+}
+
+struct Resting {
+    venue: String,
+    order_id: String,
+    order: OrderRequest,
+    remaining: f64,
+    // Size that has to trade at our price before we fill.
+    ahead: f64,
+}
+
+// Depth our own fills took from a level, which stays gone until the venue next changes the level.
+struct Taken {
+    venue: String,
+    pair: String,
+    // The side of the book, so asks for our buys.
+    side: Side,
+    level: usize,
+    displayed: f64,
+    size: f64,
+}
+
+// A simulated venue for every book it is attached to. Arriving orders take the displayed depth
+// and any remainder rests, filling only from trades at or through its price once the queue ahead
+// of it has traded. Our own fills do not change the venue's book, so what they took is remembered
+// and left out of the depth until the venue updates those levels.
+//
+// Everything runs on the books' clocks and is driven by their events, so a replay fills exactly
+// as the live run it was recorded from would have.
+pub struct PaperExchange {
+    config: PaperConfig,
+    venue_configs: HashMap<String, PaperConfig>,
+    pending: Vec<Pending>,
+    resting: Vec<Resting>,
+    taken: Vec<Taken>,
+    positions: BTreeMap<(String, String), Position>,
+    events: HashMap<String, VecDeque<OrderEvent>>,
+    next_id: u64,
+    portfolio: Option<Arc<Mutex<Portfolio>>>,
+}
+
+impl PaperExchange {
+    pub fn new(config: PaperConfig) -> PaperExchange {
+        PaperExchange {
+            config,
+            venue_configs: HashMap::new(),
+            pending: Vec::new(),
+            resting: Vec::new(),
+            taken: Vec::new(),
+            positions: BTreeMap::new(),
+            events: HashMap::new(),
+            next_id: 0,
+            portfolio: None,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        PaperExchange::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let file: PaperFile = serde_json::from_str(contents).map_err(ConfigError::Parse)?;
+        let default = file.default.over(PaperConfig::default())?;
+        let mut exchange = PaperExchange::new(default);
+        for (venue, settings) in file.venues.iter() {
+            let config = settings.over(default).map_err(|err| ConfigError::Invalid(format!("{}: {}", venue, err)))?;
+            exchange.set_venue_config(venue, config);
+        }
+        Ok(exchange)
+    }
+
+    // Fills move the portfolio's balances as they would a real account's.
+    pub fn set_portfolio(&mut self, portfolio: Arc<Mutex<Portfolio>>) {
+        self.portfolio = Some(portfolio);
+    }
+
+    pub fn set_venue_config(&mut self, venue: &str, config: PaperConfig) {
+        self.venue_configs.insert(venue.to_string(), config);
+    }
+
+    fn config(&self, venue: &str) -> PaperConfig {
+        *self.venue_configs.get(venue).unwrap_or(&self.config)
+    }
+
+    // Events are only kept for venues that something reads them from.
+    pub fn subscribe(&mut self, venue: &str) {
+        self.events.entry(venue.to_string()).or_default();
+    }
+
+    pub fn take_events(&mut self, venue: &str) -> Vec<OrderEvent> {
+        match self.events.get_mut(venue) {
+            Some(events) => events.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Keyed by venue and pair.
+    pub fn positions(&self) -> &BTreeMap<(String, String), Position> {
+        &self.positions
+    }
+
+    pub fn position(&self, venue: &str, pair: &str) -> Option<&Position> {
+        self.positions.get(&(venue.to_string(), pair.to_string()))
+    }
+
+    // The order reaches `book`'s venue after the configured latency; the id is ours to use now.
+    pub fn place(&mut self, book: &OrderBook, order: OrderRequest) -> OrderEvent {
+        let order_id = self.next_order_id();
+        let accepted = OrderEvent::Accepted { client_id: order.client_id.clone(), order_id: order_id.clone() };
+        self.submit(book, order_id, Action::Place(order));
+        accepted
+    }
+
+    pub fn cancel(&mut self, book: &OrderBook, order_id: &str) {
+        self.submit(book, order_id.to_string(), Action::Cancel);
+    }
+
+    // The new order is only placed if the old one is still open when the cancel arrives.
+    pub fn replace(&mut self, book: &OrderBook, order_id: &str, order: OrderRequest) -> OrderEvent {
+        let new_id = self.next_order_id();
+        let accepted = OrderEvent::Accepted { client_id: order.client_id.clone(), order_id: new_id.clone() };
+        self.submit(book, order_id.to_string(), Action::Replace(new_id, order));
+        accepted
+    }
+
+    // The pair of an order that is resting or on its way to the venue.
+    pub fn order_pair(&self, order_id: &str) -> Option<String> {
+        let resting = self.resting.iter()
+            .find(|r| r.order_id == order_id)
+            .map(|r| r.order.pair.to_string());
+        resting.or_else(|| self.pending.iter().find_map(|p| match &p.action {
+            Action::Place(order) if p.order_id == order_id => Some(order.pair.to_string()),
+            Action::Replace(new_id, order) if new_id == order_id => Some(order.pair.to_string()),
+            _ => None,
+        }))
+    }
+
+    fn next_order_id(&mut self) -> String {
+        self.next_id += 1;
+        format!("paper-{}", self.next_id)
+    }
+
+    fn submit(&mut self, book: &OrderBook, order_id: String, action: Action) {
+        let arrival = book.now() + self.config(&book.name).latency.as_nanos() as i64;
+        self.pending.push(Pending {
+            arrival,
+            venue: book.name.to_string(),
+            pair: book.pair.to_string(),
+            order_id,
+            action,
+        });
+        self.on_book(book);
+    }
+
+    pub fn on_book(&mut self, book: &OrderBook) {
+        if let Some(mid) = book.mid() {
+            self.positions.entry((book.name.to_string(), book.pair.to_string())).or_default().mark = Some(mid);
+        }
+        self.taken.retain(|t| t.venue != book.name.as_str() || t.pair != book.pair.as_str() || PaperExchange::displayed(book, t.side, t.level) == t.displayed);
+        self.arrive(book);
+        // Size leaving our level was either ahead of us or behind us; assume ahead.
+        for resting in self.resting.iter_mut().filter(|r| r.venue == book.name.as_str() && r.order.pair == book.pair) {
+            let displayed = match resting.order.side {
+                Side::Buy => book.bid_lookup.get(&resting.order.price),
+                Side::Sell => book.ask_lookup.get(&resting.order.price),
+            };
+            resting.ahead = resting.ahead.min(displayed.map_or(0.0, |level| level.amount));
+        }
+    }
+
+    pub fn on_trade(&mut self, book: &OrderBook, trade: &Match) {
+        self.arrive(book);
+        let mut fills = Vec::new();
+        for resting in self.resting.iter_mut().filter(|r| r.venue == book.name.as_str() && r.order.pair == book.pair) {
+            let (at, through) = match resting.order.side {
+                Side::Buy => (trade.price == resting.order.price, trade.price < resting.order.price),
+                Side::Sell => (trade.price == resting.order.price, trade.price > resting.order.price),
+            };
+            let available = if through {
+                trade.size
+            } else if at {
+                let available = trade.size - resting.ahead;
+                resting.ahead = (resting.ahead - trade.size).max(0.0);
+                available
+            } else {
+                0.0
+            };
+            if available > DUST {
+                let size = available.min(resting.remaining);
+                resting.remaining -= size;
+                fills.push((resting.order_id.clone(), resting.order.clone(), size));
+            }
+        }
+        self.resting.retain(|r| r.remaining > DUST);
+        for (order_id, order, size) in fills {
+            self.fill(&book.name, &order_id, &order, order.price, size, true);
+        }
+    }
+
+    // Acts on whatever has reached the venue by the book's time. A stale book holds everything
+    // back until it is live again.
+    fn arrive(&mut self, book: &OrderBook) {
+        if book.stale {
+            return;
+        }
+        let now = book.now();
+        let (mut due, pending): (Vec<Pending>, Vec<Pending>) = self.pending.drain(..)
+            .partition(|p| p.arrival <= now && p.venue == book.name.as_str() && p.pair == book.pair.as_str());
+        self.pending = pending;
+        due.sort_by_key(|p| p.arrival);
+        for pending in due {
+            match pending.action {
+                Action::Place(order) => self.execute(book, pending.order_id, order),
+                Action::Cancel => {
+                    if self.remove(&pending.order_id) {
+                        self.emit(&pending.venue, OrderEvent::Canceled { order_id: pending.order_id });
+                    } else {
+                        let reason = "order is not open".to_string();
+                        self.emit(&pending.venue, OrderEvent::CancelRejected { order_id: pending.order_id, reason });
+                    }
+                },
+                Action::Replace(new_id, order) => {
+                    if self.remove(&pending.order_id) {
+                        self.emit(&pending.venue, OrderEvent::Canceled { order_id: pending.order_id });
+                        self.execute(book, new_id, order);
+                    } else {
+                        let reason = "order is not open".to_string();
+                        self.emit(&pending.venue, OrderEvent::CancelRejected { order_id: pending.order_id, reason: reason.clone() });
+                        self.emit(&pending.venue, OrderEvent::Rejected { client_id: order.client_id, reason });
+                    }
+                },
+            }
+        }
+    }
+
+    fn remove(&mut self, order_id: &str) -> bool {
+        let count = self.resting.len();
+        self.resting.retain(|r| r.order_id != order_id);
+        self.resting.len() < count
+    }
+
+    fn displayed(book: &OrderBook, side: Side, level: usize) -> f64 {
+        let displayed = match side {
+            Side::Buy => book.bid_lookup.get(&level),
+            Side::Sell => book.ask_lookup.get(&level),
+        };
+        displayed.map_or(0.0, |level| level.amount)
+    }
+
+    // Takes the displayed depth up to the limit, best price first, then rests or cancels the rest.
+    fn execute(&mut self, book: &OrderBook, order_id: String, order: OrderRequest) {
+        let (side, mut levels): (Side, Vec<(usize, f64)>) = match order.side {
+            Side::Buy => (Side::Sell, book.ask_lookup.values().filter(|l| l.level <= order.price).map(|l| (l.level, l.amount)).collect()),
+            Side::Sell => (Side::Buy, book.bid_lookup.values().filter(|l| l.level >= order.price).map(|l| (l.level, l.amount)).collect()),
+        };
+        levels.sort_by_key(|(level, _)| *level);
+        if order.side == Side::Sell {
+            levels.reverse();
+        }
+        let mut remaining = order.size;
+        for (level, amount) in levels {
+            if remaining <= DUST {
+                break;
+            }
+            let position = self.taken.iter().position(|t| t.venue == book.name.as_str() && t.pair == book.pair.as_str() && t.side == side && t.level == level);
+            let taken = position.map_or(0.0, |i| self.taken[i].size);
+            let size = (amount - taken).min(remaining);
+            if size <= DUST {
+                continue;
+            }
+            self.fill(&book.name, &order_id, &order, level, size, false);
+            remaining -= size;
+            match position {
+                Some(i) => self.taken[i].size += size,
+                None => self.taken.push(Taken {
+                    venue: book.name.to_string(),
+                    pair: book.pair.to_string(),
+                    side,
+                    level,
+                    displayed: amount,
+                    size,
+                }),
+            }
+        }
+        if remaining <= DUST {
+            return;
+        }
+        match order.time_in_force {
+            TimeInForce::Ioc => self.emit(&book.name, OrderEvent::Canceled { order_id }),
+            TimeInForce::Gtc => {
+                let displayed = match order.side {
+                    Side::Buy => book.bid_lookup.get(&order.price),
+                    Side::Sell => book.ask_lookup.get(&order.price),
+                };
+                let ahead = displayed.map_or(0.0, |level| level.amount) * self.config(&book.name).queue_ahead;
+                self.resting.push(Resting {
+                    venue: book.name.to_string(),
+                    order_id,
+                    order,
+                    remaining,
+                    ahead,
+                });
+            },
+        }
+    }
+
+    fn fill(&mut self, venue: &str, order_id: &str, order: &OrderRequest, price: usize, size: f64, maker: bool) {
+        let config = self.config(venue);
+        let notional = to_price(price) * size;
+        let fee = notional * if maker { config.maker_fee_bps } else { config.taker_fee_bps } / 10000.0;
+        let position = self.positions.entry((venue.to_string(), order.pair.to_string())).or_default();
+        match order.side {
+            Side::Buy => {
+                position.base += size;
+                position.quote -= notional;
+            },
+            Side::Sell => {
+                position.base -= size;
+                position.quote += notional;
+            },
+        }
+        position.quote -= fee;
+        position.fees += fee;
+        position.volume += notional;
+        position.fills += 1;
+        let fill = OrderEvent::Fill {
+            order_id: order_id.to_string(),
+            pair: order.pair.clone(),
+            side: order.side,
+            price,
+            size,
+            fee,
+        };
+        if let Some(portfolio) = &self.portfolio {
+            portfolio.lock().unwrap().apply(venue, &fill);
+        }
+        self.emit(venue, fill);
+    }
+
+    fn emit(&mut self, venue: &str, event: OrderEvent) {
+        if let Some(events) = self.events.get_mut(venue) {
+            events.push_back(event);
+        }
+    }
+
+    pub fn print(&self, pair: &str) {
+        let mut total = 0.0;
+        for ((venue, _), position) in self.positions.iter().filter(|((_, p), _)| p == pair) {
+            println!("{:?} paper position: {:?}, PnL: {:?}", venue, position, position.pnl());
+            total += position.pnl().unwrap_or(0.0);
+        }
+        println!("{:?} paper PnL: {:.2}", pair, total);
+    }
+}
+
+// One venue of a `PaperExchange` behind the same interface as the real send clients. Fills of
+// orders that arrive or rest beyond a call come from `events`.
+pub struct PaperVenue<const S: usize, const T: usize> {
+    venue: String,
+    books: Vec<(heapless::String<8>, Arc<tokio::sync::Mutex<MultiBook<S, T>>>)>,
+    exchange: Arc<Mutex<PaperExchange>>,
+}
+
+impl<const S: usize, const T: usize> PaperVenue<S, T> {
+    pub async fn new(venue: &str, books: Vec<Arc<tokio::sync::Mutex<MultiBook<S, T>>>>, exchange: Arc<Mutex<PaperExchange>>) -> Self {
+        let mut pairs = Vec::new();
+        for book in books {
+            let pair = book.lock().await.pair.clone();
+            pairs.push((pair, book));
+        }
+        exchange.lock().unwrap().subscribe(venue);
+        PaperVenue {
+            venue: venue.to_string(),
+            books: pairs,
+            exchange,
+        }
+    }
+
+    pub fn events(&mut self) -> Vec<OrderEvent> {
+        self.exchange.lock().unwrap().take_events(&self.venue)
+    }
+
+    fn book(&self, pair: &str) -> Result<&Arc<tokio::sync::Mutex<MultiBook<S, T>>>, OrderError> {
+        self.books.iter()
+            .find(|(p, _)| p.as_str() == pair)
+            .map(|(_, book)| book)
+            .ok_or_else(|| OrderError::Pair(pair.to_string()))
+    }
+}
+
+impl<const S: usize, const T: usize> OrderClient for PaperVenue<S, T> {
+    async fn place(&mut self, order: OrderRequest) -> Result<Vec<OrderEvent>, OrderError> {
+        let guard = self.book(&order.pair)?.lock().await;
+        let book_idx = guard.book_idx(&self.venue).ok_or_else(|| OrderError::Pair(order.pair.to_string()))?;
+        let mut exchange = self.exchange.lock().unwrap();
+        let mut events = vec![exchange.place(&guard.books[book_idx], order)];
+        events.extend(exchange.take_events(&self.venue));
+        Ok(events)
+    }
+
+    async fn cancel(&mut self, order_id: &str) -> Result<Vec<OrderEvent>, OrderError> {
+        let pair = self.exchange.lock().unwrap().order_pair(order_id);
+        let pair = match pair {
+            Some(pair) => pair,
+            None => return Ok(vec![OrderEvent::CancelRejected { order_id: order_id.to_string(), reason: "order is not open".to_string() }]),
+        };
+        let guard = self.book(&pair)?.lock().await;
+        let book_idx = guard.book_idx(&self.venue).ok_or_else(|| OrderError::Pair(pair.clone()))?;
+        let mut exchange = self.exchange.lock().unwrap();
+        exchange.cancel(&guard.books[book_idx], order_id);
+        Ok(exchange.take_events(&self.venue))
+    }
+
+    async fn replace(&mut self, order_id: &str, order: OrderRequest) -> Result<Vec<OrderEvent>, OrderError> {
+        let guard = self.book(&order.pair)?.lock().await;
+        let book_idx = guard.book_idx(&self.venue).ok_or_else(|| OrderError::Pair(order.pair.to_string()))?;
+        let mut exchange = self.exchange.lock().unwrap();
+        let mut events = vec![exchange.replace(&guard.books[book_idx], order_id, order)];
+        events.extend(exchange.take_events(&self.venue));
+        Ok(events)
+    }
+}
+
+#[cfg(test)]
+fn book(levels: &[(Side, usize, f64)], time: i64) -> OrderBook {
+    use super::data_types::{PriceLevel, Snapshot};
+    let mut book = OrderBook::new(heapless::String::from("coinbase"), heapless::String::from("ETH-USD"));
+    book.replay_time = Some(time);
+    let mut snapshot = Snapshot::new();
+    for (side, level, amount) in levels {
+        let side = match side {
+            Side::Buy => &mut snapshot.bids,
+            Side::Sell => &mut snapshot.asks,
+        };
+        let _ = side.push(PriceLevel { level: *level, amount: *amount, sequence: 0 });
+    }
+    book.init(snapshot);
+    book
+}
+
+#[cfg(test)]
+fn order(side: Side, price: usize, size: f64, time_in_force: TimeInForce) -> OrderRequest {
+    OrderRequest {
+        client_id: "a".to_string(),
+        pair: heapless::String::from("ETH-USD"),
+        side,
+        price,
+        size,
+        time_in_force,
+    }
+}
+
+#[test]
+fn test_ioc_takes_depth_after_latency() {
+    let mut exchange = PaperExchange::new(PaperConfig { latency: Duration::from_millis(100), taker_fee_bps: 10.0, ..PaperConfig::default() });
+    exchange.subscribe("coinbase");
+    let levels = [(Side::Buy, 189900, 1.0), (Side::Sell, 190000, 1.0), (Side::Sell, 190100, 2.0), (Side::Sell, 190300, 5.0)];
+    let placed = exchange.place(&book(&levels, 0), order(Side::Buy, 190200, 4.0, TimeInForce::Ioc));
+    assert_eq!(OrderEvent::Accepted { client_id: "a".to_string(), order_id: "paper-1".to_string() }, placed);
+    exchange.on_book(&book(&levels, 99_000_000));
+    assert!(exchange.take_events("coinbase").is_empty());
+
+    // By the time it arrives the best ask has gone.
+    let levels = [(Side::Buy, 190000, 1.0), (Side::Sell, 190100, 2.0), (Side::Sell, 190300, 5.0)];
+    exchange.on_book(&book(&levels, 100_000_000));
+    let events = exchange.take_events("coinbase");
+    assert_eq!(2, events.len());
+    assert!(matches!(events[0], OrderEvent::Fill { price: 190100, size, .. } if size == 2.0));
+    assert_eq!(OrderEvent::Canceled { order_id: "paper-1".to_string() }, events[1]);
+    let position = exchange.position("coinbase", "ETH-USD").unwrap();
+    assert_eq!((2.0, 1, 3802.0), (position.base, position.fills, position.volume));
+    assert!((position.fees - 3.802).abs() < 1e-9);
+    // Bought half a tick over the mid, plus fees.
+    assert!((position.pnl().unwrap() + 4.802).abs() < 1e-9);
+}
+
+#[test]
+fn test_taken_depth_returns_with_venue_update() {
+    let mut exchange = PaperExchange::new(PaperConfig::default());
+    exchange.subscribe("coinbase");
+    let levels = [(Side::Buy, 189900, 1.0), (Side::Sell, 190000, 1.0), (Side::Sell, 190100, 2.0)];
+    exchange.place(&book(&levels, 0), order(Side::Buy, 190000, 0.75, TimeInForce::Ioc));
+    exchange.place(&book(&levels, 1), order(Side::Buy, 190000, 0.75, TimeInForce::Ioc));
+    let sizes: Vec<f64> = exchange.take_events("coinbase").iter().filter_map(|event| match event {
+        OrderEvent::Fill { size, .. } => Some(*size),
+        _ => None,
+    }).collect();
+    assert_eq!(vec![0.75, 0.25], sizes);
+
+    // Once the venue changes the level its whole size is there to take again.
+    let levels = [(Side::Buy, 189900, 1.0), (Side::Sell, 190000, 1.5)];
+    exchange.place(&book(&levels, 2), order(Side::Buy, 190000, 2.0, TimeInForce::Ioc));
+    assert!(matches!(exchange.take_events("coinbase")[0], OrderEvent::Fill { price: 190000, size, .. } if size == 1.5));
+}
+
+#[test]
+fn test_resting_order_fills_behind_queue() {
+    let mut exchange = PaperExchange::new(PaperConfig { maker_fee_bps: -1.0, queue_ahead: 1.0, ..PaperConfig::default() });
+    exchange.subscribe("coinbase");
+    let levels = [(Side::Buy, 189900, 3.0), (Side::Sell, 190000, 1.0)];
+    exchange.place(&book(&levels, 0), order(Side::Buy, 189900, 1.0, TimeInForce::Gtc));
+    assert!(exchange.take_events("coinbase").is_empty());
+
+    // A third of the queue ahead cancels, then trades work through the rest.
+    let levels = [(Side::Buy, 189900, 2.0), (Side::Sell, 190000, 1.0)];
+    exchange.on_book(&book(&levels, 1));
+    exchange.on_trade(&book(&levels, 2), &Match { side: Side::Sell, size: 1.5, price: 189900 });
+    exchange.on_trade(&book(&levels, 3), &Match { side: Side::Buy, size: 5.0, price: 190000 });
+    assert!(exchange.take_events("coinbase").is_empty());
+    exchange.on_trade(&book(&levels, 4), &Match { side: Side::Sell, size: 0.75, price: 189900 });
+    exchange.on_trade(&book(&levels, 5), &Match { side: Side::Sell, size: 2.0, price: 189800 });
+    let fills: Vec<f64> = exchange.take_events("coinbase").iter().map(|event| match event {
+        OrderEvent::Fill { size, price: 189900, .. } => *size,
+        other => panic!("unexpected {:?}", other),
+    }).collect();
+    assert_eq!(vec![0.25, 0.75], fills);
+    let position = exchange.position("coinbase", "ETH-USD").unwrap();
+    assert!((position.fees + 0.1899).abs() < 1e-9);
+
+    exchange.cancel(&book(&levels, 6), "paper-1");
+    assert_eq!(vec![OrderEvent::CancelRejected { order_id: "paper-1".to_string(), reason: "order is not open".to_string() }], exchange.take_events("coinbase"));
+}
+
+#[tokio::test]
+async fn test_paper_venue_order_client() {
+    let mut multi_book = MultiBook::<2, 2>::new(heapless::String::from("ETH-USD"), [heapless::String::from("coinbase"), heapless::String::from("kraken")]);
+    multi_book.books[0] = book(&[(Side::Buy, 189900, 1.0), (Side::Sell, 190000, 1.0)], 0);
+    let lock = Arc::new(tokio::sync::Mutex::new(multi_book));
+    let exchange = Arc::new(Mutex::new(PaperExchange::new(PaperConfig::default())));
+    let mut venue = PaperVenue::new("coinbase", vec![lock.clone()], exchange.clone()).await;
+
+    let events = venue.place(order(Side::Sell, 190500, 1.0, TimeInForce::Gtc)).await.unwrap();
+    assert_eq!(vec![OrderEvent::Accepted { client_id: "a".to_string(), order_id: "paper-1".to_string() }], events);
+    let events = venue.replace("paper-1", order(Side::Sell, 189900, 2.0, TimeInForce::Gtc)).await.unwrap();
+    assert_eq!(3, events.len());
+    assert_eq!(OrderEvent::Canceled { order_id: "paper-1".to_string() }, events[1]);
+    assert!(matches!(events[2], OrderEvent::Fill { price: 189900, size, .. } if size == 1.0));
+    assert_eq!(Some("ETH-USD".to_string()), exchange.lock().unwrap().order_pair("paper-2"));
+    assert_eq!(vec![OrderEvent::Canceled { order_id: "paper-2".to_string() }], venue.cancel("paper-2").await.unwrap());
+    assert!(matches!(venue.place(OrderRequest { pair: heapless::String::from("BTC-USD"), ..order(Side::Buy, 1, 1.0, TimeInForce::Ioc) }).await, Err(OrderError::Pair(_))));
+    assert!(venue.events().is_empty());
+}
+
+#[test]
+fn test_paper_config_per_venue() {
+    let exchange = PaperExchange::parse(r#"{
+        "default": {"latency_ms": 50, "taker_fee_bps": 10.0},
+        "venues": {"kraken": {"latency_ms": 120, "taker_fee_bps": 26.0, "maker_fee_bps": 16.0}}
+    }"#).unwrap();
+    assert_eq!(PaperConfig { latency: Duration::from_millis(50), taker_fee_bps: 10.0, maker_fee_bps: 0.0, queue_ahead: 1.0 }, exchange.config("coinbase"));
+    assert_eq!(PaperConfig { latency: Duration::from_millis(120), taker_fee_bps: 26.0, maker_fee_bps: 16.0, queue_ahead: 1.0 }, exchange.config("kraken"));
+    let invalid = PaperExchange::parse(r#"{"venues": {"kraken": {"queue_ahead": 1.5}}}"#);
+    assert!(matches!(invalid, Err(ConfigError::Invalid(_))));
+    assert!(PaperExchange::load(concat!(env!("CARGO_MANIFEST_DIR"), "/config/paper.json")).is_ok());
+}
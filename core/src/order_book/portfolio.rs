@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use serde::Deserialize;
+
+use super::clients::orders::OrderEvent;
+use super::data_types::{to_price, Side};
+use super::instruments::ConfigError;
+
+#[derive(Debug, Deserialize)]
+struct PortfolioConfig {
+    #[serde(default)]
+    fee_reserve_bps: f64,
+    // Venue to asset to balance.
+    venues: HashMap<String, HashMap<String, f64>>,
+}
+
+// What we hold on each venue, by asset. Pairs are the canonical "BASE-QUOTE" names.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Portfolio {
+    balances: BTreeMap<(String, String), f64>,
+    // Quote held back from buys to pay their fees.
+    fee_reserve_bps: f64,
+}
+
+impl Portfolio {
+    pub fn new(fee_reserve_bps: f64) -> Portfolio {
+        Portfolio {
+            balances: BTreeMap::new(),
+            fee_reserve_bps,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Portfolio::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let config: PortfolioConfig = serde_json::from_str(contents).map_err(ConfigError::Parse)?;
+        let mut portfolio = Portfolio::new(config.fee_reserve_bps);
+        for (venue, assets) in config.venues.iter() {
+            for (asset, amount) in assets.iter() {
+                if *amount < 0.0 {
+                    return Err(ConfigError::Invalid(format!("{:?} balance on {:?} is negative", asset, venue)));
+                }
+                portfolio.set_balance(venue, asset, *amount);
+            }
+        }
+        Ok(portfolio)
+    }
+
+    pub fn set_balance(&mut self, venue: &str, asset: &str, amount: f64) {
+        self.balances.insert((venue.to_string(), asset.to_string()), amount);
+    }
+
+    pub fn balance(&self, venue: &str, asset: &str) -> f64 {
+        *self.balances.get(&(venue.to_string(), asset.to_string())).unwrap_or(&0.0)
+    }
+
+    // Moves balances for a fill on `venue`, real or simulated. Other events change nothing.
+    pub fn apply(&mut self, venue: &str, event: &OrderEvent) {
+        let (pair, side, price, size, fee) = match event {
+            OrderEvent::Fill { pair, side, price, size, fee, .. } => (pair, side, price, size, fee),
+            _ => return,
+        };
+        let (base, quote) = match pair.split_once('-') {
+            Some(assets) => assets,
+            None => return,
+        };
+        let notional = to_price(*price) * size;
+        let (base_change, quote_change) = match side {
+            Side::Buy => (*size, -notional),
+            Side::Sell => (-*size, notional),
+        };
+        *self.balances.entry((venue.to_string(), base.to_string())).or_default() += base_change;
+        *self.balances.entry((venue.to_string(), quote.to_string())).or_default() += quote_change - fee;
+    }
+
+    // The most of `pair` that can be bought on `buy_venue` at `ask` and sold on `sell_venue`: the
+    // quote on one side, less the fee reserve, and the base on the other.
+    pub fn max_executable(&self, pair: &str, buy_venue: &str, ask: usize, sell_venue: &str) -> f64 {
+        let (base, quote) = match pair.split_once('-') {
+            Some(assets) => assets,
+            None => return 0.0,
+        };
+        let cost = to_price(ask) * (1.0 + self.fee_reserve_bps / 10000.0);
+        let buyable = self.balance(buy_venue, quote).max(0.0) / cost;
+        buyable.min(self.balance(sell_venue, base).max(0.0))
+    }
+
+    pub fn print(&self) {
+        for ((venue, asset), amount) in self.balances.iter() {
+            println!("{:?} {} balance: {:?}", venue, asset, amount);
+        }
+    }
+}
+
+#[cfg(test)]
+fn fill(side: Side, price: usize, size: f64, fee: f64) -> OrderEvent {
+    OrderEvent::Fill {
+        order_id: "1".to_string(),
+        pair: heapless::String::from("ETH-USD"),
+        side,
+        price,
+        size,
+        fee,
+    }
+}
+
+#[test]
+fn test_fills_move_balances() {
+    let mut portfolio = Portfolio::parse(r#"{"venues": {"kraken": {"ETH": 2.0, "USD": 1000.0}}}"#).unwrap();
+    portfolio.apply("kraken", &fill(Side::Sell, 190000, 0.5, 0.95));
+    portfolio.apply("coinbase", &fill(Side::Buy, 189000, 0.5, 0.0));
+    portfolio.apply("kraken", &OrderEvent::Canceled { order_id: "1".to_string() });
+    assert_eq!(1.5, portfolio.balance("kraken", "ETH"));
+    assert_eq!(1000.0 + 950.0 - 0.95, portfolio.balance("kraken", "USD"));
+    assert_eq!((0.5, -945.0), (portfolio.balance("coinbase", "ETH"), portfolio.balance("coinbase", "USD")));
+    assert!(matches!(Portfolio::parse(r#"{"venues": {"kraken": {"USD": -1.0}}}"#), Err(ConfigError::Invalid(_))));
+}
+
+#[test]
+fn test_max_executable() {
+    let mut portfolio = Portfolio::new(0.0);
+    portfolio.set_balance("coinbase", "USD", 3800.0);
+    portfolio.set_balance("kraken", "ETH", 5.0);
+    // All our ETH is on Kraken, so it can only be sold there.
+    assert_eq!(2.0, portfolio.max_executable("ETH-USD", "coinbase", 190000, "kraken"));
+    assert_eq!(0.0, portfolio.max_executable("ETH-USD", "kraken", 190000, "coinbase"));
+    portfolio.set_balance("kraken", "ETH", 1.5);
+    assert_eq!(1.5, portfolio.max_executable("ETH-USD", "coinbase", 190000, "kraken"));
+    let mut portfolio = Portfolio { fee_reserve_bps: 100.0, ..portfolio };
+    portfolio.set_balance("kraken", "ETH", 5.0);
+    assert!((portfolio.max_executable("ETH-USD", "coinbase", 190000, "kraken") - 2.0 / 1.01).abs() < 1e-9);
+}
@@ -0,0 +1,189 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use serde::{Deserialize, Serialize};
+
+use super::data_types::{Change, Match, PriceLevel, Side, Snapshot, Update};
+use super::multi_book::MultiBook;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event {
+    Snapshot {
+        time: i64,
+        venue: String,
+        pair: String,
+        bids: Vec<(usize, f64)>,
+        asks: Vec<(usize, f64)>,
+    },
+    Update {
+        time: i64,
+        venue: String,
+        pair: String,
+        changes: Vec<(Side, usize, f64)>,
+    },
+    Match {
+        time: i64,
+        venue: String,
+        pair: String,
+        side: Side,
+        size: f64,
+        price: usize,
+    },
+}
+
+impl Event {
+    pub fn snapshot(time: i64, venue: &str, pair: &str, snapshot: &Snapshot) -> Self {
+        Event::Snapshot {
+            time,
+            venue: venue.to_string(),
+            pair: pair.to_string(),
+            bids: snapshot.bids.iter().map(|p| (p.level, p.amount)).collect(),
+            asks: snapshot.asks.iter().map(|p| (p.level, p.amount)).collect(),
+        }
+    }
+
+    pub fn update(time: i64, venue: &str, pair: &str, update: &Update) -> Self {
+        Event::Update {
+            time,
+            venue: venue.to_string(),
+            pair: pair.to_string(),
+            changes: update.changes.iter()
+                .map(|c| (c.side, c.price_level.level, c.price_level.amount))
+                .collect(),
+        }
+    }
+
+    pub fn match_(time: i64, venue: &str, pair: &str, match_: &Match) -> Self {
+        Event::Match {
+            time,
+            venue: venue.to_string(),
+            pair: pair.to_string(),
+            side: match_.side,
+            size: match_.size,
+            price: match_.price,
+        }
+    }
+
+    fn time(&self) -> i64 {
+        match self {
+            Event::Snapshot { time, .. } | Event::Update { time, .. } | Event::Match { time, .. } => *time,
+        }
+    }
+
+    fn key(&self) -> (&str, &str) {
+        match self {
+            Event::Snapshot { venue, pair, .. }
+            | Event::Update { venue, pair, .. }
+            | Event::Match { venue, pair, .. } => (venue, pair),
+        }
+    }
+}
+
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    pub fn create(path: &str) -> std::io::Result<Recorder> {
+        Ok(Recorder {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    pub fn record(&mut self, event: &Event) {
+        let result = serde_json::to_writer(&mut self.writer, event)
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+        if let Err(err) = result {
+            println!("Error recording event: {:?}", err);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(err) = self.writer.flush() {
+            println!("Error flushing recording: {:?}", err);
+        }
+    }
+}
+
+// Feeds a recording made with `Recorder` back through the books, using the recorded event times as
+// the book clock. Events for venues or pairs that are not in `multi_books` are skipped.
+pub fn replay<const S: usize, const T: usize>(path: &str, multi_books: &mut [MultiBook<S, T>]) -> std::io::Result<usize> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut count = 0;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = match serde_json::from_str::<Event>(&line) {
+            Ok(event) => event,
+            Err(err) => {
+                println!("Skipping unreadable replay event {:?}: {:?}", line, err);
+                continue;
+            }
+        };
+        let (venue, pair) = event.key();
+        let multi_book = match multi_books.iter_mut().find(|m| m.pair.as_str() == pair) {
+            Some(m) => m,
+            None => continue,
+        };
+        let book_idx = match multi_book.book_idx(venue) {
+            Some(i) => i,
+            None => continue,
+        };
+        multi_book.books[book_idx].replay_time = Some(event.time());
+        apply(multi_book, book_idx, event);
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn apply<const S: usize, const T: usize>(multi_book: &mut MultiBook<S, T>, book_idx: usize, event: Event) {
+    match event {
+        Event::Snapshot { bids, asks, .. } => {
+            let mut snapshot = Snapshot::new();
+            for (level, amount) in bids {
+                let _ = snapshot.bids.push(PriceLevel { level, amount, sequence: 0 });
+            }
+            for (level, amount) in asks {
+                let _ = snapshot.asks.push(PriceLevel { level, amount, sequence: 0 });
+            }
+            multi_book.books[book_idx].init(snapshot);
+        },
+        Event::Update { changes, .. } => {
+            let mut update = Update { product_id: "", time: "", changes: heapless::Vec::new() };
+            for (side, level, amount) in changes {
+                let _ = update.changes.push(Change { side, price_level: PriceLevel { level, amount, sequence: 0 } });
+            }
+            multi_book.books[book_idx].update(update);
+            multi_book.update_spread(book_idx);
+        },
+        Event::Match { side, size, price, .. } => {
+            let book = &mut multi_book.books[book_idx];
+            if book.best_bid.is_some() && book.best_ask.is_some() {
+                book.update_impulse(Match { side, size, price });
+                multi_book.update_spread(book_idx);
+            }
+        },
+    }
+}
+
+#[test]
+fn test_event_round_trip() {
+    let event = Event::Match {
+        time: 1689025543609620000,
+        venue: "coinbase".to_string(),
+        pair: "ETH-USD".to_string(),
+        side: Side::Buy,
+        size: 0.5,
+        price: 189720,
+    };
+    let line = serde_json::to_string(&event).unwrap();
+    assert_eq!(
+        "{\"type\":\"match\",\"time\":1689025543609620000,\"venue\":\"coinbase\",\"pair\":\"ETH-USD\",\"side\":\"buy\",\"size\":0.5,\"price\":189720}",
+        line
+    );
+    assert_eq!(event, serde_json::from_str::<Event>(&line).unwrap());
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VolatilityConfig {
+    pub sample_interval: Duration,
+    pub window: usize,
+    pub tight_bps: f64,
+    pub wide_bps: f64,
+}
+
+impl Default for VolatilityConfig {
+    fn default() -> Self {
+        VolatilityConfig {
+            sample_interval: Duration::from_secs(1),
+            window: 300,
+            tight_bps: 2.0,
+            wide_bps: 10.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpreadRegime {
+    Tight,
+    Normal,
+    Wide,
+}
+
+#[derive(Default)]
+pub struct VolatilityEstimator {
+    pub config: VolatilityConfig,
+    next_sample: Option<i64>,
+    current: Option<(f64, f64)>,
+    last_sampled_mid: f64,
+    returns: VecDeque<f64>,
+    sum_returns: f64,
+    sum_squares: f64,
+    spreads: VecDeque<f64>,
+    sum_spreads: f64,
+}
+
+impl VolatilityEstimator {
+    pub fn new(config: VolatilityConfig) -> Self {
+        VolatilityEstimator {
+            config,
+            ..Default::default()
+        }
+    }
+
+    // Samples are taken on a fixed grid. The mid and spread in force at a grid point are the ones
+    // seen before this change, so a quiet book still contributes zero returns.
+    pub fn observe(&mut self, time: i64, mid: f64, spread_bps: f64) {
+        let interval = self.config.sample_interval.as_nanos() as i64;
+        match (self.next_sample, self.current) {
+            (Some(mut next), Some((current_mid, current_spread))) => {
+                let mut samples = 0;
+                while next <= time {
+                    if samples < self.config.window {
+                        self.sample(current_mid, current_spread);
+                        samples += 1;
+                    }
+                    next += interval;
+                }
+                if samples == self.config.window {
+                    next = time + interval;
+                }
+                self.next_sample = Some(next);
+            },
+            _ => {
+                self.next_sample = Some(time + interval);
+                self.last_sampled_mid = mid;
+            },
+        }
+        self.current = Some((mid, spread_bps));
+    }
+
+    fn sample(&mut self, mid: f64, spread_bps: f64) {
+        let log_return = (mid / self.last_sampled_mid).ln();
+        self.last_sampled_mid = mid;
+        self.returns.push_back(log_return);
+        self.sum_returns += log_return;
+        self.sum_squares += log_return * log_return;
+        self.spreads.push_back(spread_bps);
+        self.sum_spreads += spread_bps;
+        if self.returns.len() > self.config.window {
+            let old = self.returns.pop_front().unwrap();
+            self.sum_returns -= old;
+            self.sum_squares -= old * old;
+            self.sum_spreads -= self.spreads.pop_front().unwrap();
+        }
+    }
+
+    // Standard deviation of the sampled log returns, in basis points per sample interval.
+    pub fn volatility(&self) -> Option<f64> {
+        let n = self.returns.len() as f64;
+        if self.returns.len() < 2 {
+            return None;
+        }
+        let variance = (self.sum_squares - self.sum_returns * self.sum_returns / n) / (n - 1.0);
+        Some(variance.max(0.0).sqrt() * 10000.0)
+    }
+
+    pub fn average_spread(&self) -> Option<f64> {
+        if self.spreads.is_empty() {
+            return None;
+        }
+        Some(self.sum_spreads / self.spreads.len() as f64)
+    }
+
+    pub fn regime(&self) -> Option<SpreadRegime> {
+        let spread = self.average_spread()?;
+        if spread <= self.config.tight_bps {
+            Some(SpreadRegime::Tight)
+        } else if spread >= self.config.wide_bps {
+            Some(SpreadRegime::Wide)
+        } else {
+            Some(SpreadRegime::Normal)
+        }
+    }
+}
+
+#[test]
+fn test_volatility_samples_on_interval() {
+    let mut estimator = VolatilityEstimator::new(VolatilityConfig {
+        sample_interval: Duration::from_secs(1),
+        window: 4,
+        tight_bps: 2.0,
+        wide_bps: 10.0,
+    });
+    estimator.observe(0, 10000.0, 1.0);
+    estimator.observe(500_000_000, 10010.0, 1.0);
+    assert_eq!(None, estimator.average_spread());
+    estimator.observe(1_200_000_000, 10000.0, 1.0);
+    assert_eq!(Some(1.0), estimator.average_spread());
+    assert_eq!(Some(SpreadRegime::Tight), estimator.regime());
+    assert_eq!(None, estimator.volatility());
+    estimator.observe(2_100_000_000, 10000.0, 20.0);
+    assert!(estimator.volatility().unwrap() > 0.0);
+    estimator.observe(60_000_000_000, 10000.0, 20.0);
+    assert_eq!(4, estimator.returns.len());
+    assert_eq!(Some(SpreadRegime::Wide), estimator.regime());
+    assert!(estimator.volatility().unwrap() < 1e-6);
+}
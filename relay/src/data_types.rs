@@ -0,0 +1,45 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct RelayConfig {
+    // Feed to run: a venue name, or "kraken-v2" for Kraken's v2 API.
+    pub venue: String,
+    pub instruments: String,
+    // Pairs to relay; every pair the instruments file lists on the venue when empty.
+    #[serde(default)]
+    pub pairs: Vec<String>,
+    pub targets: Vec<TargetConfig>,
+    // Extra root certificate (PEM) for cores whose certificates aren't publicly trusted.
+    pub ca: Option<String>,
+    #[serde(default)]
+    pub queue: QueueConfig,
+}
+
+// Outbound queue kept for each core.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct QueueConfig {
+    // Messages held while the core is slow. Tops arriving at a full queue are dropped; trades are
+    // still queued, for up to `trade_wait_ms`.
+    pub capacity: usize,
+    // Messages written per flush.
+    pub batch: usize,
+    // Keep only the newest waiting top of book per instrument.
+    pub coalesce_tops: bool,
+    // How long the queue may stay full while trades keep arriving before the core is disconnected.
+    pub trade_wait_ms: u64,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        QueueConfig { capacity: 4096, batch: 64, coalesce_tops: true, trade_wait_ms: 1000 }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct TargetConfig {
+    // Core listener as ip:port.
+    pub address: String,
+    // Name the core's certificate is checked against; the link is plain WebSocket when absent.
+    pub tls_domain: Option<String>,
+}
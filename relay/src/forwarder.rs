@@ -0,0 +1,422 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use futures_util::{SinkExt, StreamExt};
+use prism_wire::{Header, Level, Message as WireMessage};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio_native_tls::native_tls;
+use tokio_tungstenite::{client_async_tls_with_config, Connector, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use prism::order_book::clients::clock;
+use prism::order_book::clients::feed::{BookAdapter, FeedEvent};
+use prism::order_book::data_types::{PriceLevel, Side, PRICE_SCALE};
+
+use crate::data_types::QueueConfig;
+use crate::queue::OutboundQueue;
+
+const TARGET_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const QUEUE_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+// Levels for one pair, kept so depth feeds can be forwarded as top of book.
+#[derive(Default)]
+struct LocalBook {
+    bids: BTreeMap<usize, f64>,
+    asks: BTreeMap<usize, f64>,
+    top: Option<((usize, f64), (usize, f64))>,
+}
+
+impl LocalBook {
+    fn set(&mut self, side: Side, level: &PriceLevel) {
+        let levels = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        if level.amount == 0.0 {
+            levels.remove(&level.level);
+        } else {
+            levels.insert(level.level, level.amount);
+        }
+    }
+
+    // The new top of book, if it differs from the last one forwarded.
+    fn changed_top(&mut self) -> Option<((usize, f64), (usize, f64))> {
+        let bid = self.bids.iter().next_back().map(|(l, a)| (*l, *a))?;
+        let ask = self.asks.iter().next().map(|(l, a)| (*l, *a))?;
+        if self.top == Some((bid, ask)) {
+            return None;
+        }
+        self.top = Some((bid, ask));
+        self.top
+    }
+}
+
+#[derive(Clone)]
+pub struct TargetTls {
+    pub domain: String,
+    pub connector: native_tls::TlsConnector,
+}
+
+// Normalises a venue feed's events into wire messages and hands them to every target.
+pub struct RelayAdapter {
+    venue: String,
+    // Every pair in the instruments file, in order, since wire instrument ids index it.
+    pairs: Vec<heapless::String<8>>,
+    // Answer to the core's challenge, when it asks for one.
+    token: Option<String>,
+    books: HashMap<heapless::String<8>, LocalBook>,
+    targets: Vec<Arc<OutboundQueue>>,
+    // Latest top of book per pair, sent to targets when they (re)connect.
+    latest: Arc<Mutex<HashMap<heapless::String<8>, WireMessage>>>,
+}
+
+impl RelayAdapter {
+    pub fn new(venue: &str, pairs: Vec<heapless::String<8>>, token: Option<String>) -> RelayAdapter {
+        RelayAdapter {
+            venue: venue.to_string(),
+            pairs,
+            token,
+            books: HashMap::new(),
+            targets: Vec::new(),
+            latest: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Adds a downstream core, returning the task that keeps it connected.
+    pub fn target(&mut self, addr: SocketAddr, tls: Option<TargetTls>, queue: QueueConfig) -> impl std::future::Future<Output = ()> {
+        let queue = Arc::new(OutboundQueue::new(queue));
+        self.targets.push(queue.clone());
+        let hello = WireMessage::Hello {
+            version: prism_wire::VERSION,
+            instruments: prism_wire::instruments_digest(&self.pairs),
+            venue: self.venue.clone(),
+        };
+        let link = Link { addr, tls, hello, token: self.token.clone() };
+        forward(link, queue, self.latest.clone())
+    }
+
+    // Each target drains on its own, so pushing never waits on a slow core.
+    fn send(&self, message: WireMessage) {
+        for target in self.targets.iter() {
+            target.push(message.clone());
+        }
+    }
+
+    // Sequences are stamped per connection when the message is sent.
+    fn header(&self, pair: &str, exchange_time: Option<i64>) -> Option<Header> {
+        let instrument = self.pairs.iter().position(|p| p == pair)?;
+        Some(Header {
+            instrument: instrument as u16,
+            seq: 0,
+            exchange_time: exchange_time.unwrap_or(0),
+            relay_time: clock::now(),
+        })
+    }
+}
+
+impl BookAdapter for RelayAdapter {
+    async fn apply(&mut self, _venue: &str, event: FeedEvent) {
+        let pair = heapless::String::<8>::from(event.pair());
+        let book = self.books.entry(pair.clone()).or_default();
+        match event {
+            FeedEvent::Snapshot { snapshot, .. } => {
+                book.bids.clear();
+                book.asks.clear();
+                for level in snapshot.bids.iter() {
+                    book.set(Side::Buy, level);
+                }
+                for level in snapshot.asks.iter() {
+                    book.set(Side::Sell, level);
+                }
+            },
+            FeedEvent::Delta { changes, .. } => {
+                for change in changes.iter() {
+                    book.set(change.side, &change.price_level);
+                }
+            },
+            FeedEvent::TopOfBook { bid, ask, .. } => {
+                book.bids.clear();
+                book.asks.clear();
+                book.set(Side::Buy, &bid);
+                book.set(Side::Sell, &ask);
+            },
+            FeedEvent::Trade { trade, time, .. } => {
+                if let Some(header) = self.header(&pair, time) {
+                    self.send(WireMessage::Trade {
+                        header,
+                        side: match trade.side {
+                            Side::Buy => prism_wire::Side::Buy,
+                            Side::Sell => prism_wire::Side::Sell,
+                        },
+                        price: fixed_price(trade.price),
+                        size: prism_wire::to_fixed(trade.size),
+                    });
+                }
+                return;
+            },
+        }
+        let (bid, ask) = match book.changed_top() {
+            Some(top) => top,
+            None => return,
+        };
+        if let Some(header) = self.header(&pair, None) {
+            let message = WireMessage::TopOfBook {
+                header,
+                bid: Level { price: fixed_price(bid.0), size: prism_wire::to_fixed(bid.1) },
+                ask: Level { price: fixed_price(ask.0), size: prism_wire::to_fixed(ask.1) },
+            };
+            self.latest.lock().unwrap().insert(pair, message.clone());
+            self.send(message);
+        }
+    }
+
+    async fn set_stale(&mut self, venue: &str, stale: bool) {
+        println!("{} relay: upstream stale {:?}", venue, stale);
+    }
+}
+
+fn fixed_price(level: usize) -> u64 {
+    level as u64 * (prism_wire::SCALE / PRICE_SCALE as u64)
+}
+
+// How to reach and identify to one core.
+struct Link {
+    addr: SocketAddr,
+    tls: Option<TargetTls>,
+    hello: WireMessage,
+    token: Option<String>,
+}
+
+impl Link {
+    async fn connect(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, String> {
+        let socket = TcpSocket::new_v4().map_err(|e| e.to_string())?;
+        socket.set_nodelay(true).map_err(|e| e.to_string())?;
+        let tcp = socket.connect(self.addr).await.map_err(|e| e.to_string())?;
+        let (url, connector) = match &self.tls {
+            Some(tls) => (format!("wss://{}:{}", tls.domain, self.addr.port()), Connector::NativeTls(tls.connector.clone())),
+            None => (format!("ws://{}", self.addr), Connector::Plain),
+        };
+        let (mut stream, _) = client_async_tls_with_config(url, tcp, None, Some(connector)).await.map_err(|e| e.to_string())?;
+        stream.send(Message::Binary(self.hello.encode())).await.map_err(|e| e.to_string())?;
+        loop {
+            let reply = match tokio::time::timeout(HANDSHAKE_TIMEOUT, stream.next()).await {
+                Ok(Some(Ok(Message::Binary(bytes)))) => WireMessage::decode(&bytes).map_err(|e| e.to_string())?,
+                Ok(other) => return Err(format!("expected a handshake reply, got {:?}", other)),
+                Err(_) => return Err("handshake timed out".to_string()),
+            };
+            match reply {
+                WireMessage::Accept { .. } => return Ok(stream),
+                WireMessage::Challenge { nonce } => {
+                    let token = self.token.as_ref().ok_or("core asked for a token but RELAY_TOKEN is not set")?;
+                    let venue = match &self.hello {
+                        WireMessage::Hello { venue, .. } => venue.as_str(),
+                        _ => "",
+                    };
+                    let proof = prism_wire::auth_proof(token.as_bytes(), &nonce, venue);
+                    stream.send(Message::Binary(WireMessage::Auth { proof }.encode())).await.map_err(|e| e.to_string())?;
+                },
+                other => return Err(format!("handshake refused: {:?}", other)),
+            }
+        }
+    }
+}
+
+// Keeps one core connected, writing its queue in batches. Messages produced while it is down are
+// dropped; the latest top of book for each pair is resent once it is back. A core that falls too
+// far behind is disconnected, even mid-write.
+async fn forward(link: Link, queue: Arc<OutboundQueue>, latest: Arc<Mutex<HashMap<heapless::String<8>, WireMessage>>>) {
+    let addr = link.addr;
+    let mut report = tokio::time::interval(QUEUE_REPORT_INTERVAL);
+    loop {
+        queue.set_open(false);
+        let mut stream = match link.connect().await {
+            Ok(stream) => stream,
+            Err(err) => {
+                println!("Failed to connect to core {:?}: {}", addr, err);
+                tokio::time::sleep(TARGET_RECONNECT_DELAY).await;
+                continue;
+            },
+        };
+        println!("Connected to core {:?}", addr);
+        let mut seq = 0;
+        let mut stamp = |mut message: WireMessage| {
+            seq += 1;
+            if let WireMessage::TopOfBook { header, .. } | WireMessage::Trade { header, .. } = &mut message {
+                header.seq = seq;
+            }
+            Message::Binary(message.encode())
+        };
+        let tops: Vec<WireMessage> = latest.lock().unwrap().values().cloned().collect();
+        let mut open = true;
+        for top in tops {
+            if stream.feed(stamp(top)).await.is_err() {
+                open = false;
+            }
+        }
+        if open && stream.flush().await.is_err() {
+            open = false;
+        }
+        queue.set_open(open);
+        while open {
+            let reply = tokio::select! {
+                batch = queue.pop_batch() => {
+                    let write = async {
+                        for message in batch {
+                            stream.feed(stamp(message)).await?;
+                        }
+                        stream.flush().await
+                    };
+                    tokio::select! {
+                        result = write => if let Err(err) = result {
+                            println!("Lost connection to core {:?}: {:?}", addr, err);
+                            open = false;
+                        },
+                        _ = queue.lagging() => {
+                            println!("Core {:?} fell behind, disconnecting", addr);
+                            open = false;
+                        },
+                    }
+                    continue;
+                },
+                _ = queue.lagging() => {
+                    println!("Core {:?} fell behind, disconnecting", addr);
+                    break;
+                },
+                _ = report.tick() => {
+                    let stats = queue.stats();
+                    println!(
+                        "Core {:?} queue: depth {:?}, peak {:?}, coalesced {:?}, dropped {:?}, trades lost {:?}, lagged {:?}, sent {:?} in {:?} batches",
+                        addr, stats.depth, stats.peak, stats.coalesced, stats.dropped, stats.trades_lost, stats.lagged, stats.sent, stats.batches,
+                    );
+                    continue;
+                },
+                msg = stream.next() => match msg {
+                    Some(Ok(Message::Binary(bytes))) => match WireMessage::decode(&bytes) {
+                        Ok(WireMessage::Ping { origin }) => {
+                            let received = clock::now();
+                            Message::Binary(WireMessage::Pong { origin, received, sent: clock::now() }.encode())
+                        },
+                        _ => continue,
+                    },
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => {
+                        println!("Core {:?} closed the connection", addr);
+                        break;
+                    },
+                    Some(Ok(_)) => continue,
+                },
+            };
+            if let Err(err) = stream.send(reply).await {
+                println!("Lost connection to core {:?}: {:?}", addr, err);
+                open = false;
+            }
+        }
+        tokio::time::sleep(TARGET_RECONNECT_DELAY).await;
+    }
+}
+
+#[cfg(test)]
+fn level(level: usize, amount: f64) -> PriceLevel {
+    PriceLevel { level, amount, sequence: 0 }
+}
+
+#[tokio::test]
+async fn test_depth_forwarded_as_top_of_book() {
+    use prism::order_book::data_types::{Change, Match, Snapshot};
+
+    let mut adapter = RelayAdapter::new("coinbase", vec![heapless::String::from("ETH-USD"), heapless::String::from("BTC-USD")], None);
+    let queue = Arc::new(OutboundQueue::new(QueueConfig { coalesce_tops: false, ..QueueConfig::default() }));
+    queue.set_open(true);
+    adapter.targets.push(queue.clone());
+    let mut snapshot = Snapshot::new();
+    let _ = snapshot.bids.push(level(3000000, 1.0));
+    let _ = snapshot.bids.push(level(2999900, 2.0));
+    let _ = snapshot.asks.push(level(3000100, 0.5));
+    let pair = heapless::String::<8>::from("BTC-USD");
+    adapter.apply("coinbase", FeedEvent::Snapshot { pair: pair.clone(), snapshot }).await;
+    // Below the top of book, so nothing is forwarded.
+    let mut changes = heapless::Vec::new();
+    let _ = changes.push(Change { side: Side::Buy, price_level: level(2999900, 3.0) });
+    adapter.apply("coinbase", FeedEvent::Delta { pair: pair.clone(), changes }).await;
+    let mut changes = heapless::Vec::new();
+    let _ = changes.push(Change { side: Side::Buy, price_level: level(3000000, 0.0) });
+    adapter.apply("coinbase", FeedEvent::Delta { pair: pair.clone(), changes }).await;
+    adapter.apply("coinbase", FeedEvent::Trade { pair: pair.clone(), trade: Match { side: Side::Sell, size: 0.25, price: 3000000 }, time: Some(1689025543609000000) }).await;
+
+    let mut sent = queue.pop_batch().await.into_iter();
+    let tops: Vec<(u16, Level, Level)> = (0..2).map(|_| match sent.next().unwrap() {
+        WireMessage::TopOfBook { header, bid, ask } => (header.instrument, bid, ask),
+        other => panic!("Expected top of book, got {:?}", other),
+    }).collect();
+    assert_eq!((1, Level { price: 3000000000000, size: 100000000 }, Level { price: 3000100000000, size: 50000000 }), tops[0]);
+    assert_eq!(Level { price: 2999900000000, size: 300000000 }, tops[1].1);
+    match sent.next().unwrap() {
+        WireMessage::Trade { header, side, price, size } => {
+            assert_eq!((1, 1689025543609000000), (header.instrument, header.exchange_time));
+            assert_eq!((prism_wire::Side::Sell, 3000000000000, 25000000), (side, price, size));
+        },
+        other => panic!("Expected trade, got {:?}", other),
+    }
+    assert!(sent.next().is_none());
+    assert_eq!(0, queue.stats().depth);
+    assert!(matches!(adapter.latest.lock().unwrap()[&pair], WireMessage::TopOfBook { bid: Level { price: 2999900000000, .. }, .. }));
+}
+
+#[tokio::test]
+async fn test_link_answers_challenge() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let core = tokio::spawn(async move {
+        let (connection, _) = listener.accept().await.unwrap();
+        let mut stream = tokio_tungstenite::accept_async(connection).await.unwrap();
+        let hello = match stream.next().await {
+            Some(Ok(Message::Binary(bytes))) => WireMessage::decode(&bytes).unwrap(),
+            other => panic!("Expected hello, got {:?}", other),
+        };
+        let nonce = [9u8; 32];
+        stream.send(Message::Binary(WireMessage::Challenge { nonce }.encode())).await.unwrap();
+        let proof = match stream.next().await {
+            Some(Ok(Message::Binary(bytes))) => WireMessage::decode(&bytes).unwrap(),
+            other => panic!("Expected auth, got {:?}", other),
+        };
+        stream.send(Message::Binary(WireMessage::Accept { version: prism_wire::VERSION }.encode())).await.unwrap();
+        (hello, proof, stream)
+    });
+    let hello = WireMessage::Hello { version: prism_wire::VERSION, instruments: 1, venue: "bybit".to_string() };
+    let link = Link { addr, tls: None, hello: hello.clone(), token: Some("secret".to_string()) };
+    assert!(link.connect().await.is_ok());
+    let (received, proof, _stream) = core.await.unwrap();
+    assert_eq!(hello, received);
+    assert_eq!(WireMessage::Auth { proof: prism_wire::auth_proof(b"secret", &[9u8; 32], "bybit") }, proof);
+}
+
+#[tokio::test]
+async fn test_forward_answers_ping() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let core = tokio::spawn(async move {
+        let (connection, _) = listener.accept().await.unwrap();
+        let mut stream = tokio_tungstenite::accept_async(connection).await.unwrap();
+        stream.next().await;
+        stream.send(Message::Binary(WireMessage::Accept { version: prism_wire::VERSION }.encode())).await.unwrap();
+        stream.send(Message::Binary(WireMessage::Ping { origin: 42 }.encode())).await.unwrap();
+        match stream.next().await {
+            Some(Ok(Message::Binary(bytes))) => WireMessage::decode(&bytes).unwrap(),
+            other => panic!("Expected pong, got {:?}", other),
+        }
+    });
+    let hello = WireMessage::Hello { version: prism_wire::VERSION, instruments: 1, venue: "bybit".to_string() };
+    let link = Link { addr, tls: None, hello, token: None };
+    let queue = Arc::new(OutboundQueue::new(QueueConfig::default()));
+    let relay = tokio::spawn(forward(link, queue, Arc::new(Mutex::new(HashMap::new()))));
+    match core.await.unwrap() {
+        WireMessage::Pong { origin, received, sent } => {
+            assert_eq!(42, origin);
+            assert!(received > 0 && sent >= received);
+        },
+        other => panic!("Expected pong, got {:?}", other),
+    }
+    relay.abort();
+}
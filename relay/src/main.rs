@@ -0,0 +1,87 @@
+mod data_types;
+mod forwarder;
+mod queue;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use prism::order_book::clients::binance::binance_client::BinanceFeed;
+use prism::order_book::clients::bitstamp::bitstamp_client::BitstampFeed;
+use prism::order_book::clients::bybit::bybit_client::BybitFeed;
+use prism::order_book::clients::client::{Heartbeat, ReconnectPolicy};
+use prism::order_book::clients::coinbase::coinbase_client::CoinbaseFeed;
+use prism::order_book::clients::feed::{ExchangeFeed, FeedDriver};
+use prism::order_book::clients::gemini::gemini_client::GeminiFeed;
+use prism::order_book::clients::kraken::kraken_client::KrakenFeed;
+use prism::order_book::clients::kraken::kraken_v2_client::KrakenV2Feed;
+use prism::order_book::clients::okx::okx_client::OkxFeed;
+use prism::order_book::instruments::InstrumentRegistry;
+
+use tokio_native_tls::native_tls;
+
+use data_types::RelayConfig;
+use forwarder::{RelayAdapter, TargetTls};
+
+const DEFAULT_CONFIG: &str = "config/relay.json";
+const RELAY_RECONNECT: ReconnectPolicy = ReconnectPolicy {
+    initial_delay: Duration::from_secs(1),
+    max_delay: Duration::from_secs(60),
+    multiplier: 2.0,
+    jitter: 0.2,
+    max_attempts: Some(10),
+    cooldown: Duration::from_secs(300),
+};
+const RELAY_HEARTBEAT: Heartbeat = Heartbeat {
+    ping_interval: Duration::from_secs(15),
+    idle_timeout: Duration::from_secs(30),
+};
+
+async fn relay<F: ExchangeFeed>(feed: F, adapter: RelayAdapter) {
+    let mut driver = FeedDriver::new(feed, adapter, RELAY_RECONNECT, RELAY_HEARTBEAT);
+    driver.run().await;
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let path = match args.iter().position(|a| a == "--config") {
+        Some(i) => args.get(i + 1).expect("--config requires a path").as_str(),
+        None => DEFAULT_CONFIG,
+    };
+    let contents = std::fs::read_to_string(path).unwrap_or_else(|err| panic!("Failed to read {:?}: {}", path, err));
+    let config: RelayConfig = serde_json::from_str(&contents).unwrap_or_else(|err| panic!("Failed to parse {:?}: {}", path, err));
+    let mut registry = match InstrumentRegistry::load(&config.instruments) {
+        Ok(registry) => registry,
+        Err(err) => panic!("Failed to load instruments from {:?}: {}", config.instruments, err),
+    };
+    // Instrument ids on the wire index the whole file, whichever pairs this relay carries.
+    let mut adapter = RelayAdapter::new(&config.venue, registry.pairs(), std::env::var("RELAY_TOKEN").ok());
+    if !config.pairs.is_empty() {
+        registry.retain_pairs(&config.pairs);
+    }
+    let registry = Arc::new(registry);
+
+    let mut tls = native_tls::TlsConnector::builder();
+    if let Some(ca) = &config.ca {
+        let pem = std::fs::read(ca).unwrap_or_else(|err| panic!("Failed to read {:?}: {}", ca, err));
+        tls.add_root_certificate(native_tls::Certificate::from_pem(&pem).expect("CA should be a PEM certificate"));
+    }
+    let connector = tls.build().expect("Failed to build TLS connector");
+    for target in config.targets.iter() {
+        let addr = target.address.parse().unwrap_or_else(|_| panic!("Target {:?} should be ip:port", target.address));
+        let tls = target.tls_domain.clone().map(|domain| TargetTls { domain, connector: connector.clone() });
+        println!("Relaying {} to {:?}", config.venue, target);
+        tokio::spawn(adapter.target(addr, tls, config.queue));
+    }
+    match config.venue.as_str() {
+        "coinbase" => relay(CoinbaseFeed::new(registry), adapter).await,
+        "kraken" => relay(KrakenFeed::new(registry), adapter).await,
+        "kraken-v2" => relay(KrakenV2Feed::new(registry), adapter).await,
+        "gemini" => relay(GeminiFeed::new(registry), adapter).await,
+        "bitstamp" => relay(BitstampFeed::new(registry), adapter).await,
+        "binance" => relay(BinanceFeed::new(registry), adapter).await,
+        "okx" => relay(OkxFeed::new(registry), adapter).await,
+        "bybit" => relay(BybitFeed::new(registry), adapter).await,
+        other => panic!("Unknown venue {:?}", other),
+    }
+}
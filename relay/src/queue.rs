@@ -0,0 +1,244 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use prism_wire::Message as WireMessage;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::data_types::QueueConfig;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QueueStats {
+    pub depth: usize,
+    pub peak: usize,
+    // Tops replaced by a newer one for the same instrument before they were sent.
+    pub coalesced: u64,
+    // Tops refused by a full queue, plus those produced while the core was unreachable.
+    pub dropped: u64,
+    // Trades that never reached the core: queued when it disconnected or fell behind, or produced
+    // while it was unreachable.
+    pub trades_lost: u64,
+    // Times the core was disconnected for leaving the queue full past the trade wait.
+    pub lagged: u64,
+    pub sent: u64,
+    pub batches: u64,
+}
+
+enum Entry {
+    // Resolved from `tops` when it is taken, so the newest top goes out in the oldest's place.
+    Top(u16),
+    Message(WireMessage),
+}
+
+struct Inner {
+    entries: VecDeque<Entry>,
+    tops: HashMap<u16, WireMessage>,
+    open: bool,
+    // When the queue last filled up, while it stays full.
+    full_since: Option<Instant>,
+    lagging: bool,
+    stats: QueueStats,
+}
+
+impl Inner {
+    fn close(&mut self) {
+        self.open = false;
+        for entry in self.entries.drain(..) {
+            match entry {
+                Entry::Message(WireMessage::Trade { .. }) => self.stats.trades_lost += 1,
+                _ => self.stats.dropped += 1,
+            }
+        }
+        self.tops.clear();
+        self.full_since = None;
+        self.stats.depth = 0;
+    }
+}
+
+// Messages waiting for one core. The venue feed is the only producer and the core's link the
+// only consumer. Pushing never waits, so one slow core can't hold up the feed or the other cores.
+// Tops may be coalesced or dropped. Trades are queued past capacity instead, but a core that
+// leaves its queue full for longer than the trade wait is cut off rather than fall further behind.
+pub struct OutboundQueue {
+    config: QueueConfig,
+    inner: Mutex<Inner>,
+    ready: Notify,
+    lag: Notify,
+}
+
+impl OutboundQueue {
+    pub fn new(config: QueueConfig) -> OutboundQueue {
+        let inner = Inner {
+            entries: VecDeque::new(),
+            tops: HashMap::new(),
+            open: false,
+            full_since: None,
+            lagging: false,
+            stats: QueueStats::default(),
+        };
+        OutboundQueue { config, inner: Mutex::new(inner), ready: Notify::new(), lag: Notify::new() }
+    }
+
+    pub fn push(&self, message: WireMessage) {
+        let mut inner = self.inner.lock().unwrap();
+        let trade = matches!(message, WireMessage::Trade { .. });
+        if !inner.open {
+            if trade {
+                inner.stats.trades_lost += 1;
+            } else {
+                inner.stats.dropped += 1;
+            }
+            return;
+        }
+        let top = match &message {
+            WireMessage::TopOfBook { header, .. } if self.config.coalesce_tops => Some(header.instrument),
+            _ => None,
+        };
+        if let Some(instrument) = top {
+            if let Some(queued) = inner.tops.get_mut(&instrument) {
+                *queued = message;
+                inner.stats.coalesced += 1;
+                return;
+            }
+        }
+        if inner.entries.len() >= self.config.capacity {
+            if !trade {
+                inner.stats.dropped += 1;
+                return;
+            }
+            let full_since = *inner.full_since.get_or_insert_with(Instant::now);
+            if full_since.elapsed() >= Duration::from_millis(self.config.trade_wait_ms) {
+                inner.stats.trades_lost += 1;
+                inner.stats.lagged += 1;
+                inner.lagging = true;
+                inner.close();
+                self.lag.notify_one();
+                return;
+            }
+        }
+        match top {
+            Some(instrument) => {
+                inner.tops.insert(instrument, message);
+                inner.entries.push_back(Entry::Top(instrument));
+            },
+            None => inner.entries.push_back(Entry::Message(message)),
+        }
+        inner.stats.depth = inner.entries.len();
+        inner.stats.peak = inner.stats.peak.max(inner.stats.depth);
+        self.ready.notify_one();
+    }
+
+    // Waits for at least one message, then takes up to a batch.
+    pub async fn pop_batch(&self) -> Vec<WireMessage> {
+        loop {
+            {
+                let mut inner = self.inner.lock().unwrap();
+                if !inner.entries.is_empty() {
+                    let count = inner.entries.len().min(self.config.batch);
+                    let mut batch = Vec::with_capacity(count);
+                    for _ in 0..count {
+                        match inner.entries.pop_front() {
+                            Some(Entry::Top(instrument)) => batch.extend(inner.tops.remove(&instrument)),
+                            Some(Entry::Message(message)) => batch.push(message),
+                            None => break,
+                        }
+                    }
+                    inner.stats.depth = inner.entries.len();
+                    if inner.stats.depth < self.config.capacity {
+                        inner.full_since = None;
+                    }
+                    inner.stats.sent += batch.len() as u64;
+                    inner.stats.batches += 1;
+                    return batch;
+                }
+            }
+            self.ready.notified().await;
+        }
+    }
+
+    // Resolves once the core has fallen too far behind and should be disconnected.
+    pub async fn lagging(&self) {
+        loop {
+            let notified = self.lag.notified();
+            if self.inner.lock().unwrap().lagging {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    // A closed queue discards what it holds and everything pushed until it is opened again.
+    pub fn set_open(&self, open: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        if open {
+            inner.open = true;
+            inner.lagging = false;
+        } else {
+            inner.close();
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.inner.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+fn top(instrument: u16, price: u64) -> WireMessage {
+    let header = prism_wire::Header { instrument, ..Default::default() };
+    WireMessage::TopOfBook { header, bid: prism_wire::Level { price, size: 1 }, ask: prism_wire::Level { price: price + 1, size: 1 } }
+}
+
+#[cfg(test)]
+fn trade(instrument: u16, price: u64) -> WireMessage {
+    let header = prism_wire::Header { instrument, ..Default::default() };
+    WireMessage::Trade { header, side: prism_wire::Side::Buy, price, size: 1 }
+}
+
+#[tokio::test]
+async fn test_tops_coalesced_in_place() {
+    let queue = OutboundQueue::new(QueueConfig { capacity: 8, batch: 8, coalesce_tops: true, trade_wait_ms: 1000 });
+    queue.push(top(0, 1));
+    queue.set_open(true);
+    for message in [top(0, 10), trade(0, 10), top(1, 20), top(0, 11), top(0, 12)] {
+        queue.push(message);
+    }
+    assert_eq!(vec![top(0, 12), trade(0, 10), top(1, 20)], queue.pop_batch().await);
+    let stats = queue.stats();
+    assert_eq!((0, 3, 2, 1, 3, 1), (stats.depth, stats.peak, stats.coalesced, stats.dropped, stats.sent, stats.batches));
+}
+
+#[tokio::test]
+async fn test_full_queue_drops_tops_and_cuts_off_lagging_core() {
+    let queue = OutboundQueue::new(QueueConfig { capacity: 2, batch: 1, coalesce_tops: false, trade_wait_ms: 50 });
+    queue.set_open(true);
+    queue.push(trade(0, 1));
+    queue.push(top(0, 1));
+    queue.push(top(0, 2));
+    // A trade still goes in while the queue hasn't been full for long.
+    queue.push(trade(0, 2));
+    assert_eq!((3, 1), (queue.stats().depth, queue.stats().dropped));
+    assert_eq!(vec![trade(0, 1)], queue.pop_batch().await);
+    assert_eq!(vec![top(0, 1)], queue.pop_batch().await);
+
+    // Draining below capacity restarts the wait.
+    queue.push(trade(0, 3));
+    tokio::time::sleep(std::time::Duration::from_millis(60)).await;
+    queue.push(trade(0, 4));
+    assert!(tokio::time::timeout(std::time::Duration::from_millis(20), queue.lagging()).await.is_err());
+    tokio::time::sleep(std::time::Duration::from_millis(60)).await;
+    queue.push(trade(0, 5));
+    tokio::time::timeout(std::time::Duration::from_secs(1), queue.lagging()).await.unwrap();
+    queue.push(top(0, 3));
+    queue.push(trade(0, 6));
+    let stats = queue.stats();
+    // The three queued trades, the one that found the queue still full, and the one after it.
+    assert_eq!((0, 1, 5, 2), (stats.depth, stats.lagged, stats.trades_lost, stats.dropped));
+
+    queue.set_open(true);
+    assert!(tokio::time::timeout(std::time::Duration::from_millis(20), queue.lagging()).await.is_err());
+    queue.push(trade(0, 7));
+    queue.set_open(false);
+    assert_eq!((6, 2), (queue.stats().trades_lost, queue.stats().dropped));
+}
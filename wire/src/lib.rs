@@ -0,0 +1,385 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+// Binary messages between a relay and the core. Each WebSocket binary message carries exactly
+// one little-endian message, starting with its tag:
+//
+//   hello:       1 | version u16 | instruments digest u32 | venue length u8 | venue
+//   accept:      2 | version u16
+//   reject:      3 | version u16 | reason u8
+//   challenge:   4 | nonce [u8; 32]
+//   auth:        5 | proof [u8; 32]
+//   ping:        6 | origin time ns i64
+//   pong:        7 | origin time ns i64 | received time ns i64 | sent time ns i64
+//   top of book: 10 | header | bid price u64 | bid size u64 | ask price u64 | ask size u64
+//   trade:       11 | header | side u8 | price u64 | size u64
+//
+//   header:      instrument u16 | sequence u64 | exchange time ns i64 | relay time ns i64
+//
+// The relay opens with a hello and waits for an accept before sending data. A core holding a
+// shared token first answers with a challenge, which the relay must answer with an auth whose
+// proof is an HMAC-SHA256 of the nonce and venue under the token. Instrument ids index
+// the instruments file, so the hello carries a digest of its pairs and the core rejects relays
+// that loaded a different file. Prices and sizes are fixed-point with eight decimals; a time of
+// zero means the venue gave none. Sequences start at 1 on every connection.
+//
+// Once accepted, the core may send pings to estimate the relay's clock offset; the relay echoes
+// the origin time with its own receive and send times, as in NTP.
+// Version 2 added the token challenge, version 3 the ping.
+pub const VERSION: u16 = 3;
+// Oldest version this build can still decode.
+pub const MIN_VERSION: u16 = 1;
+pub const SCALE: u64 = 100_000_000;
+pub const HEADER_LEN: usize = 26;
+
+const HELLO: u8 = 1;
+const ACCEPT: u8 = 2;
+const REJECT: u8 = 3;
+const CHALLENGE: u8 = 4;
+const AUTH: u8 = 5;
+const PING: u8 = 6;
+const PONG: u8 = 7;
+const TOP_OF_BOOK: u8 = 10;
+const TRADE: u8 = 11;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WireError {
+    Short(usize),
+    Tag(u8),
+    Side(u8),
+    Reason(u8),
+    Venue,
+}
+
+impl std::fmt::Display for WireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireError::Short(len) => write!(f, "message truncated at {} bytes", len),
+            WireError::Tag(tag) => write!(f, "unknown message tag {}", tag),
+            WireError::Side(side) => write!(f, "unknown side {}", side),
+            WireError::Reason(reason) => write!(f, "unknown reject reason {}", reason),
+            WireError::Venue => write!(f, "venue is not UTF-8"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RejectReason {
+    Version,
+    Instruments,
+    Auth,
+    // The core already takes the venue's data directly.
+    Venue,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Header {
+    pub instrument: u16,
+    pub seq: u64,
+    pub exchange_time: i64,
+    pub relay_time: i64,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Level {
+    pub price: u64,
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Hello { version: u16, instruments: u32, venue: String },
+    Accept { version: u16 },
+    Reject { version: u16, reason: RejectReason },
+    Challenge { nonce: [u8; 32] },
+    Auth { proof: [u8; 32] },
+    Ping { origin: i64 },
+    Pong { origin: i64, received: i64, sent: i64 },
+    TopOfBook { header: Header, bid: Level, ask: Level },
+    Trade { header: Header, side: Side, price: u64, size: u64 },
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64);
+        match self {
+            Message::Hello { version, instruments, venue } => {
+                out.push(HELLO);
+                out.extend_from_slice(&version.to_le_bytes());
+                out.extend_from_slice(&instruments.to_le_bytes());
+                let venue = &venue.as_bytes()[..venue.len().min(u8::MAX as usize)];
+                out.push(venue.len() as u8);
+                out.extend_from_slice(venue);
+            },
+            Message::Accept { version } => {
+                out.push(ACCEPT);
+                out.extend_from_slice(&version.to_le_bytes());
+            },
+            Message::Reject { version, reason } => {
+                out.push(REJECT);
+                out.extend_from_slice(&version.to_le_bytes());
+                out.push(match reason {
+                    RejectReason::Version => 1,
+                    RejectReason::Instruments => 2,
+                    RejectReason::Auth => 3,
+                    RejectReason::Venue => 4,
+                });
+            },
+            Message::Challenge { nonce } => {
+                out.push(CHALLENGE);
+                out.extend_from_slice(nonce);
+            },
+            Message::Auth { proof } => {
+                out.push(AUTH);
+                out.extend_from_slice(proof);
+            },
+            Message::Ping { origin } => {
+                out.push(PING);
+                out.extend_from_slice(&origin.to_le_bytes());
+            },
+            Message::Pong { origin, received, sent } => {
+                out.push(PONG);
+                for time in [origin, received, sent] {
+                    out.extend_from_slice(&time.to_le_bytes());
+                }
+            },
+            Message::TopOfBook { header, bid, ask } => {
+                out.push(TOP_OF_BOOK);
+                encode_header(header, &mut out);
+                for level in [bid, ask] {
+                    out.extend_from_slice(&level.price.to_le_bytes());
+                    out.extend_from_slice(&level.size.to_le_bytes());
+                }
+            },
+            Message::Trade { header, side, price, size } => {
+                out.push(TRADE);
+                encode_header(header, &mut out);
+                out.push(match side {
+                    Side::Buy => 0,
+                    Side::Sell => 1,
+                });
+                out.extend_from_slice(&price.to_le_bytes());
+                out.extend_from_slice(&size.to_le_bytes());
+            },
+        }
+        out
+    }
+
+    // Trailing bytes are ignored, so later versions can extend a message without breaking older
+    // readers.
+    pub fn decode(bytes: &[u8]) -> Result<Message, WireError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        match reader.u8()? {
+            HELLO => {
+                let version = reader.u16()?;
+                let instruments = reader.u32()?;
+                let len = reader.u8()? as usize;
+                let venue = reader.slice(len)?;
+                let venue = String::from_utf8(venue.to_vec()).map_err(|_| WireError::Venue)?;
+                Ok(Message::Hello { version, instruments, venue })
+            },
+            ACCEPT => Ok(Message::Accept { version: reader.u16()? }),
+            REJECT => {
+                let version = reader.u16()?;
+                let reason = match reader.u8()? {
+                    1 => RejectReason::Version,
+                    2 => RejectReason::Instruments,
+                    3 => RejectReason::Auth,
+                    4 => RejectReason::Venue,
+                    other => return Err(WireError::Reason(other)),
+                };
+                Ok(Message::Reject { version, reason })
+            },
+            CHALLENGE => Ok(Message::Challenge { nonce: reader.take()? }),
+            AUTH => Ok(Message::Auth { proof: reader.take()? }),
+            PING => Ok(Message::Ping { origin: reader.i64()? }),
+            PONG => Ok(Message::Pong { origin: reader.i64()?, received: reader.i64()?, sent: reader.i64()? }),
+            TOP_OF_BOOK => {
+                let header = reader.header()?;
+                let bid = Level { price: reader.u64()?, size: reader.u64()? };
+                let ask = Level { price: reader.u64()?, size: reader.u64()? };
+                Ok(Message::TopOfBook { header, bid, ask })
+            },
+            TRADE => {
+                let header = reader.header()?;
+                let side = match reader.u8()? {
+                    0 => Side::Buy,
+                    1 => Side::Sell,
+                    other => return Err(WireError::Side(other)),
+                };
+                Ok(Message::Trade { header, side, price: reader.u64()?, size: reader.u64()? })
+            },
+            other => Err(WireError::Tag(other)),
+        }
+    }
+}
+
+fn encode_header(header: &Header, out: &mut Vec<u8>) {
+    out.extend_from_slice(&header.instrument.to_le_bytes());
+    out.extend_from_slice(&header.seq.to_le_bytes());
+    out.extend_from_slice(&header.exchange_time.to_le_bytes());
+    out.extend_from_slice(&header.relay_time.to_le_bytes());
+}
+
+// The version both sides speak, or None if the peer's is older than this build supports.
+pub fn negotiate(remote: u16) -> Option<u16> {
+    if remote < MIN_VERSION {
+        return None;
+    }
+    Some(remote.min(VERSION))
+}
+
+// Identifies the ordered pair list that instrument ids index.
+pub fn instruments_digest<S: AsRef<str>>(pairs: &[S]) -> u32 {
+    let joined: Vec<&str> = pairs.iter().map(|p| p.as_ref()).collect();
+    crc32fast::hash(joined.join(",").as_bytes())
+}
+
+fn auth_mac(token: &[u8], nonce: &[u8; 32], venue: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(token).expect("HMAC takes keys of any length");
+    mac.update(nonce);
+    mac.update(venue.as_bytes());
+    mac
+}
+
+pub fn auth_proof(token: &[u8], nonce: &[u8; 32], venue: &str) -> [u8; 32] {
+    auth_mac(token, nonce, venue).finalize().into_bytes().into()
+}
+
+// Compares in constant time.
+pub fn verify_proof(token: &[u8], nonce: &[u8; 32], venue: &str, proof: &[u8; 32]) -> bool {
+    auth_mac(token, nonce, venue).verify_slice(proof).is_ok()
+}
+
+pub fn to_fixed(value: f64) -> u64 {
+    (value * SCALE as f64).round() as u64
+}
+
+pub fn from_fixed(value: u64) -> f64 {
+    value as f64 / SCALE as f64
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn slice(&mut self, len: usize) -> Result<&'a [u8], WireError> {
+        let end = self.pos + len;
+        let bytes = self.bytes.get(self.pos..end).ok_or(WireError::Short(self.bytes.len()))?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], WireError> {
+        Ok(self.slice(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, WireError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, WireError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, WireError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, WireError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn i64(&mut self) -> Result<i64, WireError> {
+        Ok(i64::from_le_bytes(self.take()?))
+    }
+
+    fn header(&mut self) -> Result<Header, WireError> {
+        Ok(Header { instrument: self.u16()?, seq: self.u64()?, exchange_time: self.i64()?, relay_time: self.i64()? })
+    }
+}
+
+#[cfg(test)]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Layouts are pinned byte for byte; changing an existing one needs a new VERSION.
+#[test]
+fn test_layout() {
+    let hello = Message::Hello { version: 1, instruments: 0x01020304, venue: "okx".to_string() };
+    assert_eq!("01010004030201036f6b78", hex(&hello.encode()));
+    assert_eq!("020100", hex(&Message::Accept { version: 1 }.encode()));
+    assert_eq!("03010002", hex(&Message::Reject { version: 1, reason: RejectReason::Instruments }.encode()));
+    assert_eq!(format!("04{}", "ab".repeat(32)), hex(&Message::Challenge { nonce: [0xab; 32] }.encode()));
+    assert_eq!(format!("05{}", "cd".repeat(32)), hex(&Message::Auth { proof: [0xcd; 32] }.encode()));
+    assert_eq!("060100000000000000", hex(&Message::Ping { origin: 1 }.encode()));
+    let pong = Message::Pong { origin: 1, received: 2, sent: -1 };
+    assert_eq!(concat!("07", "0100000000000000", "0200000000000000", "ffffffffffffffff"), hex(&pong.encode()));
+    let header = Header { instrument: 1, seq: 2, exchange_time: 3, relay_time: 4 };
+    let trade = Message::Trade { header, side: Side::Sell, price: 5, size: 6 };
+    let expected = concat!("0b", "0100", "0200000000000000", "0300000000000000", "0400000000000000", "01", "0500000000000000", "0600000000000000");
+    assert_eq!(expected, hex(&trade.encode()));
+    let top = Message::TopOfBook { header, bid: Level { price: 7, size: 8 }, ask: Level { price: 9, size: 10 } };
+    assert_eq!(1 + HEADER_LEN + 32, top.encode().len());
+}
+
+#[test]
+fn test_round_trip() {
+    let header = Header { instrument: 1, seq: 42, exchange_time: 1689025543609000000, relay_time: 1689025543610000000 };
+    let messages = [
+        Message::Hello { version: VERSION, instruments: instruments_digest(&["ETH-USD", "BTC-USD"]), venue: "binance".to_string() },
+        Message::Accept { version: VERSION },
+        Message::Reject { version: VERSION, reason: RejectReason::Version },
+        Message::Reject { version: VERSION, reason: RejectReason::Auth },
+        Message::Reject { version: VERSION, reason: RejectReason::Venue },
+        Message::Challenge { nonce: [7; 32] },
+        Message::Auth { proof: auth_proof(b"token", &[7; 32], "binance") },
+        Message::Ping { origin: 1689025543609000000 },
+        Message::Pong { origin: 1689025543609000000, received: 1689025543609500000, sent: 1689025543609600000 },
+        Message::TopOfBook { header, bid: Level { price: to_fixed(30000.0), size: to_fixed(1.5) }, ask: Level { price: to_fixed(30000.01), size: to_fixed(0.25) } },
+        Message::Trade { header, side: Side::Buy, price: to_fixed(30000.0), size: to_fixed(0.00012345) },
+    ];
+    for message in messages {
+        assert_eq!(Ok(message.clone()), Message::decode(&message.encode()));
+    }
+    assert_eq!(30000.01, from_fixed(to_fixed(30000.01)));
+}
+
+#[test]
+fn test_decode_compatibility() {
+    // A later version may append fields to a message.
+    let mut bytes = Message::Accept { version: 2 }.encode();
+    bytes.extend_from_slice(&[0xff, 0xff]);
+    assert_eq!(Ok(Message::Accept { version: 2 }), Message::decode(&bytes));
+    assert_eq!(Err(WireError::Tag(99)), Message::decode(&[99]));
+    let bytes = Message::Trade { header: Header::default(), side: Side::Buy, price: 1, size: 1 }.encode();
+    assert_eq!(Err(WireError::Short(bytes.len() - 1)), Message::decode(&bytes[..bytes.len() - 1]));
+}
+
+#[test]
+fn test_negotiate() {
+    assert_eq!(Some(VERSION), negotiate(VERSION));
+    assert_eq!(Some(VERSION), negotiate(VERSION + 1));
+    assert_eq!(None, negotiate(MIN_VERSION - 1));
+    assert_ne!(instruments_digest(&["ETH-USD", "BTC-USD"]), instruments_digest(&["BTC-USD", "ETH-USD"]));
+}
+
+#[test]
+fn test_auth_proof() {
+    let nonce = [1u8; 32];
+    let proof = auth_proof(b"secret", &nonce, "binance");
+    assert!(verify_proof(b"secret", &nonce, "binance", &proof));
+    assert!(!verify_proof(b"other", &nonce, "binance", &proof));
+    assert!(!verify_proof(b"secret", &[2u8; 32], "binance", &proof));
+    // A proof is bound to the venue it was made for.
+    assert!(!verify_proof(b"secret", &nonce, "okx", &proof));
+}